@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE moderation_rules (
+    id BIGINT UNSIGNED AUTO_INCREMENT,
+    channel_id BIGINT UNSIGNED NOT NULL,
+    kind VARCHAR(63) NOT NULL,
+    pattern TEXT,
+    threshold INT UNSIGNED,
+    window_secs INT UNSIGNED,
+    action VARCHAR(63) NOT NULL,
+    timeout_duration INT UNSIGNED,
+    reason VARCHAR(255),
+    PRIMARY KEY(id),
+    FOREIGN KEY (channel_id) REFERENCES channels(id)
+);
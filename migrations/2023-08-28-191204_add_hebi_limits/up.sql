@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE hebi_limits (
+    channel_id BIGINT UNSIGNED NOT NULL,
+    fuel INT UNSIGNED,
+    memory_kb INT UNSIGNED,
+    http_requests INT UNSIGNED,
+    response_kb INT UNSIGNED,
+    output_length INT UNSIGNED,
+    timeout_secs INT UNSIGNED,
+    PRIMARY KEY(channel_id),
+    FOREIGN KEY (channel_id) REFERENCES channels(id)
+);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE egress_domains (
+    channel_id BIGINT UNSIGNED NOT NULL,
+    domain VARCHAR(255) NOT NULL,
+    PRIMARY KEY(channel_id, domain),
+    FOREIGN KEY (channel_id) REFERENCES channels(id)
+);
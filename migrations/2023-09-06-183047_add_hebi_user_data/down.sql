@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+DELETE FROM hebi_data WHERE user_id != 0;
+
+UPDATE hebi_data SET value = JSON_UNQUOTE(value) WHERE JSON_TYPE(value) = 'STRING';
+
+ALTER TABLE hebi_data
+    DROP PRIMARY KEY,
+    ADD PRIMARY KEY(channel_id, name),
+    DROP COLUMN expires_at,
+    DROP COLUMN user_id;
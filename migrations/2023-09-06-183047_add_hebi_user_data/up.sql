@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE hebi_data
+    ADD COLUMN user_id BIGINT UNSIGNED NOT NULL DEFAULT 0,
+    ADD COLUMN expires_at BIGINT UNSIGNED,
+    DROP PRIMARY KEY,
+    ADD PRIMARY KEY(channel_id, user_id, name);
+
+-- Values are now stored as JSON
+UPDATE hebi_data SET value = JSON_QUOTE(value) WHERE value IS NOT NULL;
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE hebi_modules (
+    channel_id BIGINT UNSIGNED NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    source TEXT NOT NULL,
+    PRIMARY KEY(channel_id, name),
+    FOREIGN KEY (channel_id) REFERENCES channels(id)
+);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE link_codes (
+    code VARCHAR(16) NOT NULL PRIMARY KEY,
+    user_id BIGINT UNSIGNED NOT NULL,
+    expires_at BIGINT UNSIGNED NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE channel_roles (
+    channel_id BIGINT UNSIGNED NOT NULL,
+    user_id BIGINT UNSIGNED NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    PRIMARY KEY(channel_id, user_id),
+    FOREIGN KEY (channel_id) REFERENCES channels(id),
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE global_roles (
+    user_id BIGINT UNSIGNED NOT NULL PRIMARY KEY,
+    role VARCHAR(16) NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE hebi_limits CHANGE native_output_kb memory_kb INT UNSIGNED;
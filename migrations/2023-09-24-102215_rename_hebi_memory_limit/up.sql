@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE hebi_limits CHANGE memory_kb native_output_kb INT UNSIGNED;
@@ -0,0 +1,99 @@
+use axum::extract::{Path, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+
+use super::state::AppState;
+use super::Result;
+use crate::api::error::ApiError;
+use crate::command_handler::CommandHandler;
+use crate::database::cache::CacheStats;
+use crate::database::models::{GlobalRole, User, WebSession};
+
+#[derive(Serialize)]
+pub struct GlobalRoleInfo {
+    pub user: User,
+    pub role: GlobalRole,
+}
+
+#[derive(Deserialize)]
+pub struct GlobalRolePayload {
+    /// In the format of `platform:id`
+    pub user: String,
+    pub role: GlobalRole,
+}
+
+pub async fn get_global_roles(
+    session: WebSession,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<GlobalRoleInfo>>> {
+    ensure_admin(&cmd, session.user_id).await?;
+
+    let roles = cmd
+        .db
+        .run(|db| {
+            let mut roles = Vec::new();
+            for assignment in db.get_global_roles()? {
+                if let Some(user) = db.get_user_by_id(assignment.user_id)? {
+                    roles.push(GlobalRoleInfo {
+                        user,
+                        role: assignment.role,
+                    });
+                }
+            }
+            Ok(roles)
+        })
+        .await?;
+
+    Ok(Json(roles))
+}
+
+pub async fn set_global_role(
+    session: WebSession,
+    cmd: State<CommandHandler>,
+    Json(payload): Json<GlobalRolePayload>,
+) -> Result<()> {
+    ensure_admin(&cmd, session.user_id).await?;
+
+    let user_id = cmd.services.resolve_or_create_user(&payload.user).await?;
+    cmd.services.set_global_role(user_id, payload.role).await?;
+
+    Ok(())
+}
+
+pub async fn delete_global_role(
+    session: WebSession,
+    Path(user_id): Path<u64>,
+    cmd: State<CommandHandler>,
+) -> Result<()> {
+    ensure_admin(&cmd, session.user_id).await?;
+
+    cmd.services.remove_global_role(user_id).await?;
+
+    Ok(())
+}
+
+/// Entries and hit counts of the database caches
+pub async fn get_cache_stats(
+    session: WebSession,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<CacheStats>>> {
+    ensure_admin(&cmd, session.user_id).await?;
+
+    Ok(Json(cmd.db.cache_stats()))
+}
+
+async fn ensure_admin(cmd: &CommandHandler, user_id: u64) -> Result<()> {
+    if cmd.db.run(move |db| db.is_admin(user_id)).await? {
+        Ok(())
+    } else {
+        Err(ApiError::Unauthorized("Not an admin".to_owned()))
+    }
+}
+
+pub fn create_router() -> Router<AppState> {
+    Router::new()
+        .route("/roles", get(get_global_roles).put(set_global_role))
+        .route("/roles/:user_id", delete(delete_global_role))
+        .route("/caches", get(get_cache_stats))
+}
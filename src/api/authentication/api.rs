@@ -1,11 +1,15 @@
 use super::schema::{get_user_info, UserInfo};
 use crate::{
     api::error::ApiError,
-    command_handler::CommandHandler,
-    database::models::{User, WebSession},
+    command_handler::{services::LINK_CODE_LIFETIME_MINUTES, CommandHandler},
+    database::models::{ProfileKey, ProfileValue, User, WebSession},
+};
+use axum::{
+    extract::{Path, State},
+    Json,
 };
-use axum::{extract::State, Json};
 use http::StatusCode;
+use serde::{Deserialize, Serialize};
 
 pub async fn get_session(web_session: WebSession) -> Json<WebSession> {
     Json(web_session)
@@ -16,29 +20,112 @@ pub async fn get_user(cmd: State<CommandHandler>, user: User) -> Result<Json<Use
     Ok(Json(user_info))
 }
 
-pub async fn logout(cmd: State<CommandHandler>, web_session: WebSession) {
+pub async fn logout(cmd: State<CommandHandler>, web_session: WebSession) -> Result<(), ApiError> {
     cmd.db
-        .remove_web_session(&web_session.session_id)
-        .expect("DB Error");
+        .run(move |db| db.remove_web_session(&web_session.session_id))
+        .await?;
+
+    Ok(())
 }
 
 pub async fn set_lastfm_name(
     web_session: WebSession,
     cmd: State<CommandHandler>,
     name: String,
-) -> StatusCode {
+) -> Result<StatusCode, ApiError> {
     cmd.db
-        .set_lastfm_name(web_session.user_id, &name)
-        .expect("DB Error");
+        .run(move |db| db.set_lastfm_name(web_session.user_id, &name))
+        .await?;
 
-    StatusCode::ACCEPTED
+    Ok(StatusCode::ACCEPTED)
 }
 
-pub async fn disconnect_spotify(session: WebSession, cmd: State<CommandHandler>) {
-    cmd.db
-        .remove_user_data(session.user_id, "spotify_access_token")
-        .expect("DB error");
+pub async fn disconnect_spotify(
+    session: WebSession,
+    cmd: State<CommandHandler>,
+) -> Result<(), ApiError> {
     cmd.db
-        .remove_user_data(session.user_id, "spotify_refresh_token")
-        .expect("DB error");
+        .run(move |db| {
+            db.remove_user_data(session.user_id, "spotify_access_token")?;
+            db.remove_user_data(session.user_id, "spotify_refresh_token")
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_profile(
+    web_session: WebSession,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<ProfileValue>>, ApiError> {
+    Ok(Json(
+        cmd.db
+            .run(move |db| db.get_profile(web_session.user_id))
+            .await?,
+    ))
+}
+
+#[derive(Deserialize)]
+pub struct SetProfileValue {
+    pub value: String,
+    #[serde(default = "default_public")]
+    pub public: bool,
+}
+
+fn default_public() -> bool {
+    true
+}
+
+pub async fn set_profile_value(
+    web_session: WebSession,
+    cmd: State<CommandHandler>,
+    Path(key): Path<ProfileKey>,
+    Json(SetProfileValue { value, public }): Json<SetProfileValue>,
+) -> Result<StatusCode, ApiError> {
+    cmd.services
+        .set_profile_value(web_session.user_id, key, &value, public)
+        .await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+pub async fn delete_profile_value(
+    web_session: WebSession,
+    cmd: State<CommandHandler>,
+    Path(key): Path<ProfileKey>,
+) -> Result<StatusCode, ApiError> {
+    cmd.services
+        .unset_profile_value(web_session.user_id, key)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize)]
+pub struct LinkCode {
+    pub code: String,
+    pub expires_in_minutes: i64,
+}
+
+/// Creates a code for linking the user with an account on a chat platform
+pub async fn create_link_code(
+    web_session: WebSession,
+    cmd: State<CommandHandler>,
+) -> Result<Json<LinkCode>, ApiError> {
+    let code = cmd.services.create_link_code(web_session.user_id).await?;
+
+    Ok(Json(LinkCode {
+        code,
+        expires_in_minutes: LINK_CODE_LIFETIME_MINUTES,
+    }))
+}
+
+pub async fn redeem_link_code(
+    cmd: State<CommandHandler>,
+    user: User,
+    Path(code): Path<String>,
+) -> Result<Json<User>, ApiError> {
+    let user = cmd.services.redeem_link_code(&code, user).await?;
+
+    Ok(Json(user))
 }
@@ -23,7 +23,7 @@ use crate::{
     command_handler::{discord_api::DiscordApi, spotify_api::SpotifyApi, CommandHandler},
     database::{
         models::{UserData, WebSession},
-        Database,
+        Database, DatabaseError,
     },
     platform::UserIdentifier,
 };
@@ -33,6 +33,8 @@ const TWITCH_MANAGE_SCOPES: &[&str] = &[
     "channel:read:predictions",
     "channel:read:redemptions",
     "channel:manage:redemptions",
+    "moderation:read",
+    "channel:read:vips",
 ];
 const DISCORD_SCOPES: &str = "identify";
 const SPOTIFY_SCOPES: &[&str] = &["user-read-playback-state", "user-read-recently-played"];
@@ -42,8 +44,13 @@ const TWITCH_BOT_SCOPES: &[&str] = &[
     "chat:edit",
     "whispers:read",
     "whispers:edit",
+    "user:manage:whispers",
     "channel:moderate",
     "moderator:manage:banned_users",
+    "moderator:manage:chat_messages",
+    "moderator:manage:shield_mode",
+    "moderator:manage:chat_settings",
+    "moderator:manage:announcements",
 ];
 
 type StateStorage = State<Arc<DashMap<String, String>>>;
@@ -88,32 +95,34 @@ pub async fn admin_authenticate_twitch_bot(
     state_storage: StateStorage,
     Query(Authenticateparams { redirect_to }): Query<Authenticateparams>,
 ) -> Result<Redirect, (StatusCode, &'static str)> {
-    if let Ok(Some(admin_user)) = cmd.db.get_admin_user() {
-        if admin_user.id == current_session.user_id {
-            tracing::info!("Authenticating the bot (Twitch):");
+    let user_id = current_session.user_id;
+    if cmd
+        .db
+        .run(move |db| db.is_admin(user_id))
+        .await
+        .map_err(db_error)?
+    {
+        tracing::info!("Authenticating the bot (Twitch):");
 
-            let client_id = twitch_api::get_client_id().expect("Twitch client ID not specified");
+        let client_id = twitch_api::get_client_id().expect("Twitch client ID not specified");
 
-            let token = generate_state_token();
+        let token = generate_state_token();
 
-            let uri = AuthPlatform::Twitch.construct_uri(
-                &client_id,
-                &TWITCH_BOT_SCOPES.join("%20"),
-                true,
-                Some("bot"),
-                Some(&token),
-            );
+        let uri = AuthPlatform::Twitch.construct_uri(
+            &client_id,
+            &TWITCH_BOT_SCOPES.join("%20"),
+            true,
+            Some("bot"),
+            Some(&token),
+        );
 
-            state_storage.insert(token, redirect_to.unwrap_or_else(|| "/profile".to_string()));
+        state_storage.insert(token, redirect_to.unwrap_or_else(|| "/profile".to_string()));
 
-            tracing::info!("{}", uri);
+        tracing::info!("{}", uri);
 
-            Ok(Redirect::to(&uri))
-        } else {
-            Err((StatusCode::UNAUTHORIZED, "Not admin user!"))
-        }
+        Ok(Redirect::to(&uri))
     } else {
-        Err((StatusCode::UNAUTHORIZED, "Admin user not configured!"))
+        Err((StatusCode::UNAUTHORIZED, "Not admin user!"))
     }
 }
 
@@ -197,19 +206,31 @@ pub async fn twitch_redirect(
 
     let user = cmd
         .db
-        .get_or_create_user(&UserIdentifier::TwitchID(twitch_user.id))
-        .expect("DB error");
+        .run(move |db| db.get_or_create_user(&UserIdentifier::TwitchID(twitch_user.id)))
+        .await
+        .map_err(db_error)?;
 
     if let Some(web_session) = current_session {
         let current_user = cmd
             .db
-            .get_user_by_id(web_session.user_id)
-            .expect("DB Error")
+            .run(move |db| db.get_user_by_id(web_session.user_id))
+            .await
+            .map_err(db_error)?
             .unwrap();
 
-        cmd.db.merge_users(current_user, user);
+        if current_user.id != user.id {
+            cmd.services
+                .link_users(current_user, user)
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to link users: {e}");
+                    (StatusCode::BAD_REQUEST, "Could not link the accounts")
+                })?;
+        }
     } else {
-        let cookie = create_user_session(&cmd.db, user.id, twitch_user.display_name);
+        let cookie = create_user_session(&cmd.db, user.id, twitch_user.display_name)
+            .await
+            .map_err(db_error)?;
 
         jar = jar.add(cookie);
     }
@@ -223,39 +244,36 @@ pub async fn admin_twitch_bot_redirect(
     Query(RedirectParams { code, state: _ }): Query<RedirectParams>,
     current_session: WebSession,
 ) -> Result<Redirect, (StatusCode, &'static str)> {
-    if let Ok(Some(admin_user)) = cmd.db.get_admin_user() {
-        if admin_user.id == current_session.user_id {
-            let auth_response = trade_twitch_code(&client, &code)
-                .await
-                .expect("Failed to get Twitch auth response");
-
-            let current = Utc::now();
-
-            cmd.db
-                .set_auth("twitch_access_token", &auth_response.access_token)
-                .expect("DB error");
-            cmd.db
-                .set_auth("twitch_refresh_token", &auth_response.refresh_token)
-                .expect("DB error");
+    let user_id = current_session.user_id;
+    if cmd
+        .db
+        .run(move |db| db.is_admin(user_id))
+        .await
+        .map_err(db_error)?
+    {
+        let auth_response = trade_twitch_code(&client, &code)
+            .await
+            .expect("Failed to get Twitch auth response");
 
-            cmd.db
-                .set_auth("twitch_created_at", &current.to_rfc3339())
-                .expect("DB error");
+        let current = Utc::now();
 
-            let expires_at = current + Duration::seconds(auth_response.expires_in);
+        let expires_at = current + Duration::seconds(auth_response.expires_in);
 
-            cmd.db
-                .set_auth("twitch_expires_at", &expires_at.to_rfc3339())
-                .expect("DB error");
+        cmd.db
+            .run(move |db| {
+                db.set_auth("twitch_access_token", &auth_response.access_token)?;
+                db.set_auth("twitch_refresh_token", &auth_response.refresh_token)?;
+                db.set_auth("twitch_created_at", &current.to_rfc3339())?;
+                db.set_auth("twitch_expires_at", &expires_at.to_rfc3339())
+            })
+            .await
+            .map_err(db_error)?;
 
-            tracing::info!("Successfully authenticated the bot and saved the token!");
+        tracing::info!("Successfully authenticated the bot and saved the token!");
 
-            Ok(Redirect::to("/profile"))
-        } else {
-            Err((StatusCode::UNAUTHORIZED, "Not admin user!"))
-        }
+        Ok(Redirect::to("/profile"))
     } else {
-        Err((StatusCode::UNAUTHORIZED, "Admin user not configured!"))
+        Err((StatusCode::UNAUTHORIZED, "Not admin user!"))
     }
 }
 
@@ -377,20 +395,33 @@ pub async fn discord_redirect(
         .await
         .expect("Discord API Error");
 
+    let discord_id = discord_user.id.to_string();
     let user = db
-        .get_or_create_user(&UserIdentifier::DiscordID(discord_user.id.to_string()))
-        .expect("DB Error");
+        .run(move |db| db.get_or_create_user(&UserIdentifier::DiscordID(discord_id)))
+        .await
+        .map_err(db_error)?;
 
     if let Some(web_session) = current_session {
         let current_user = cmd
             .db
-            .get_user_by_id(web_session.user_id)
-            .expect("DB Error")
+            .run(move |db| db.get_user_by_id(web_session.user_id))
+            .await
+            .map_err(db_error)?
             .unwrap();
 
-        cmd.db.merge_users(current_user, user);
+        if current_user.id != user.id {
+            cmd.services
+                .link_users(current_user, user)
+                .await
+                .map_err(|e| {
+                    tracing::warn!("Failed to link users: {e}");
+                    (StatusCode::BAD_REQUEST, "Could not link the accounts")
+                })?;
+        }
     } else {
-        let cookie = create_user_session(db, user.id, discord_user.name);
+        let cookie = create_user_session(db, user.id, discord_user.name)
+            .await
+            .map_err(db_error)?;
 
         jar = jar.add(cookie);
     }
@@ -414,7 +445,7 @@ pub async fn spotify_redirect(
     Query(RedirectParams { code, state: _ }): Query<RedirectParams>,
     cmd: State<CommandHandler>,
     session: WebSession,
-) -> Redirect {
+) -> Result<Redirect, (StatusCode, &'static str)> {
     let db = &cmd.db;
 
     let client_id = env::var("SPOTIFY_CLIENT_ID").expect("SPOTIFY_CLIENT_ID missing");
@@ -429,42 +460,53 @@ pub async fn spotify_redirect(
         .await
         .expect("Spotify API Error");
 
-    db.set_user_data(
-        &UserData {
-            name: "spotify_access_token".to_string(),
-            value: auth.access_token,
-            public: false,
-            user_id: session.user_id,
-        },
-        true,
-    )
-    .expect("DB Error");
-
-    db.set_user_data(
-        &UserData {
-            name: "spotify_refresh_token".to_string(),
-            value: auth.refresh_token,
-            public: false,
-            user_id: session.user_id,
-        },
-        true,
-    )
-    .expect("DB Error");
+    db.run(move |db| {
+        db.set_user_data(
+            &UserData {
+                name: "spotify_access_token".to_string(),
+                value: auth.access_token,
+                public: false,
+                user_id: session.user_id,
+            },
+            true,
+        )?;
+
+        db.set_user_data(
+            &UserData {
+                name: "spotify_refresh_token".to_string(),
+                value: auth.refresh_token,
+                public: false,
+                user_id: session.user_id,
+            },
+            true,
+        )
+    })
+    .await
+    .map_err(db_error)?;
 
-    Redirect::to("/profile")
+    Ok(Redirect::to("/profile"))
 }
 
-fn create_user_session(db: &Database, user_id: u64, display_name: String) -> Cookie<'static> {
+async fn create_user_session(
+    db: &Database,
+    user_id: u64,
+    display_name: String,
+) -> Result<Cookie<'static>, DatabaseError> {
     let session_id = db
-        .create_web_session(user_id, display_name)
-        .expect("DB error");
+        .run(move |db| db.create_web_session(user_id, display_name))
+        .await?;
 
-    Cookie::build("session_id", session_id)
+    Ok(Cookie::build("session_id", session_id)
         .secure(true)
         .path("/")
         .http_only(false)
         .same_site(SameSite::Lax)
-        .finish()
+        .finish())
+}
+
+fn db_error(e: DatabaseError) -> (StatusCode, &'static str) {
+    tracing::error!("Database error: {e}");
+    (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
 }
 
 fn generate_state_token() -> String {
@@ -580,15 +622,23 @@ impl FromRequestParts<AppState> for WebSession {
             .unwrap();
 
         match cookie_jar.get("session_id") {
-            Some(session_id) => match state
-                .cmd
-                .db
-                .get_web_session(session_id.value())
-                .expect("DB Error")
-            {
-                Some(web_session) => Ok(web_session),
-                None => Err(StatusCode::UNAUTHORIZED),
-            },
+            Some(session_id) => {
+                let session_id = session_id.value().to_owned();
+
+                match state
+                    .cmd
+                    .db
+                    .run(move |db| db.get_web_session(&session_id))
+                    .await
+                {
+                    Ok(Some(web_session)) => Ok(web_session),
+                    Ok(None) => Err(StatusCode::UNAUTHORIZED),
+                    Err(e) => {
+                        tracing::error!("Failed to get web session: {e}");
+                        Err(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                }
+            }
             None => Err(StatusCode::UNAUTHORIZED),
         }
     }
@@ -604,12 +654,18 @@ impl FromRequestParts<AppState> for User {
     ) -> Result<Self, Self::Rejection> {
         let session = WebSession::from_request_parts(parts, state).await?;
 
-        // TODO handle this
-        Ok(state
+        match state
             .cmd
             .db
-            .get_user_by_id(session.user_id)
-            .expect("DB error")
-            .expect("Invalid web session"))
+            .run(move |db| db.get_user_by_id(session.user_id))
+            .await
+        {
+            Ok(Some(user)) => Ok(user),
+            Ok(None) => Err(StatusCode::UNAUTHORIZED),
+            Err(e) => {
+                tracing::error!("Failed to get user: {e}");
+                Err(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
     }
 }
@@ -4,7 +4,7 @@ mod schema;
 
 use super::state::AppState;
 use axum::{
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
 
@@ -28,5 +28,12 @@ pub fn create_session_router() -> Router<AppState> {
         .route("/user", get(api::get_user))
         .route("/logout", post(api::logout))
         .route("/lastfm", post(api::set_lastfm_name))
+        .route("/link", post(api::create_link_code))
+        .route("/link/:code", post(api::redeem_link_code))
+        .route("/data", get(api::get_profile))
+        .route(
+            "/data/:key",
+            put(api::set_profile_value).delete(api::delete_profile_value),
+        )
         .route("/spotify", delete(api::disconnect_spotify))
 }
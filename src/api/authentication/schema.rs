@@ -18,11 +18,17 @@ pub struct UserInfo {
 }
 
 pub async fn get_user_info(cmd: &CommandHandler, user: User) -> Result<UserInfo, ApiError> {
-    let admin = if let Some(admin) = cmd.db.get_admin_user()? {
-        admin.id == user.id
-    } else {
-        false
-    };
+    let user_id = user.id;
+    let (admin, lastfm_name, spotify_connected) = cmd
+        .db
+        .run(move |db| {
+            Ok((
+                db.is_admin(user_id)?,
+                db.get_lastfm_name(user_id)?,
+                db.get_spotify_access_token(user_id)?.is_some(),
+            ))
+        })
+        .await?;
 
     let platform_handler = cmd.platform_handler.read().await;
 
@@ -50,10 +56,6 @@ pub async fn get_user_info(cmd: &CommandHandler, user: User) -> Result<UserInfo,
         _ => None,
     };
 
-    let lastfm_name = cmd.db.get_lastfm_name(user.id)?;
-
-    let spotify_connected = cmd.db.get_spotify_access_token(user.id)?.is_some();
-
     Ok(UserInfo {
         base_user: user,
         twitch_user,
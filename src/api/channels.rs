@@ -1,5 +1,5 @@
 use axum::extract::{Path, Query, State};
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post, put};
 use axum::{Json, Router};
 use chrono::Utc;
 use futures::future::join_all;
@@ -13,13 +13,22 @@ use tokio::sync::Mutex;
 use super::state::AppState;
 use super::Result;
 use crate::api::error::ApiError;
-use crate::command_handler::{CommandHandler, ExecutionContext};
+use crate::command_handler::eval::storage::validate_channel_module;
+use crate::command_handler::inquiry_helper::LintError;
+use crate::command_handler::platform_handler::PlatformHandler;
+use crate::command_handler::{automod, CommandHandler, ExecutionContext};
 use crate::database;
-use crate::database::models::{Command, CommandMode, Filter, User, WebSession};
+use crate::database::models::{
+    Command, CommandMode, Filter, HebiDataScope, HebiLimits, HebiModule, ModerationRule,
+    ModerationRuleAction, ModerationRuleKind, NewModerationRule, Role, User, WebSession,
+};
+use crate::database::DatabaseError;
 use crate::platform::{ChannelIdentifier, Permissions, ServerPlatformContext, UserIdentifier};
 
+const HEBI_DATA_PAGE_SIZE: i64 = 500;
+
 pub async fn get_channels(cmd: State<CommandHandler>) -> Result<Json<Vec<Channel>>> {
-    let base_channels = cmd.db.get_channels().expect("DB error");
+    let base_channels = cmd.db.run(|db| db.get_channels()).await?;
     let mut friendly_names =
         get_friendly_names(base_channels.iter().map(|ch| ch.id).collect(), &cmd).await?;
 
@@ -41,7 +50,8 @@ pub async fn get_channel_info(
 ) -> Result<Json<ChannelInfo>> {
     let channel = cmd
         .db
-        .get_channel_by_id(channel_id)?
+        .run(move |db| db.get_channel_by_id(channel_id))
+        .await?
         .ok_or(ApiError::NotFound)?;
 
     let display_name = match get_channel_display_name(&channel, &cmd).await {
@@ -87,7 +97,9 @@ pub async fn get_channel_commands(
     Path(channel_id): Path<u64>,
     cmd: State<CommandHandler>,
 ) -> Result<Json<Vec<Command>>> {
-    Ok(Json(cmd.db.get_commands(channel_id)?))
+    Ok(Json(
+        cmd.db.run(move |db| db.get_commands(channel_id)).await?,
+    ))
 }
 
 pub async fn get_channel_eventsub_triggers(
@@ -96,7 +108,8 @@ pub async fn get_channel_eventsub_triggers(
 ) -> Result<Json<Vec<Value>>> {
     let channel = cmd
         .db
-        .get_channel_by_id(channel_id)?
+        .run(move |db| db.get_channel_by_id(channel_id))
+        .await?
         .ok_or(ApiError::NotFound)?;
 
     match channel.get_identifier() {
@@ -112,7 +125,8 @@ pub async fn get_channel_eventsub_triggers(
 
             let triggers = cmd
                 .db
-                .get_eventsub_triggers_for_broadcaster(&twitch_user.id)?
+                .run(move |db| db.get_eventsub_triggers_for_broadcaster(&twitch_user.id))
+                .await?
                 .into_iter()
                 .map(|trigger| {
                     json!({
@@ -140,7 +154,455 @@ pub async fn get_filters(
         .await?
         >= Permissions::ChannelMod
     {
-        Ok(Json(cmd.db.get_filters_in_channel_id(channel_id)?))
+        Ok(Json(
+            cmd.db
+                .run(move |db| db.get_filters_in_channel_id(channel_id))
+                .await?,
+        ))
+    } else {
+        Err(ApiError::Unauthorized(
+            "Not a moderator in this channel".to_owned(),
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ModerationRulePayload {
+    pub kind: ModerationRuleKind,
+    pub pattern: Option<String>,
+    pub threshold: Option<u32>,
+    pub window_secs: Option<u32>,
+    pub action: ModerationRuleAction,
+    pub timeout_duration: Option<u32>,
+    pub reason: Option<String>,
+}
+
+impl ModerationRulePayload {
+    fn into_rule(self, channel_id: u64) -> Result<NewModerationRule> {
+        let rule = ModerationRule {
+            id: 0,
+            channel_id,
+            kind: self.kind,
+            pattern: self.pattern,
+            threshold: self.threshold,
+            window_secs: self.window_secs,
+            action: self.action,
+            timeout_duration: self.timeout_duration,
+            reason: self.reason,
+        };
+        automod::validate_rule(rule.clone()).map_err(ApiError::BadRequest)?;
+
+        Ok(NewModerationRule {
+            channel_id,
+            kind: rule.kind.to_string(),
+            pattern: rule.pattern,
+            threshold: rule.threshold,
+            window_secs: rule.window_secs,
+            action: rule.action.to_string(),
+            timeout_duration: rule.timeout_duration,
+            reason: rule.reason,
+        })
+    }
+}
+
+pub async fn get_moderation_rules(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<ModerationRule>>> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    Ok(Json(
+        cmd.db
+            .run(move |db| db.get_moderation_rules(channel_id))
+            .await?,
+    ))
+}
+
+pub async fn add_moderation_rule(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+    Json(payload): Json<ModerationRulePayload>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    let rule = payload.into_rule(channel_id)?;
+    cmd.db.run(move |db| db.add_moderation_rule(rule)).await?;
+    cmd.automod.invalidate(channel_id);
+
+    Ok(())
+}
+
+pub async fn update_moderation_rule(
+    session: WebSession,
+    Path((channel_id, rule_id)): Path<(u64, u64)>,
+    cmd: State<CommandHandler>,
+    Json(payload): Json<ModerationRulePayload>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    let rule = payload.into_rule(channel_id)?;
+    cmd.db
+        .run(move |db| db.update_moderation_rule(rule_id, rule))
+        .await
+        .map_err(|e| match e {
+            DatabaseError::InvalidValue => ApiError::NotFound,
+            e => e.into(),
+        })?;
+    cmd.automod.invalidate(channel_id);
+
+    Ok(())
+}
+
+pub async fn delete_moderation_rule(
+    session: WebSession,
+    Path((channel_id, rule_id)): Path<(u64, u64)>,
+    cmd: State<CommandHandler>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    cmd.db
+        .run(move |db| db.delete_moderation_rule(channel_id, rule_id))
+        .await
+        .map_err(|e| match e {
+            DatabaseError::InvalidValue => ApiError::NotFound,
+            e => e.into(),
+        })?;
+    cmd.automod.invalidate(channel_id);
+
+    Ok(())
+}
+
+pub async fn get_hebi_limits(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+) -> Result<Json<HebiLimits>> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    Ok(Json(
+        cmd.db
+            .run(move |db| db.get_hebi_limits(channel_id))
+            .await?
+            .unwrap_or_default(),
+    ))
+}
+
+/// Limits protect the resources of the whole bot, so only the admins can change them
+pub async fn set_hebi_limits(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+    Json(limits): Json<HebiLimits>,
+) -> Result<()> {
+    let user_id = session.user_id;
+    if !cmd.db.run(move |db| db.is_admin(user_id)).await? {
+        return Err(ApiError::Unauthorized("Not an admin".to_owned()));
+    }
+
+    cmd.db
+        .run(move |db| {
+            db.set_hebi_limits(HebiLimits {
+                channel_id,
+                ..limits
+            })
+        })
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct HebiDataQuery {
+    #[serde(default)]
+    pub prefix: String,
+    /// Only list the data of this user, or the channel-wide data when set to 0
+    pub user_id: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct HebiDataEntry {
+    pub name: String,
+    pub user_id: Option<u64>,
+    pub value: Value,
+    pub expires_at: Option<u64>,
+}
+
+pub async fn get_hebi_data(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    Query(query): Query<HebiDataQuery>,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<HebiDataEntry>>> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    let scope = query.user_id.map(|user_id| match user_id {
+        0 => HebiDataScope::Channel,
+        user_id => HebiDataScope::User(user_id),
+    });
+
+    let entries = cmd
+        .db
+        .run(move |db| db.list_hebi_data(channel_id, scope, &query.prefix, HEBI_DATA_PAGE_SIZE))
+        .await?
+        .into_iter()
+        .map(|data| HebiDataEntry {
+            value: data
+                .value
+                .map(|raw_value| serde_json::from_str(&raw_value).unwrap_or(raw_value.into()))
+                .unwrap_or_default(),
+            user_id: (data.user_id != 0).then_some(data.user_id),
+            name: data.name,
+            expires_at: data.expires_at,
+        })
+        .collect();
+
+    Ok(Json(entries))
+}
+
+pub async fn delete_hebi_data(
+    session: WebSession,
+    Path((channel_id, name)): Path<(u64, String)>,
+    Query(query): Query<HebiDataQuery>,
+    cmd: State<CommandHandler>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    let scope = match query.user_id {
+        None | Some(0) => HebiDataScope::Channel,
+        Some(user_id) => HebiDataScope::User(user_id),
+    };
+    cmd.db
+        .run(move |db| db.remove_hebi_data(channel_id, scope, &name))
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct HebiModulePayload {
+    pub source: String,
+}
+
+pub async fn get_hebi_modules(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<HebiModule>>> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    Ok(Json(
+        cmd.db
+            .run(move |db| db.get_hebi_modules(channel_id))
+            .await?,
+    ))
+}
+
+/// Creates the module or replaces its source
+pub async fn set_hebi_module(
+    session: WebSession,
+    Path((channel_id, name)): Path<(u64, String)>,
+    cmd: State<CommandHandler>,
+    Json(payload): Json<HebiModulePayload>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    validate_channel_module(&name, &payload.source).map_err(ApiError::BadRequest)?;
+
+    cmd.db
+        .run(
+            move |db| match db.update_hebi_module(channel_id, &name, &payload.source) {
+                Err(DatabaseError::InvalidValue) => db.add_hebi_module(HebiModule {
+                    channel_id,
+                    name,
+                    source: payload.source,
+                }),
+                result => result,
+            },
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_hebi_module(
+    session: WebSession,
+    Path((channel_id, name)): Path<(u64, String)>,
+    cmd: State<CommandHandler>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    cmd.db
+        .run(move |db| db.remove_hebi_module(channel_id, &name))
+        .await
+        .map_err(|e| match e {
+            DatabaseError::InvalidValue => ApiError::NotFound,
+            e => e.into(),
+        })?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct EgressDomainPayload {
+    pub domain: String,
+}
+
+pub async fn get_egress_domains(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<String>>> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    Ok(Json(
+        cmd.db
+            .run(move |db| db.get_egress_domains(channel_id))
+            .await?,
+    ))
+}
+
+pub async fn add_egress_domain(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+    Json(payload): Json<EgressDomainPayload>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    let domain = payload.domain.trim().to_lowercase();
+
+    match url::Host::parse(&domain) {
+        Ok(url::Host::Domain(_)) => (),
+        _ => return Err(ApiError::BadRequest(format!("invalid domain {domain}"))),
+    }
+
+    cmd.db
+        .run(move |db| db.add_egress_domain(channel_id, &domain))
+        .await?;
+    cmd.egress.invalidate(channel_id);
+
+    Ok(())
+}
+
+pub async fn delete_egress_domain(
+    session: WebSession,
+    Path((channel_id, domain)): Path<(u64, String)>,
+    cmd: State<CommandHandler>,
+) -> Result<()> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    cmd.db
+        .run(move |db| db.remove_egress_domain(channel_id, &domain))
+        .await
+        .map_err(|e| match e {
+            DatabaseError::InvalidValue => ApiError::NotFound,
+            e => e.into(),
+        })?;
+    cmd.egress.invalidate(channel_id);
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct ChannelRoleInfo {
+    pub user: User,
+    pub role: Role,
+}
+
+#[derive(Deserialize)]
+pub struct ChannelRolePayload {
+    /// In the format of `platform:id`
+    pub user: String,
+    pub role: Role,
+}
+
+pub async fn get_channel_roles(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+) -> Result<Json<Vec<ChannelRoleInfo>>> {
+    ensure_channel_mod(&cmd, session.user_id, channel_id).await?;
+
+    let roles = cmd
+        .db
+        .run(move |db| {
+            let mut roles = Vec::new();
+            for channel_role in db.get_channel_roles(channel_id)?.iter() {
+                if let Some(user) = db.get_user_by_id(channel_role.user_id)? {
+                    roles.push(ChannelRoleInfo {
+                        user,
+                        role: channel_role.role,
+                    });
+                }
+            }
+            Ok(roles)
+        })
+        .await?;
+
+    Ok(Json(roles))
+}
+
+pub async fn set_channel_role(
+    session: WebSession,
+    Path(channel_id): Path<u64>,
+    cmd: State<CommandHandler>,
+    Json(payload): Json<ChannelRolePayload>,
+) -> Result<()> {
+    ensure_channel_owner(&cmd, session.user_id, channel_id).await?;
+
+    let user_identifier = UserIdentifier::from_string(&payload.user)
+        .map_err(|_| ApiError::BadRequest(format!("invalid user {}", payload.user)))?;
+    cmd.db
+        .run(move |db| {
+            let user = db.get_or_create_user(&user_identifier)?;
+            db.set_channel_role(channel_id, user.id, payload.role)
+        })
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_channel_role(
+    session: WebSession,
+    Path((channel_id, user_id)): Path<(u64, u64)>,
+    cmd: State<CommandHandler>,
+) -> Result<()> {
+    ensure_channel_owner(&cmd, session.user_id, channel_id).await?;
+
+    cmd.db
+        .run(move |db| db.remove_channel_role(channel_id, user_id))
+        .await
+        .map_err(|e| match e {
+            DatabaseError::InvalidValue => ApiError::NotFound,
+            e => e.into(),
+        })?;
+
+    Ok(())
+}
+
+async fn ensure_channel_owner(cmd: &CommandHandler, user_id: u64, channel_id: u64) -> Result<()> {
+    if cmd
+        .get_permissions_in_channel_by_id(user_id, channel_id)
+        .await?
+        >= Permissions::ChannelOwner
+    {
+        Ok(())
+    } else {
+        Err(ApiError::Unauthorized(
+            "Not the owner of this channel".to_owned(),
+        ))
+    }
+}
+
+async fn ensure_channel_mod(cmd: &CommandHandler, user_id: u64, channel_id: u64) -> Result<()> {
+    if cmd
+        .get_permissions_in_channel_by_id(user_id, channel_id)
+        .await?
+        >= Permissions::ChannelMod
+    {
+        Ok(())
     } else {
         Err(ApiError::Unauthorized(
             "Not a moderator in this channel".to_owned(),
@@ -149,7 +611,7 @@ pub async fn get_filters(
 }
 
 pub async fn get_channel_count(cmd: State<CommandHandler>) -> Result<Json<i64>> {
-    Ok(Json(cmd.db.get_channels_amount()?))
+    Ok(Json(cmd.db.run(|db| db.get_channels_amount()).await?))
 }
 
 #[derive(Serialize)]
@@ -167,8 +629,18 @@ async fn get_friendly_names(
     let mut twitch_channels = HashMap::new();
     let mut discord_channels = HashMap::new();
 
-    for id in channel_ids {
-        match cmd.db.get_channel_by_id(id)? {
+    let channels = cmd
+        .db
+        .run(move |db| {
+            channel_ids
+                .into_iter()
+                .map(|id| Ok((id, db.get_channel_by_id(id)?)))
+                .collect::<Result<Vec<_>, DatabaseError>>()
+        })
+        .await?;
+
+    for (id, channel) in channels {
+        match channel {
             Some(channel) => match channel.get_identifier() {
                 ChannelIdentifier::TwitchChannel((twitch_id, _)) => {
                     twitch_channels.insert(twitch_id, id);
@@ -290,6 +762,43 @@ async fn get_channel_display_name(
     }
 }
 
+/// Context for running an action in the channel as the user, who is identified by one of their platforms.
+fn api_execution_context<'a>(
+    cmd: &'a CommandHandler,
+    platform_handler: &'a PlatformHandler,
+    channel: &database::models::Channel,
+    user: &'a User,
+) -> Result<ExecutionContext<'a, ServerPlatformContext>> {
+    let executing_user = if let Some(twitch_id) = user.twitch_id.clone() {
+        UserIdentifier::TwitchID(twitch_id)
+    } else if let Some(local_ip) = &user.local_addr {
+        let addr = local_ip
+            .parse()
+            .map_err(|_| ApiError::BadRequest(format!("Invalid local address {local_ip}")))?;
+        UserIdentifier::IpAddr(addr)
+    } else {
+        return Err(ApiError::BadRequest(
+            "The user has no platform to test with".to_owned(),
+        ));
+    };
+
+    let platform_ctx = ServerPlatformContext {
+        target_channel: channel.get_identifier(),
+        executing_user,
+        cmd: cmd.clone(),
+        display_name: "Tester via API".to_owned(),
+    };
+
+    Ok(ExecutionContext {
+        db: &cmd.db,
+        channel_id: Some(channel.id),
+        platform_handler,
+        platform_ctx,
+        user,
+        processing_timestamp: Utc::now(),
+    })
+}
+
 #[derive(Deserialize)]
 pub struct EvalParams {
     pub mode: String,
@@ -312,7 +821,8 @@ pub async fn eval(
 
     let channel = cmd
         .db
-        .get_channel_by_id(channel_id)?
+        .run(move |db| db.get_channel_by_id(channel_id))
+        .await?
         .ok_or(ApiError::NotFound)?;
 
     if cmd
@@ -323,32 +833,8 @@ pub async fn eval(
         let command_mode = CommandMode::from_str(&mode)
             .map_err(|_| ApiError::BadRequest(format!("Invalid command mode {mode}")))?;
 
-        let executing_user = if let Some(twitch_id) = user.twitch_id.clone() {
-            UserIdentifier::TwitchID(twitch_id)
-        } else if let Some(local_ip) = &user.local_addr {
-            UserIdentifier::IpAddr(local_ip.parse().unwrap())
-        } else {
-            todo!()
-        };
-
-        let platform_ctx = ServerPlatformContext {
-            target_channel: channel.get_identifier(),
-            executing_user,
-            cmd: cmd.0.clone(),
-            display_name: "Tester via API".to_owned(),
-        };
-
-        let processing_timestamp = Utc::now();
         let platform_handler = cmd.platform_handler.read().await;
-        let execution_ctx = ExecutionContext {
-            db: &cmd.db,
-            channel_id: Some(channel.id),
-            platform_handler: &platform_handler,
-            platform_ctx,
-            user: &user,
-            processing_timestamp,
-            blocked_users: &cmd.blocked_users,
-        };
+        let execution_ctx = api_execution_context(&cmd, &platform_handler, &channel, &user)?;
 
         let command = Command {
             name: "EVAL testing".to_owned(),
@@ -371,13 +857,112 @@ pub async fn eval(
     }
 }
 
+#[derive(Deserialize)]
+pub struct ValidateParams {
+    pub mode: String,
+    /// Also render the template, without the helpers that have side effects
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default)]
+    pub args: String,
+}
+
+#[derive(Serialize)]
+pub struct ValidationResult {
+    pub errors: Vec<LintError>,
+    pub output: Option<String>,
+}
+
+pub async fn validate_command(
+    Path(channel_id): Path<u64>,
+    user: User,
+    Query(ValidateParams {
+        mode,
+        dry_run,
+        args,
+    }): Query<ValidateParams>,
+    cmd: State<CommandHandler>,
+    payload: String,
+) -> Result<Json<ValidationResult>> {
+    let channel = cmd
+        .db
+        .run(move |db| db.get_channel_by_id(channel_id))
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if cmd
+        .get_permissions_in_channel(user.clone(), &channel.get_identifier())
+        .await?
+        < Permissions::ChannelMod
+    {
+        return Err(ApiError::Unauthorized(
+            "Not a moderator in this channel".to_owned(),
+        ));
+    }
+
+    let command_mode = CommandMode::from_str(&mode)
+        .map_err(|_| ApiError::BadRequest(format!("Invalid command mode {mode}")))?;
+
+    let mut errors = cmd.validate_command(&command_mode, &payload);
+    let mut output = None;
+
+    if dry_run && errors.is_empty() && command_mode == CommandMode::Template {
+        let args = args
+            .split(',')
+            .filter(|item| !item.is_empty())
+            .map(str::to_owned)
+            .collect();
+
+        let platform_handler = cmd.platform_handler.read().await;
+        let execution_ctx = api_execution_context(&cmd, &platform_handler, &channel, &user)?;
+
+        match cmd.dry_run_template(&payload, &execution_ctx, args).await {
+            Ok(rendered) => output = Some(rendered),
+            Err(err) => errors.push(LintError {
+                line: None,
+                column: None,
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    Ok(Json(ValidationResult { errors, output }))
+}
+
 pub fn create_router() -> Router<AppState> {
     Router::new()
         .route("/", get(get_channels))
         .route("/count", get(get_channel_count))
         .route("/:id/info", get(get_channel_info))
         .route("/:id/filters", get(get_filters))
+        .route(
+            "/:id/moderation/rules",
+            get(get_moderation_rules).post(add_moderation_rule),
+        )
+        .route(
+            "/:id/moderation/rules/:rule_id",
+            put(update_moderation_rule).delete(delete_moderation_rule),
+        )
+        .route(
+            "/:id/hebi/limits",
+            get(get_hebi_limits).put(set_hebi_limits),
+        )
+        .route("/:id/hebi/data", get(get_hebi_data))
+        .route("/:id/hebi/modules", get(get_hebi_modules))
+        .route(
+            "/:id/hebi/modules/:name",
+            put(set_hebi_module).delete(delete_hebi_module),
+        )
+        .route("/:id/hebi/data/:name", delete(delete_hebi_data))
+        .route(
+            "/:id/egress/domains",
+            get(get_egress_domains).post(add_egress_domain),
+        )
+        .route("/:id/egress/domains/:domain", delete(delete_egress_domain))
         .route("/:id/eventsub", get(get_channel_eventsub_triggers))
+        .route("/:id/roles", get(get_channel_roles).put(set_channel_role))
+        .route("/:id/roles/:user_id", delete(delete_channel_role))
         .route("/:id/commands", get(get_channel_commands))
+        .route("/:id/commands/validate", post(validate_command))
         .route("/:id/eval", post(eval))
 }
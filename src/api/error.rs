@@ -1,4 +1,7 @@
-use crate::{command_handler::error::CommandError, database::DatabaseError};
+use crate::{
+    command_handler::{error::CommandError, services::ServiceError},
+    database::DatabaseError,
+};
 use axum::response::IntoResponse;
 use http::StatusCode;
 
@@ -44,6 +47,16 @@ impl From<CommandError> for ApiError {
     }
 }
 
+impl From<ServiceError> for ApiError {
+    fn from(e: ServiceError) -> Self {
+        match e {
+            ServiceError::InvalidInput(msg) => Self::BadRequest(msg),
+            ServiceError::Database(e) => Self::DatabaseError(e),
+            e => Self::GenericError(e.to_string()),
+        }
+    }
+}
+
 impl IntoResponse for ApiError {
     fn into_response(self) -> axum::response::Response {
         tracing::info!("Responding with error {self:?}");
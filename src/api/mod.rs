@@ -1,3 +1,4 @@
+mod admin;
 mod authentication;
 mod channels;
 mod error;
@@ -38,6 +39,7 @@ pub async fn run(command_handler: CommandHandler) {
 
     let api_routes = Router::new()
         .nest("/session", authentication::create_session_router())
+        .nest("/admin", admin::create_router())
         .nest("/channels", channels::create_router())
         .nest("/hooks", webhooks::create_router());
 
@@ -54,11 +54,20 @@ pub async fn eventsub_callback(
                         let platform_handler = cmd.platform_handler.read().await;
                         let twitch_api = &platform_handler.twitch_api.as_ref().unwrap().helix_api;
 
-                        if let Some(redeem) = cmd
+                        let subscription_id = notification.subscription.id.clone();
+                        let redeem = match cmd
                             .db
-                            .get_eventsub_redeem(&notification.subscription.id)
-                            .expect("DB error")
+                            .run(move |db| db.get_eventsub_redeem(&subscription_id))
+                            .await
                         {
+                            Ok(redeem) => redeem,
+                            Err(err) => {
+                                error!("Could not get EventSub redeem: {err}");
+                                return;
+                            }
+                        };
+
+                        if let Some(redeem) = redeem {
                             let event = notification
                                 .get_event()
                                 .expect("Failed to get notification event");
@@ -92,10 +101,15 @@ pub async fn eventsub_callback(
                                 display_name: user.display_name,
                             };
 
-                            let channel = cmd
-                                .db
-                                .get_channel(&context.target_channel)
-                                .expect("DB error");
+                            let target_channel = context.target_channel.clone();
+                            let channel =
+                                match cmd.db.run(move |db| db.get_channel(&target_channel)).await {
+                                    Ok(channel) => channel,
+                                    Err(err) => {
+                                        error!("Could not get channel: {err}");
+                                        return;
+                                    }
+                                };
 
                             if let Err(err) = cmd
                                 .handle_server_message(
@@ -0,0 +1,347 @@
+use crate::database::{
+    models::{ModerationRule, ModerationRuleKind},
+    Database, DatabaseError,
+};
+use dashmap::DashMap;
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Messages shorter than this are not checked for caps and symbols spam
+const MIN_SPAM_CHECK_LENGTH: usize = 10;
+/// How long messages are kept for the repetition and rate checks
+const HISTORY_WINDOW: Duration = Duration::from_secs(300);
+/// Also limits the thresholds of the rules that count messages in the history
+const HISTORY_MAX_LENGTH: usize = 50;
+const DEFAULT_WINDOW_SECS: u32 = 30;
+
+/// Evaluates incoming chat messages against the per-channel moderation rules.
+#[derive(Clone)]
+pub struct AutoMod {
+    db: Database,
+    rules_cache: Arc<DashMap<u64, Arc<Vec<CompiledRule>>>>,
+    history: Arc<DashMap<(u64, String), VecDeque<(Instant, String)>>>, // Channel id and user identifier
+    link_regex: Regex,
+}
+
+struct CompiledRule {
+    rule: ModerationRule,
+    regex: Option<Regex>,
+}
+
+pub struct InboundMessage<'a> {
+    pub channel_id: u64,
+    pub user: String,
+    pub text: &'a str,
+    pub emote_count: usize,
+}
+
+impl AutoMod {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            rules_cache: Arc::new(DashMap::new()),
+            history: Arc::new(DashMap::new()),
+            link_regex: Regex::new(
+                r"(?i)\b(?:https?://)?((?:[a-z0-9-]+\.)+[a-z]{2,})(?:[/?#]\S*)?",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// Drops the cached rules of a channel, should be called after they are modified.
+    pub fn invalidate(&self, channel_id: u64) {
+        self.rules_cache.remove(&channel_id);
+    }
+
+    /// Returns the first rule that the message violates.
+    /// Messages of exempt users are neither checked nor recorded, `is_exempt` is only awaited
+    /// in channels that have rules.
+    pub async fn check(
+        &self,
+        msg: &InboundMessage<'_>,
+        is_exempt: impl Future<Output = bool>,
+    ) -> Result<Option<ModerationRule>, DatabaseError> {
+        let rules = self.get_rules(msg.channel_id).await?;
+
+        if rules.is_empty() || is_exempt.await {
+            return Ok(None);
+        }
+
+        let now = Instant::now();
+
+        let mut history = self
+            .history
+            .entry((msg.channel_id, msg.user.clone()))
+            .or_default();
+
+        history.push_back((now, msg.text.to_owned()));
+        trim_history(&mut history, now);
+
+        Ok(rules
+            .iter()
+            .find(|compiled| self.violates(compiled, msg, &history, now))
+            .map(|compiled| compiled.rule.clone()))
+    }
+
+    /// Drops the messages that are older than the history window,
+    /// and forgets the users that haven't sent anything within it.
+    pub fn sweep_history(&self) {
+        let now = Instant::now();
+
+        self.history.retain(|_, history| {
+            trim_history(history, now);
+            !history.is_empty()
+        });
+    }
+
+    pub fn start_history_sweep(&self) {
+        let automod = self.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HISTORY_WINDOW);
+
+            loop {
+                interval.tick().await;
+                automod.sweep_history();
+            }
+        });
+    }
+
+    async fn get_rules(&self, channel_id: u64) -> Result<Arc<Vec<CompiledRule>>, DatabaseError> {
+        if let Some(rules) = self.rules_cache.get(&channel_id) {
+            return Ok(rules.clone());
+        }
+
+        let rules = Arc::new(
+            self.db
+                .run(move |db| db.get_moderation_rules(channel_id))
+                .await?
+                .into_iter()
+                .filter_map(|rule| match compile_rule(rule) {
+                    Ok(compiled) => Some(compiled),
+                    Err(e) => {
+                        tracing::warn!("Skipping invalid moderation rule: {e}");
+                        None
+                    }
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        self.rules_cache.insert(channel_id, rules.clone());
+
+        Ok(rules)
+    }
+
+    fn violates(
+        &self,
+        compiled: &CompiledRule,
+        msg: &InboundMessage,
+        history: &VecDeque<(Instant, String)>,
+        now: Instant,
+    ) -> bool {
+        let rule = &compiled.rule;
+        let threshold = rule.threshold.unwrap_or_default() as usize;
+        let window = Duration::from_secs(rule.window_secs.unwrap_or(DEFAULT_WINDOW_SECS) as u64);
+
+        let in_window = move || {
+            history
+                .iter()
+                .filter(move |(sent_at, _)| now.duration_since(*sent_at) <= window)
+        };
+
+        match rule.kind {
+            ModerationRuleKind::BannedPhrase => compiled
+                .regex
+                .as_ref()
+                .map_or(false, |regex| regex.is_match(msg.text)),
+            ModerationRuleKind::Links => {
+                let allowed_domains: Vec<&str> = rule
+                    .pattern
+                    .as_deref()
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|domain| !domain.is_empty())
+                    .collect();
+
+                self.link_regex.captures_iter(msg.text).any(|captures| {
+                    let domain = captures[1].to_lowercase();
+                    !is_domain_allowed(&domain, &allowed_domains)
+                })
+            }
+            ModerationRuleKind::Caps => {
+                percentage_of(msg.text, char::is_uppercase, char::is_alphabetic)
+                    .map_or(false, |percentage| percentage >= threshold)
+            }
+            ModerationRuleKind::Symbols => {
+                percentage_of(msg.text, |c| !c.is_alphanumeric(), |c| !c.is_whitespace())
+                    .map_or(false, |percentage| percentage >= threshold)
+            }
+            ModerationRuleKind::Emotes => msg.emote_count > threshold,
+            ModerationRuleKind::Repetition => {
+                in_window().filter(|(_, text)| text == msg.text).count() >= threshold.max(2)
+            }
+            ModerationRuleKind::Rate => in_window().count() > threshold,
+        }
+    }
+}
+
+fn trim_history(history: &mut VecDeque<(Instant, String)>, now: Instant) {
+    while history.front().map_or(false, |(sent_at, _)| {
+        now.duration_since(*sent_at) > HISTORY_WINDOW
+    }) || history.len() > HISTORY_MAX_LENGTH
+    {
+        history.pop_front();
+    }
+}
+
+/// Compiles a rule, which also serves as validation for user-provided rules.
+fn compile_rule(rule: ModerationRule) -> Result<CompiledRule, String> {
+    let regex = match rule.kind {
+        ModerationRuleKind::BannedPhrase => {
+            let pattern = rule
+                .pattern
+                .as_deref()
+                .ok_or("banned phrase rules require a pattern")?;
+
+            Some(Regex::new(pattern).map_err(|e| e.to_string())?)
+        }
+        ModerationRuleKind::Links => None,
+        _ => {
+            let threshold = rule
+                .threshold
+                .ok_or_else(|| format!("{} rules require a threshold", rule.kind))?;
+
+            // Rate rules need one more message than the threshold to fire
+            let max_threshold = match rule.kind {
+                ModerationRuleKind::Repetition => Some(HISTORY_MAX_LENGTH),
+                ModerationRuleKind::Rate => Some(HISTORY_MAX_LENGTH - 1),
+                _ => None,
+            };
+
+            if let Some(max_threshold) = max_threshold {
+                if threshold as usize > max_threshold {
+                    return Err(format!(
+                        "the threshold of {} rules can be at most {max_threshold}",
+                        rule.kind
+                    ));
+                }
+            }
+            None
+        }
+    };
+
+    Ok(CompiledRule { rule, regex })
+}
+
+pub fn validate_rule(rule: ModerationRule) -> Result<(), String> {
+    compile_rule(rule).map(|_| ())
+}
+
+fn is_domain_allowed(domain: &str, allowed_domains: &[&str]) -> bool {
+    allowed_domains.iter().any(|allowed| {
+        let allowed = allowed.to_lowercase();
+        domain == allowed || domain.ends_with(&format!(".{allowed}"))
+    })
+}
+
+/// Percentage of the counted characters that match `predicate`.
+/// Returns `None` if the message is too short to be judged.
+fn percentage_of(
+    text: &str,
+    predicate: impl Fn(char) -> bool,
+    counted: impl Fn(char) -> bool,
+) -> Option<usize> {
+    let counted_chars: Vec<char> = text.chars().filter(|c| counted(*c)).collect();
+
+    if counted_chars.len() < MIN_SPAM_CHECK_LENGTH {
+        return None;
+    }
+
+    let matching = counted_chars.iter().filter(|c| predicate(**c)).count();
+
+    Some(matching * 100 / counted_chars.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_domain_allowed, percentage_of, trim_history, validate_rule, HISTORY_MAX_LENGTH,
+        HISTORY_WINDOW,
+    };
+    use crate::database::models::{ModerationRule, ModerationRuleAction, ModerationRuleKind};
+    use std::{
+        collections::VecDeque,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn caps_percentage() {
+        assert_eq!(
+            percentage_of("HELLO WORLD", char::is_uppercase, char::is_alphabetic),
+            Some(100)
+        );
+        assert_eq!(
+            percentage_of("Hello world", char::is_uppercase, char::is_alphabetic),
+            Some(10)
+        );
+        assert_eq!(
+            percentage_of("HI", char::is_uppercase, char::is_alphabetic),
+            None
+        );
+    }
+
+    #[test]
+    fn allowed_subdomains() {
+        let allowed = ["youtube.com", "twitch.tv"];
+
+        assert!(is_domain_allowed("youtube.com", &allowed));
+        assert!(is_domain_allowed("www.youtube.com", &allowed));
+        assert!(is_domain_allowed("clips.twitch.tv", &allowed));
+        assert!(!is_domain_allowed("notyoutube.com", &allowed));
+        assert!(!is_domain_allowed("bit.ly", &allowed));
+    }
+
+    #[test]
+    fn stale_history_is_trimmed() {
+        let stale = Instant::now();
+        let now = stale + HISTORY_WINDOW + Duration::from_secs(1);
+
+        let mut history = VecDeque::from([(stale, "old".to_owned()), (now, "new".to_owned())]);
+        trim_history(&mut history, now);
+        assert_eq!(history.len(), 1);
+
+        let mut history = VecDeque::from([(stale, "old".to_owned())]);
+        trim_history(&mut history, now);
+        assert!(history.is_empty());
+
+        let mut history = (0..=HISTORY_MAX_LENGTH)
+            .map(|i| (now, i.to_string()))
+            .collect();
+        trim_history(&mut history, now);
+        assert_eq!(history.len(), HISTORY_MAX_LENGTH);
+    }
+
+    #[test]
+    fn rate_threshold_fits_in_history() {
+        let rule = |threshold| ModerationRule {
+            id: 0,
+            channel_id: 0,
+            kind: ModerationRuleKind::Rate,
+            pattern: None,
+            threshold: Some(threshold),
+            window_secs: None,
+            action: ModerationRuleAction::Delete,
+            timeout_duration: None,
+            reason: None,
+        };
+
+        assert!(validate_rule(rule(HISTORY_MAX_LENGTH as u32 - 1)).is_ok());
+        assert!(validate_rule(rule(HISTORY_MAX_LENGTH as u32)).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use super::*;
+use crate::command_handler::services::Services;
+
+pub struct Block {
+    services: Services,
+}
+
+#[async_trait]
+impl ExecutableCommand for Block {
+    fn get_names(&self) -> &[&str] {
+        &["block", "unblock"]
+    }
+
+    fn get_cooldown(&self) -> u64 {
+        0
+    }
+
+    fn get_permissions(&self) -> Permissions {
+        Permissions::Admin
+    }
+
+    async fn execute<'a, P: PlatformContext + Send + Sync>(
+        &self,
+        _ctx: &ExecutionContext<'a, P>,
+        trigger_name: &str,
+        args: Vec<&str>,
+    ) -> Result<Option<String>, CommandError> {
+        let identifier = args
+            .first()
+            .ok_or_else(|| CommandError::MissingArgument("user (platform:id)".to_owned()))?;
+
+        if trigger_name == "unblock" {
+            self.services.unblock_user(identifier).await?;
+
+            Ok(Some(format!("Unblocked {identifier}")))
+        } else {
+            self.services.block_user(identifier).await?;
+
+            Ok(Some(format!("Blocked {identifier}")))
+        }
+    }
+}
+
+impl Block {
+    pub fn new(services: Services) -> Self {
+        Self { services }
+    }
+}
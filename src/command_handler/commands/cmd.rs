@@ -1,12 +1,20 @@
 use std::str::FromStr;
+use std::sync::Arc;
 
 use super::*;
 use crate::{
     api::get_base_url,
+    command_handler::{dry_run_template_command, validate_command_action},
     database::{models::CommandMode, DatabaseError},
 };
+use handlebars::Handlebars;
 
-pub struct Cmd;
+/// Given before the command name, the action is only checked and not saved
+const DRY_RUN_FLAG: &str = "--dry-run";
+
+pub struct Cmd {
+    template_registry: Arc<Handlebars<'static>>,
+}
 
 #[async_trait]
 impl ExecutableCommand for Cmd {
@@ -33,8 +41,13 @@ impl ExecutableCommand for Cmd {
         let channel_identifier = ctx.platform_ctx.get_channel();
         let channel = ctx
             .db
-            .get_or_create_channel(&channel_identifier)?
+            .run({
+                let channel_identifier = channel_identifier.clone();
+                move |db| db.get_or_create_channel(&channel_identifier)
+            })
+            .await?
             .ok_or(CommandError::NoPermissions)?; // Shouldn't happen anyway
+        let channel_id = channel.id;
 
         match trigger_name {
             "addcmd" => {
@@ -72,9 +85,7 @@ impl ExecutableCommand for Cmd {
                 CommandError::MissingArgument("must be either add or delete".to_string())
             })? {
                 "add" | "create" => {
-                    let mut command_name = arguments
-                        .next()
-                        .ok_or_else(|| CommandError::MissingArgument("command name".to_string()))?;
+                    let (dry_run, mut command_name) = next_command_name(&mut arguments)?;
 
                     for prefix in ctx.platform_ctx.get_prefixes() {
                         if let Some(stripped_name) = command_name.strip_prefix(prefix) {
@@ -88,11 +99,26 @@ impl ExecutableCommand for Cmd {
                         return Err(CommandError::MissingArgument("command action".to_string()));
                     }
 
-                    match ctx.db.add_command_to_channel(
-                        &channel_identifier,
-                        command_name,
-                        &command_action,
-                    ) {
+                    if let Some(response) = self
+                        .check_action(ctx, &CommandMode::Template, &command_action, dry_run)
+                        .await?
+                    {
+                        return Ok(Some(response));
+                    }
+
+                    let command_name = command_name.to_owned();
+                    let added = ctx
+                        .db
+                        .run(move |db| {
+                            db.add_command_to_channel(
+                                &channel_identifier,
+                                &command_name,
+                                &command_action,
+                            )
+                        })
+                        .await;
+
+                    match added {
                         Ok(()) => Ok(Some("Command successfully added".to_string())),
                         Err(DatabaseError::DieselError(diesel::result::Error::DatabaseError(
                             diesel::result::DatabaseErrorKind::UniqueViolation,
@@ -110,29 +136,64 @@ impl ExecutableCommand for Cmd {
                         command_name = stripped_name;
                     }
 
-                    match ctx
+                    let command_name = command_name.to_owned();
+                    let deleted = ctx
                         .db
-                        .delete_command_from_channel(&channel_identifier, command_name)
-                    {
+                        .run(move |db| {
+                            db.delete_command_from_channel(&channel_identifier, &command_name)
+                        })
+                        .await;
+
+                    match deleted {
                         Ok(()) => Ok(Some("Command succesfully removed".to_string())),
                         Err(e) => Err(CommandError::DatabaseError(e)),
                     }
                 }
                 "edit" | "update" => {
-                    let command_name = arguments
-                        .next()
-                        .ok_or_else(|| CommandError::MissingArgument("command name".to_string()))?;
+                    let (dry_run, command_name) = next_command_name(&mut arguments)?;
                     let command_action = arguments.collect::<Vec<&str>>().join(" ");
 
                     if command_action.is_empty() {
                         return Err(CommandError::MissingArgument("command action".to_string()));
                     }
 
-                    match ctx.db.update_command_action(
-                        &channel_identifier,
-                        command_name,
-                        &command_action,
-                    ) {
+                    let command_name = command_name.to_owned();
+                    let command = ctx
+                        .db
+                        .run({
+                            let channel_identifier = channel_identifier.clone();
+                            let command_name = command_name.clone();
+                            move |db| db.get_command(&channel_identifier, &command_name)
+                        })
+                        .await?;
+
+                    let mode = match command {
+                        Some(command) => command.mode,
+                        None => return Ok(Some(format!("command {command_name} doesn't exist"))),
+                    };
+
+                    if let Some(response) = self
+                        .check_action(ctx, &mode, &command_action, dry_run)
+                        .await?
+                    {
+                        return Ok(Some(response));
+                    }
+
+                    let updated = ctx
+                        .db
+                        .run({
+                            let command_name = command_name.clone();
+                            move |db| {
+                                db.update_command_action(
+                                    &channel_identifier,
+                                    &command_name,
+                                    &command_action,
+                                )
+                            }
+                        })
+                        .await;
+
+                    match updated {
                         Ok(()) => Ok(Some(format!("Command {command_name} updated"))),
                         Err(e) => Err(CommandError::DatabaseError(e)),
                     }
@@ -146,7 +207,13 @@ impl ExecutableCommand for Cmd {
                         command_name = stripped_name;
                     }
 
-                    match ctx.db.get_command(&channel_identifier, command_name)? {
+                    let name = command_name.to_owned();
+                    let command = ctx
+                        .db
+                        .run(move |db| db.get_command(&channel_identifier, &name))
+                        .await?;
+
+                    match command {
                         Some(command) => Ok(Some(command.action)),
                         None => Ok(Some(format!("command {} doesn't exist", command_name))),
                     }
@@ -166,8 +233,12 @@ impl ExecutableCommand for Cmd {
                         return Err(CommandError::MissingArgument("triggers".to_string()));
                     }
 
+                    let command_name = command_name.to_owned();
                     ctx.db
-                        .set_command_triggers(channel.id, command_name, &triggers)?;
+                        .run(move |db| {
+                            db.set_command_triggers(channel_id, &command_name, &triggers)
+                        })
+                        .await?;
 
                     Ok(Some(String::from("Succesfully updated command triggers")))
                 }
@@ -180,7 +251,7 @@ impl ExecutableCommand for Cmd {
                         command_name = stripped_name;
                     }
 
-                    let commands = ctx.db.get_commands(channel.id)?;
+                    let commands = ctx.db.run(move |db| db.get_commands(channel_id)).await?;
 
                     for command in commands {
                         if command.name == command_name {
@@ -203,8 +274,12 @@ impl ExecutableCommand for Cmd {
                         CommandError::InvalidArgument(format!("invalid command mode {raw_mode}"))
                     })?;
 
+                    let command_name = command_name.to_owned();
                     ctx.db
-                        .set_command_mode(&channel_identifier, command_name, mode)?;
+                        .run(move |db| {
+                            db.set_command_mode(&channel_identifier, &command_name, mode)
+                        })
+                        .await?;
 
                     Ok(Some("Updated command mode".to_owned()))
                 }
@@ -220,3 +295,60 @@ impl ExecutableCommand for Cmd {
         Ok(response)
     }
 }
+
+impl Cmd {
+    pub fn new(template_registry: Arc<Handlebars<'static>>) -> Self {
+        Self { template_registry }
+    }
+
+    /// Fails if the action is not valid. On a dry run, returns the response to show instead of saving the action.
+    async fn check_action<'a, P: PlatformContext + Send + Sync>(
+        &self,
+        ctx: &ExecutionContext<'a, P>,
+        mode: &CommandMode,
+        action: &str,
+        dry_run: bool,
+    ) -> Result<Option<String>, CommandError> {
+        let errors = validate_command_action(&self.template_registry, mode, action);
+
+        if !errors.is_empty() {
+            let errors = errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join("; ");
+            return Err(CommandError::InvalidArgument(errors));
+        }
+
+        if !dry_run {
+            return Ok(None);
+        }
+
+        match mode {
+            CommandMode::Template => {
+                let output =
+                    dry_run_template_command(&self.template_registry, action, ctx, vec![]).await?;
+                Ok(Some(format!("Action is valid, output: {output}")))
+            }
+            // Hebi scripts can't be run without their side effects
+            CommandMode::Hebi => Ok(Some("Action is valid".to_owned())),
+        }
+    }
+}
+
+fn next_command_name<'a>(
+    arguments: &mut impl Iterator<Item = &'a str>,
+) -> Result<(bool, &'a str), CommandError> {
+    let mut command_name = arguments
+        .next()
+        .ok_or_else(|| CommandError::MissingArgument("command name".to_string()))?;
+
+    let dry_run = command_name == DRY_RUN_FLAG;
+    if dry_run {
+        command_name = arguments
+            .next()
+            .ok_or_else(|| CommandError::MissingArgument("command name".to_string()))?;
+    }
+
+    Ok((dry_run, command_name))
+}
@@ -44,7 +44,10 @@ impl ExecutableCommand for GeoHub {
                 let leaderboard = self.client.get_leaderboard(limit).await?;
 
                 let scores = if channel {
-                    let names = ctx.db.get_geohub_link_names(channel_id)?;
+                    let names = ctx
+                        .db
+                        .run(move |db| db.get_geohub_link_names(channel_id))
+                        .await?;
                     leaderboard
                         .today
                         .into_iter()
@@ -73,7 +76,7 @@ impl ExecutableCommand for GeoHub {
                     channel_id,
                     geohub_name: username,
                 };
-                ctx.db.create_geohub_link(link)?;
+                ctx.db.run(move |db| db.create_geohub_link(link)).await?;
                 Ok(Some("Succesfully linked.".to_owned()))
             }
         }
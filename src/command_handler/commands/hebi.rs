@@ -1,10 +1,17 @@
 use super::*;
-use crate::command_handler::eval::{eval_hebi, storage::ModuleStorage};
+use crate::command_handler::eval::{
+    cache::PreparedScript,
+    context::HebiContext,
+    eval_hebi,
+    storage::{validate_channel_module, ModuleStorage},
+};
+use crate::database::{models::HebiModule, DatabaseError};
 use ::hebi::prelude::NativeModule;
 
 pub struct DebugHebi {
     native_modules: Arc<Vec<NativeModule>>,
     module_storage: ModuleStorage,
+    services: Services,
 }
 
 #[async_trait]
@@ -21,6 +28,10 @@ impl ExecutableCommand for DebugHebi {
         Permissions::ChannelMod
     }
 
+    fn allows_trusted(&self) -> bool {
+        true
+    }
+
     async fn execute<'a, P: PlatformContext + Send + Sync>(
         &self,
         ctx: &ExecutionContext<'a, P>,
@@ -30,25 +41,150 @@ impl ExecutableCommand for DebugHebi {
         let action = args.join(" ");
 
         let db = ctx.db.clone();
-        let hebi_ctx = ctx.try_into()?;
+        let hebi_ctx = HebiContext::new(ctx).await?;
 
         eval_hebi(
-            action,
+            PreparedScript::new(&action),
             &self.native_modules,
             self.module_storage.clone(),
             db,
             &[],
             hebi_ctx,
+            self.services.clone(),
+            // Builtin commands don't have access to the command handler
+            None,
         )
         .await
     }
 }
 
 impl DebugHebi {
-    pub fn new(native_modules: Arc<Vec<NativeModule>>, module_storage: ModuleStorage) -> Self {
+    pub fn new(
+        native_modules: Arc<Vec<NativeModule>>,
+        module_storage: ModuleStorage,
+        services: Services,
+    ) -> Self {
         Self {
             native_modules,
             module_storage,
+            services,
+        }
+    }
+}
+
+/// Management of the channel's Hebi modules, e.g. `hebi module add <name> <source>`
+pub struct HebiModules;
+
+#[async_trait]
+impl ExecutableCommand for HebiModules {
+    fn get_names(&self) -> &[&str] {
+        &["hebi"]
+    }
+
+    fn get_cooldown(&self) -> u64 {
+        0
+    }
+
+    fn get_permissions(&self) -> Permissions {
+        Permissions::ChannelMod
+    }
+
+    async fn execute<'a, P: PlatformContext + Send + Sync>(
+        &self,
+        ctx: &ExecutionContext<'a, P>,
+        _trigger_name: &str,
+        args: Vec<&str>,
+    ) -> Result<Option<String>, CommandError> {
+        let channel_id = ctx
+            .channel_id
+            .ok_or_else(|| CommandError::InvalidArgument("not in a channel".to_owned()))?;
+
+        let mut arguments = args.into_iter();
+
+        match arguments.next() {
+            Some("module" | "modules") => (),
+            _ => return Err(CommandError::MissingArgument("module".to_owned())),
+        }
+
+        let action = arguments
+            .next()
+            .ok_or_else(|| CommandError::MissingArgument("add, edit, remove or list".to_owned()))?;
+
+        if action == "list" {
+            let names: Vec<String> = ctx
+                .db
+                .run(move |db| db.get_hebi_modules(channel_id))
+                .await?
+                .into_iter()
+                .map(|module| module.name)
+                .collect();
+
+            return Ok(Some(if names.is_empty() {
+                "No modules".to_owned()
+            } else {
+                names.join(", ")
+            }));
+        }
+
+        let name = arguments
+            .next()
+            .ok_or_else(|| CommandError::MissingArgument("module name".to_owned()))?;
+
+        match action {
+            "add" | "edit" => {
+                let source = arguments.collect::<Vec<&str>>().join(" ");
+                validate_channel_module(name, &source).map_err(CommandError::InvalidArgument)?;
+
+                let module = HebiModule {
+                    channel_id,
+                    name: name.to_owned(),
+                    source,
+                };
+
+                if action == "add" {
+                    match ctx.db.run(move |db| db.add_hebi_module(module)).await {
+                        Ok(()) => Ok(Some(format!("Module {name} added"))),
+                        Err(DatabaseError::DieselError(diesel::result::Error::DatabaseError(
+                            diesel::result::DatabaseErrorKind::UniqueViolation,
+                            _,
+                        ))) => Ok(Some(format!("Module {name} already exists"))),
+                        Err(e) => Err(e.into()),
+                    }
+                } else {
+                    let updated = ctx
+                        .db
+                        .run(move |db| {
+                            db.update_hebi_module(module.channel_id, &module.name, &module.source)
+                        })
+                        .await;
+
+                    match updated {
+                        Ok(()) => Ok(Some(format!("Module {name} updated"))),
+                        Err(DatabaseError::InvalidValue) => {
+                            Ok(Some(format!("Module {name} does not exist")))
+                        }
+                        Err(e) => Err(e.into()),
+                    }
+                }
+            }
+            "remove" | "delete" => {
+                let module_name = name.to_owned();
+                let removed = ctx
+                    .db
+                    .run(move |db| db.remove_hebi_module(channel_id, &module_name))
+                    .await;
+
+                match removed {
+                    Ok(()) => Ok(Some(format!("Module {name} removed"))),
+                    Err(DatabaseError::InvalidValue) => {
+                        Ok(Some(format!("Module {name} does not exist")))
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            }
+            other => Err(CommandError::InvalidArgument(format!(
+                "unknown action {other}"
+            ))),
         }
     }
 }
@@ -0,0 +1,62 @@
+use super::*;
+use crate::api::get_base_url;
+use crate::command_handler::services::{Services, LINK_CODE_LIFETIME_MINUTES};
+use crate::platform::ChannelIdentifier;
+
+pub struct Link {
+    services: Services,
+}
+
+#[async_trait]
+impl ExecutableCommand for Link {
+    fn get_names(&self) -> &[&str] {
+        &["link"]
+    }
+
+    fn get_cooldown(&self) -> u64 {
+        5
+    }
+
+    fn get_permissions(&self) -> Permissions {
+        Permissions::Default
+    }
+
+    async fn execute<'a, P: PlatformContext + Send + Sync>(
+        &self,
+        ctx: &ExecutionContext<'a, P>,
+        _trigger_name: &str,
+        args: Vec<&str>,
+    ) -> Result<Option<String>, CommandError> {
+        match args.first() {
+            Some(code) => {
+                self.services
+                    .redeem_link_code(code, ctx.user.clone())
+                    .await?;
+
+                Ok(Some("Your accounts are now linked".to_owned()))
+            }
+            None => {
+                // Anyone who sees the code can link their account with the user's
+                if !matches!(ctx.platform_ctx.get_channel(), ChannelIdentifier::Anonymous) {
+                    return Err(CommandError::InvalidArgument(format!(
+                        "link codes can only be created in private messages or at {}/profile",
+                        get_base_url()
+                    )));
+                }
+
+                let code = self.services.create_link_code(ctx.user.id).await?;
+
+                Ok(Some(format!(
+                    "Use \"link {code}\" on another platform or at {}/profile within {LINK_CODE_LIFETIME_MINUTES} minutes to link it with this account. Don't share the code with anyone!",
+                    get_base_url()
+                )))
+            }
+        }
+    }
+}
+
+impl Link {
+    pub fn new(services: Services) -> Self {
+        Self { services }
+    }
+}
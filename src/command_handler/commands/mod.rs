@@ -1,18 +1,31 @@
+mod block;
 mod cmd;
 mod debug;
 mod geohub;
 mod hebi;
+mod link;
 mod ping;
+mod profile;
 mod reload;
 mod shell;
 mod twitch_eventsub;
 mod whoami;
 
 use self::{
-    cmd::Cmd, debug::Debug, geohub::GeoHub, hebi::DebugHebi, ping::Ping, reload::Reload,
-    shell::Shell, twitch_eventsub::TwitchEventSub, whoami::WhoAmI,
+    block::Block,
+    cmd::Cmd,
+    debug::Debug,
+    geohub::GeoHub,
+    hebi::{DebugHebi, HebiModules},
+    link::Link,
+    ping::Ping,
+    profile::Profile,
+    reload::Reload,
+    shell::Shell,
+    twitch_eventsub::TwitchEventSub,
+    whoami::WhoAmI,
 };
-use super::{eval::storage::ModuleStorage, CommandError, ExecutionContext};
+use super::{eval::storage::ModuleStorage, services::Services, CommandError, ExecutionContext};
 use crate::platform::{Permissions, PlatformContext};
 use ::hebi::prelude::NativeModule;
 use async_trait::async_trait;
@@ -31,6 +44,11 @@ pub trait ExecutableCommand {
         Permissions::Default
     }
 
+    /// Trusted users can use the command in any channel, regardless of their permissions there
+    fn allows_trusted(&self) -> bool {
+        false
+    }
+
     async fn execute<'a, P: PlatformContext + Send + Sync>(
         &self,
         ctx: &ExecutionContext<'a, P>,
@@ -49,8 +67,12 @@ pub enum BuiltinCommand {
     Shell(Shell),
     TwitchEventSub(TwitchEventSub),
     DebugHebi(DebugHebi),
+    HebiModules(HebiModules),
     Reload(Reload),
     GeoHub(GeoHub),
+    Profile(Profile),
+    Link(Link),
+    Block(Block),
 }
 
 impl std::fmt::Debug for BuiltinCommand {
@@ -63,16 +85,21 @@ pub fn create_builtin_commands(
     template_registry: Arc<Handlebars<'static>>,
     native_modules: Arc<Vec<NativeModule>>,
     module_storage: ModuleStorage,
+    services: Services,
 ) -> Vec<BuiltinCommand> {
     vec![
         Ping::default().into(),
-        Debug::new(template_registry).into(),
-        Cmd.into(),
+        Debug::new(template_registry.clone()).into(),
+        Cmd::new(template_registry).into(),
         WhoAmI.into(),
         Shell.into(),
         TwitchEventSub.into(),
-        DebugHebi::new(native_modules, module_storage.clone()).into(),
+        DebugHebi::new(native_modules, module_storage.clone(), services.clone()).into(),
+        HebiModules.into(),
         Reload { module_storage }.into(),
         GeoHub::default().into(),
+        Profile::new(services.clone()).into(),
+        Link::new(services.clone()).into(),
+        Block::new(services).into(),
     ]
 }
@@ -1,5 +1,6 @@
 use super::*;
 use crate::get_version;
+use crate::platform::ChannelIdentifier;
 use std::fmt::Write;
 use std::{sync::Arc, time::Instant};
 use tokio::fs;
@@ -81,6 +82,18 @@ impl ExecutableCommand for Ping {
             write!(output, ", chat latency: {}ms", latency.num_milliseconds()).unwrap();
         }
 
+        if let (ChannelIdentifier::TwitchChannel(_), Some(twitch_api)) = (
+            ctx.platform_ctx.get_channel(),
+            &ctx.platform_handler.twitch_api,
+        ) {
+            write!(
+                output,
+                ", outgoing messages: {}",
+                twitch_api.queue_metrics.summary()
+            )
+            .unwrap();
+        }
+
         Ok(Some(output))
     }
 }
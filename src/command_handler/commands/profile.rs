@@ -0,0 +1,77 @@
+use std::str::FromStr;
+
+use super::*;
+use crate::command_handler::services::Services;
+use crate::database::models::ProfileKey;
+
+/// Given before the key, the value is only visible to the user themselves
+const PRIVATE_FLAG: &str = "--private";
+
+pub struct Profile {
+    services: Services,
+}
+
+#[async_trait]
+impl ExecutableCommand for Profile {
+    fn get_names(&self) -> &[&str] {
+        &["set", "unset"]
+    }
+
+    fn get_cooldown(&self) -> u64 {
+        0
+    }
+
+    fn get_permissions(&self) -> Permissions {
+        Permissions::Default
+    }
+
+    async fn execute<'a, P: PlatformContext + Send + Sync>(
+        &self,
+        ctx: &ExecutionContext<'a, P>,
+        trigger_name: &str,
+        args: Vec<&str>,
+    ) -> Result<Option<String>, CommandError> {
+        let mut arguments = args.into_iter().peekable();
+
+        let public = arguments.next_if_eq(&PRIVATE_FLAG).is_none();
+
+        let raw_key = match arguments.next() {
+            Some(raw_key) => raw_key,
+            None => {
+                let keys = ProfileKey::ALL.map(|key| key.to_string()).join(", ");
+                return Ok(Some(format!(
+                    "usage: {trigger_name} <key> {}, available keys: {keys}",
+                    if trigger_name == "set" { "<value>" } else { "" }
+                )));
+            }
+        };
+        let key = ProfileKey::from_str(&raw_key.to_lowercase())
+            .map_err(|_| CommandError::InvalidArgument(format!("unknown key {raw_key}")))?;
+
+        if trigger_name == "unset" {
+            self.services.unset_profile_value(ctx.user.id, key).await?;
+
+            return Ok(Some(format!("Removed your {key}")));
+        }
+
+        let value = arguments.collect::<Vec<&str>>().join(" ");
+        if value.is_empty() {
+            return Err(CommandError::MissingArgument(key.to_string()));
+        }
+
+        self.services
+            .set_profile_value(ctx.user.id, key, &value, public)
+            .await?;
+
+        Ok(Some(format!(
+            "Updated your {key}{}",
+            if public { "" } else { " (private)" }
+        )))
+    }
+}
+
+impl Profile {
+    pub fn new(services: Services) -> Self {
+        Self { services }
+    }
+}
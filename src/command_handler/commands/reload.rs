@@ -44,9 +44,7 @@ impl ExecutableCommand for Reload {
 
         match subcommand {
             Subcommand::Hebi => match self.module_storage.update() {
-                Ok(Some(commit)) => Ok(Some(format!(
-                    "Hebi modules were updated to commit {commit}"
-                ))),
+                Ok(Some(version)) => Ok(Some(format!("Hebi modules were updated to {version}"))),
                 Ok(None) => Ok(Some("Hebi modules are already up to date".to_owned())),
                 Err(err) => Err(CommandError::GenericError(format!(
                     "Could not reload hebi modules: {err:#}"
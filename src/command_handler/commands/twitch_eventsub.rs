@@ -68,16 +68,22 @@ impl ExecutableCommand for TwitchEventSub {
                                 ))
                             })?;
 
-                        let id = &subscription_response.data.first().unwrap().id;
-
-                        ctx.db.add_eventsub_trigger(NewEventSubTrigger {
-                            broadcaster_id: &broadcaster_id,
-                            event_type: subscription.get_type(),
-                            action: &action,
-                            creation_payload: &serde_json::to_string(&subscription)
-                                .expect("failed to serialize"),
-                            id,
-                        })?;
+                        let id = subscription_response.data.first().unwrap().id.clone();
+                        let event_type = subscription.get_type().to_owned();
+                        let creation_payload =
+                            serde_json::to_string(&subscription).expect("failed to serialize");
+
+                        ctx.db
+                            .run(move |db| {
+                                db.add_eventsub_trigger(NewEventSubTrigger {
+                                    broadcaster_id: &broadcaster_id,
+                                    event_type: &event_type,
+                                    action: &action,
+                                    creation_payload: &creation_payload,
+                                    id: &id,
+                                })
+                            })
+                            .await?;
 
                         Ok(Some("Trigger successfully added".to_owned()))
                     }
@@ -97,7 +103,10 @@ impl ExecutableCommand for TwitchEventSub {
                             app_api
                                 .delete_eventsub_subscription(&subscription.id)
                                 .await?;
-                            ctx.db.delete_eventsub_trigger(&subscription.id)?;
+                            let subscription_id = subscription.id.clone();
+                            ctx.db
+                                .run(move |db| db.delete_eventsub_trigger(&subscription_id))
+                                .await?;
 
                             Ok(Some("Trigger succesfully removed".to_owned()))
                         } else {
@@ -109,7 +118,10 @@ impl ExecutableCommand for TwitchEventSub {
                     "list" => {
                         let triggers = ctx
                             .db
-                            .get_eventsub_triggers_for_broadcaster(&broadcaster_id)?;
+                            .run(move |db| {
+                                db.get_eventsub_triggers_for_broadcaster(&broadcaster_id)
+                            })
+                            .await?;
 
                         if !triggers.is_empty() {
                             let output = triggers
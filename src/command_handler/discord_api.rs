@@ -1,3 +1,4 @@
+use chrono::Utc;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
@@ -6,6 +7,7 @@ use twilight_http::Client;
 use twilight_model::guild::{Guild, Permissions};
 use twilight_model::id::Id;
 use twilight_model::user::{CurrentUser, User};
+use twilight_model::util::Timestamp;
 use twilight_util::permission_calculator::PermissionCalculator;
 
 #[derive(Clone, Debug)]
@@ -168,4 +170,31 @@ impl DiscordApi {
             }
         })
     }
+
+    pub async fn delete_message(&self, channel_id: u64, message_id: u64) -> anyhow::Result<()> {
+        self.http
+            .delete_message(Id::new(channel_id), Id::new(message_id))
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Disables communication for a guild member for the given amount of seconds.
+    pub async fn timeout_member(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        duration_secs: u64,
+    ) -> anyhow::Result<()> {
+        let until = Utc::now().timestamp() + duration_secs as i64;
+
+        self.http
+            .update_guild_member(Id::new(guild_id), Id::new(user_id))
+            .communication_disabled_until(Some(Timestamp::from_secs(until)?))?
+            .exec()
+            .await?;
+
+        Ok(())
+    }
 }
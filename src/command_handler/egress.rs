@@ -0,0 +1,234 @@
+use crate::database::{Database, DatabaseError};
+use dashmap::DashMap;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, Client, Url,
+};
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+const MAX_REDIRECTS: usize = 5;
+
+/// Decides which URLs user-defined commands (templates and Hebi) are allowed to request.
+///
+/// Private, loopback and link-local addresses are always blocked (unless `HTTP_EGRESS_ALLOW_PRIVATE` is set),
+/// and channels can additionally restrict the requests to an allowlist of domains.
+#[derive(Clone)]
+pub struct EgressPolicy {
+    db: Database,
+    client: Client,
+    /// Used for channels with an allowlist, as redirects could lead outside of it
+    no_redirect_client: Client,
+    allowlist_cache: Arc<DashMap<u64, Arc<Vec<String>>>>,
+    allow_private: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EgressError {
+    #[error("invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("only http and https URLs are allowed")]
+    UnsupportedScheme,
+    #[error("requests to private addresses are not allowed")]
+    PrivateAddress,
+    #[error("domain {0} is not in the allowlist of this channel")]
+    DomainNotAllowed(String),
+    #[error("database error")]
+    DatabaseError(#[from] DatabaseError),
+}
+
+impl EgressPolicy {
+    pub fn new(db: Database) -> Self {
+        let allow_private =
+            env::var("HTTP_EGRESS_ALLOW_PRIVATE").map_or(false, |value| value == "1");
+
+        let build_client = |redirect_policy: redirect::Policy| {
+            let mut builder = Client::builder().redirect(redirect_policy);
+
+            if !allow_private {
+                builder = builder.dns_resolver(Arc::new(PublicResolver));
+            }
+
+            builder.build().expect("Failed to build HTTP client")
+        };
+
+        let redirect_policy = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.stop()
+            } else if !allow_private && is_private_url(attempt.url()) {
+                attempt.error(EgressError::PrivateAddress)
+            } else {
+                attempt.follow()
+            }
+        });
+
+        Self {
+            db,
+            client: build_client(redirect_policy),
+            no_redirect_client: build_client(redirect::Policy::none()),
+            allowlist_cache: Arc::new(DashMap::new()),
+            allow_private,
+        }
+    }
+
+    /// Drops the cached allowlist of a channel, should be called after it is modified.
+    pub fn invalidate(&self, channel_id: u64) {
+        self.allowlist_cache.remove(&channel_id);
+    }
+
+    /// Checks the URL against the policy, and returns the client that should be used for requesting it.
+    pub async fn check(
+        &self,
+        channel_id: Option<u64>,
+        raw_url: &str,
+    ) -> Result<(Url, &Client), EgressError> {
+        let url = Url::parse(raw_url)?;
+
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(EgressError::UnsupportedScheme);
+        }
+
+        if !self.allow_private && is_private_url(&url) {
+            return Err(EgressError::PrivateAddress);
+        }
+
+        let allowlist = match channel_id {
+            Some(channel_id) => self.get_allowlist(channel_id).await?,
+            None => Arc::default(),
+        };
+
+        if allowlist.is_empty() {
+            return Ok((url, &self.client));
+        }
+
+        let host = url.host_str().unwrap_or_default().to_lowercase();
+
+        if allowlist
+            .iter()
+            .any(|domain| host == *domain || host.ends_with(&format!(".{domain}")))
+        {
+            Ok((url, &self.no_redirect_client))
+        } else {
+            Err(EgressError::DomainNotAllowed(host))
+        }
+    }
+
+    async fn get_allowlist(&self, channel_id: u64) -> Result<Arc<Vec<String>>, DatabaseError> {
+        if let Some(allowlist) = self.allowlist_cache.get(&channel_id) {
+            return Ok(allowlist.clone());
+        }
+
+        let allowlist = Arc::new(
+            self.db
+                .run(move |db| db.get_egress_domains(channel_id))
+                .await?,
+        );
+        self.allowlist_cache.insert(channel_id, allowlist.clone());
+
+        Ok(allowlist)
+    }
+}
+
+/// Filters out non-public addresses after the DNS lookup, so a public domain can't point to an internal service.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(Box::new(EgressError::PrivateAddress) as _);
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+fn is_private_url(url: &Url) -> bool {
+    match url.host() {
+        Some(url::Host::Ipv4(ip)) => !is_public_ip(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => !is_public_ip(IpAddr::V6(ip)),
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        None => true,
+    }
+}
+
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b)) // Carrier-grade NAT
+                || (a == 198 && (18..20).contains(&b))) // Benchmarking
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ipv4) => is_public_ip(IpAddr::V4(ipv4)),
+            None => {
+                let first_segment = ip.segments()[0];
+
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first_segment & 0xfe00) == 0xfc00 // Unique local
+                    || (first_segment & 0xffc0) == 0xfe80) // Link-local
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_private_url, is_public_ip};
+    use reqwest::Url;
+
+    #[test]
+    fn private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip} should be private");
+        }
+
+        for ip in ["1.1.1.1", "8.8.8.8", "2606:4700:4700::1111"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip} should be public");
+        }
+    }
+
+    #[test]
+    fn private_urls() {
+        assert!(is_private_url(
+            &Url::parse("http://localhost:8080").unwrap()
+        ));
+        assert!(is_private_url(&Url::parse("http://[::1]/").unwrap()));
+        assert!(!is_private_url(&Url::parse("https://example.com").unwrap()));
+    }
+}
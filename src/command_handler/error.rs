@@ -1,3 +1,5 @@
+use super::eval::limits::LimitError;
+use super::services::ServiceError;
 use crate::{database::DatabaseError, platform::UserIdentifierError};
 use std::{env::VarError, fmt, num::ParseIntError};
 
@@ -64,6 +66,22 @@ impl From<anyhow::Error> for CommandError {
     }
 }
 
+impl From<LimitError> for CommandError {
+    fn from(e: LimitError) -> Self {
+        Self::GenericError(e.to_string())
+    }
+}
+
+impl From<ServiceError> for CommandError {
+    fn from(e: ServiceError) -> Self {
+        match e {
+            ServiceError::InvalidInput(msg) => Self::InvalidArgument(msg),
+            ServiceError::Database(e) => Self::DatabaseError(e),
+            e => Self::GenericError(e.to_string()),
+        }
+    }
+}
+
 impl From<&'static str> for CommandError {
     fn from(msg: &'static str) -> Self {
         CommandError::GenericError(msg.to_owned())
@@ -0,0 +1,228 @@
+use super::instrument::{check_reserved_names, instrument_source, InstrumentedSource};
+use crate::database::{
+    cache::{Cache, CacheStats},
+    models::Command,
+};
+use hebi::prelude::*;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+const CACHE_TTL: Duration = Duration::from_secs(3600);
+const CACHE_CAPACITY: usize = 10_000;
+
+/// Script source after the preprocessing that has to be done before it can be evaluated,
+/// and the chunk it compiles to once it has been evaluated.
+#[derive(Debug, Clone)]
+pub struct PreparedScript {
+    /// Hash of the original source, to tell if the command was changed since it was cached
+    source_hash: u64,
+    source: Arc<InstrumentedSource>,
+    /// Why the script can't be run, checked on the original source before it is instrumented
+    invalid: Option<Arc<str>>,
+    chunk: Arc<OnceLock<Chunk>>,
+}
+
+impl PreparedScript {
+    pub fn new(source: &str) -> Self {
+        Self {
+            source_hash: hash_source(source),
+            source: Arc::new(instrument_source(source)),
+            invalid: check_reserved_names(source).err().map(Arc::from),
+            chunk: Arc::default(),
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source.source
+    }
+
+    pub fn instrumented(&self) -> &InstrumentedSource {
+        &self.source
+    }
+
+    /// Compiles the script on the first call, every later evaluation reuses the chunk.
+    pub fn compile(&self, hebi: &mut Hebi) -> hebi::Result<Chunk> {
+        if let Some(reason) = &self.invalid {
+            return Err(hebi::Error::User(reason.to_string().into()));
+        }
+
+        if let Some(chunk) = self.chunk.get() {
+            return Ok(chunk.clone());
+        }
+
+        let chunk = hebi.compile(self.source())?;
+        Ok(self.chunk.get_or_init(|| chunk).clone())
+    }
+}
+
+fn hash_source(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Prepared scripts of Hebi commands, keyed by channel id and lowercase command name.
+///
+/// Entries are invalidated by the database whenever the command is changed or removed.
+/// A script that is prepared from a command loaded before the change can still be inserted
+/// after the invalidation, so the source is compared on every hit as well.
+#[derive(Debug, Clone)]
+pub struct ScriptCache {
+    scripts: Cache<(u64, String), PreparedScript>,
+}
+
+impl ScriptCache {
+    pub fn new() -> Self {
+        Self {
+            scripts: Cache::new("hebi scripts", CACHE_TTL, CACHE_CAPACITY),
+        }
+    }
+
+    pub fn get(&self, command: &Command) -> PreparedScript {
+        let key = (command.channel_id, command.name.to_lowercase());
+
+        if let Some(script) = self.scripts.get(&key) {
+            if script.source_hash == hash_source(&command.action) {
+                return script;
+            }
+        }
+
+        let script = PreparedScript::new(&command.action);
+        self.scripts.insert(key, script.clone());
+
+        script
+    }
+
+    pub fn invalidate(&self, channel_id: u64, command_name: &str) {
+        self.scripts
+            .remove(&(channel_id, command_name.to_lowercase()));
+    }
+
+    pub fn invalidate_channel(&self, channel_id: u64) {
+        self.scripts.retain(|(id, _), _| *id != channel_id);
+    }
+
+    pub fn purge_expired(&self) {
+        self.scripts.purge_expired();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.scripts.stats()
+    }
+}
+
+impl Default for ScriptCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PreparedScript, ScriptCache};
+    use crate::{
+        command_handler::eval::create_stdlib_modules,
+        database::models::{Command, CommandMode},
+    };
+    use hebi::prelude::*;
+    use std::{sync::Arc, time::Instant};
+
+    fn command(name: &str, action: &str) -> Command {
+        Command {
+            name: name.to_owned(),
+            action: action.to_owned(),
+            permissions: None,
+            channel_id: 1,
+            cooldown: None,
+            triggers: None,
+            mode: CommandMode::Hebi,
+        }
+    }
+
+    #[test]
+    fn invalidated_on_update() {
+        let cache = ScriptCache::new();
+
+        let first = cache.get(&command("test", "1 + 1"));
+        assert!(Arc::ptr_eq(
+            &first.source,
+            &cache.get(&command("TEST", "1 + 1")).source
+        ));
+
+        cache.invalidate(1, "Test");
+        let updated = cache.get(&command("test", "2 + 2"));
+        assert!(updated.source().ends_with("2 + 2\n"));
+
+        cache.invalidate_channel(1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn stale_script_is_replaced() {
+        let cache = ScriptCache::new();
+
+        // A script prepared from the old command after the update invalidated the entry
+        cache.get(&command("test", "1 + 1"));
+
+        let updated = cache.get(&command("test", "2 + 2"));
+        assert!(updated.source().ends_with("2 + 2\n"));
+        assert_eq!(cache.stats().entries, 1);
+    }
+
+    /// Per-invocation cost of the Hebi command path, without the database and platform access.
+    /// Run with `cargo test --release bench_command_path -- --ignored --nocapture`
+    #[tokio::test]
+    #[ignore]
+    async fn bench_command_path() {
+        const ITERATIONS: u32 = 1000;
+        let source =
+            "fn fib(n):\n  if n < 2:\n    return n\n  return fib(n - 1) + fib(n - 2)\nfib(10)\n";
+
+        let modules = create_stdlib_modules();
+        let cache = ScriptCache::new();
+        let fib = command("fib", source);
+
+        let run = |script: PreparedScript| {
+            let modules = &modules;
+            async move {
+                let mut hebi = Hebi::builder().finish();
+                for module in modules {
+                    hebi.register(module);
+                }
+
+                let chunk = script.compile(&mut hebi).unwrap();
+                hebi.run_async(chunk).await.unwrap().to_string()
+            }
+        };
+
+        for cached in [false, true] {
+            let start = Instant::now();
+
+            for _ in 0..ITERATIONS {
+                let script = if cached {
+                    cache.get(&fib)
+                } else {
+                    PreparedScript::new(source)
+                };
+
+                assert_eq!(run(script).await, "55");
+            }
+
+            let label = if cached { "cached" } else { "uncached" };
+            println!("{label} invocation: {:?}", start.elapsed() / ITERATIONS);
+        }
+
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            cache.get(&fib);
+        }
+        println!("cache lookup: {:?}", start.elapsed() / ITERATIONS);
+
+        let stats = cache.stats();
+        assert_eq!((stats.entries, stats.misses), (1, 1));
+    }
+}
@@ -0,0 +1,98 @@
+use super::limits::Sandbox;
+use crate::{
+    command_handler::CommandHandler,
+    platform::{ChannelIdentifier, PlatformContext, ServerPlatformContext, UserIdentifier},
+};
+use hebi::prelude::*;
+use tracing::{instrument, warn};
+
+/// How many commands can be nested through `chat.run` (a command running a command running a command...)
+pub const MAX_RUN_DEPTH: u32 = 3;
+
+/// Where the messages of the `chat` module go, and who the nested commands are executed as.
+#[derive(Clone)]
+pub struct ChatContext {
+    cmd: CommandHandler,
+    channel: ChannelIdentifier,
+    user: UserIdentifier,
+    display_name: String,
+}
+
+impl ChatContext {
+    pub fn new<P: PlatformContext>(cmd: CommandHandler, platform_ctx: &P) -> Self {
+        Self {
+            cmd,
+            channel: platform_ctx.get_channel(),
+            user: platform_ctx.get_user_identifier(),
+            display_name: platform_ctx.get_display_name().to_owned(),
+        }
+    }
+
+    async fn send(&self, text: String) -> hebi::Result<()> {
+        // The budget is shared with the commands this one runs and the command that ran it
+        if let Some(sandbox) = Sandbox::current() {
+            sandbox.start_message().map_err(hebi::Error::user)?;
+        }
+
+        self.cmd
+            .platform_handler
+            .read()
+            .await
+            .send_to_channel(self.channel.clone(), text)
+            .await
+            .map_err(|err| {
+                warn!("Failed to send message: {err}");
+                hebi::Error::User(err.to_string().into())
+            })
+    }
+}
+
+#[instrument(name = "hebi.chat.say", skip_all)]
+pub async fn say(scope: Scope<'_>, chat: ChatContext) -> hebi::Result<()> {
+    let text = scope.param::<Value>(0)?.to_string();
+
+    chat.send(text).await
+}
+
+/// Like `say`, but mentions the user who executed the command.
+#[instrument(name = "hebi.chat.reply", skip_all)]
+pub async fn reply(scope: Scope<'_>, chat: ChatContext) -> hebi::Result<()> {
+    let text = scope.param::<Value>(0)?.to_string();
+
+    chat.send(format!("@{}, {text}", chat.display_name)).await
+}
+
+/// Runs another command as the same user, and returns its output.
+#[instrument(name = "hebi.chat.run", skip(scope, chat))]
+pub async fn run(scope: Scope<'_>, chat: ChatContext) -> hebi::Result<Value<'_>> {
+    let command = scope.param::<String>(0)?;
+    let args = match scope.param::<List>(1) {
+        Ok(list) => (0..list.len())
+            .filter_map(|i| list.get(i))
+            .map(|value| value.to_string())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let depth = Sandbox::current().map_or(0, |sandbox| sandbox.depth);
+    if depth >= MAX_RUN_DEPTH {
+        return Err(hebi::Error::User(
+            format!("commands cannot be nested more than {MAX_RUN_DEPTH} levels deep").into(),
+        ));
+    }
+
+    let platform_ctx = ServerPlatformContext {
+        target_channel: chat.channel.clone(),
+        executing_user: chat.user.clone(),
+        cmd: chat.cmd.clone(),
+        display_name: chat.display_name.clone(),
+    };
+
+    let output = chat
+        .cmd
+        .run_nested_command(command, args, platform_ctx)
+        .await
+        .map_err(|err| hebi::Error::User(err.to_string().into()))?;
+
+    output.into_value(scope.global())
+}
@@ -1,23 +1,65 @@
 use crate::{
     command_handler::{error::CommandError, ExecutionContext},
-    platform::PlatformContext,
+    platform::{Permissions, PlatformContext},
 };
 
 #[derive(Debug, Clone)]
 pub struct HebiContext {
     pub channel_id: u64,
+    pub user_id: u64,
+    pub display_name: String,
+    pub user_identifier: String,
+    pub platform: String,
+    pub channel: String,
+    pub permissions: i32,
+    pub processing_timestamp: i64, // Unix timestamp in milliseconds
+    /// Timezone of the user, as accepted by `time.format`
+    pub timezone: String,
 }
 
-impl<P: PlatformContext> TryFrom<&ExecutionContext<'_, P>> for HebiContext {
-    type Error = CommandError;
+impl HebiContext {
+    /// Lookups that fail fall back to the defaults, as most scripts don't use them.
+    pub async fn new<P: PlatformContext>(
+        ctx: &ExecutionContext<'_, P>,
+    ) -> Result<Self, CommandError> {
+        let channel_id = ctx.channel_id.ok_or_else(|| {
+            CommandError::InvalidArgument("Hebi executing outside of a channel context".to_owned())
+        })?;
+
+        let user_identifier = ctx.platform_ctx.get_user_identifier();
+        let channel = ctx.platform_ctx.get_channel();
+        let user_id = ctx.user.id;
+
+        let permissions = match ctx.get_permissions().await {
+            Ok(permissions) => permissions,
+            // Blocked users can't run anything
+            Err(CommandError::NoPermissions) => return Err(CommandError::NoPermissions),
+            Err(e) => {
+                tracing::warn!("Could not get permissions for the Hebi context: {e}");
+                Permissions::Default
+            }
+        };
+
+        let timezone = ctx
+            .db
+            .run(move |db| db.get_timezone(user_id))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!("Could not get the timezone for the Hebi context: {e}");
+                None
+            })
+            .unwrap_or_else(|| "UTC".to_owned());
 
-    fn try_from(ctx: &ExecutionContext<P>) -> Result<Self, Self::Error> {
         Ok(HebiContext {
-            channel_id: ctx.channel_id.ok_or_else(|| {
-                CommandError::InvalidArgument(
-                    "Hebi executing outside of a channel context".to_owned(),
-                )
-            })?,
+            channel_id,
+            user_id: ctx.user.id,
+            display_name: ctx.platform_ctx.get_display_name().to_owned(),
+            platform: user_identifier.get_platform_name().to_owned(),
+            user_identifier: user_identifier.to_string(),
+            channel: channel.get_channel().unwrap_or_default().to_owned(),
+            permissions: permissions as i32,
+            processing_timestamp: ctx.processing_timestamp.timestamp_millis(),
+            timezone,
         })
     }
 }
@@ -1,39 +1,168 @@
-use super::context::HebiContext;
-use crate::database::Database;
+use super::{context::HebiContext, utils::to_hebi_value};
+use crate::database::{models::HebiDataScope, Database, DatabaseError};
+use chrono::Utc;
 use hebi::prelude::*;
 use tracing::{error, instrument};
 
-#[instrument(name = "hebi.db.get", skip_all)]
-pub fn get(scope: Scope<'_>, db: Database, ctx: HebiContext) -> hebi::Result<Value<'_>> {
+/// How many keys `list` returns at most
+const LIST_LIMIT: i64 = 100;
+
+#[derive(Debug, Clone, Copy)]
+pub enum DbFunction {
+    Get,
+    Set,
+    Increment,
+    Remove,
+    List,
+}
+
+/// Function names in the `db` module for the channel and user scopes, and their implementations.
+pub const FUNCTIONS: &[(&str, &str, DbFunction)] = &[
+    ("get", "user_get", DbFunction::Get),
+    ("set", "user_set", DbFunction::Set),
+    ("increment", "user_increment", DbFunction::Increment),
+    ("remove", "user_remove", DbFunction::Remove),
+    ("list", "user_list", DbFunction::List),
+];
+
+pub async fn call(
+    scope: Scope<'_>,
+    function: DbFunction,
+    db: Database,
+    ctx: HebiContext,
+    data_scope: HebiDataScope,
+) -> hebi::Result<Value<'_>> {
+    match function {
+        DbFunction::Get => get(scope, db, ctx, data_scope).await,
+        DbFunction::Set => set(scope, db, ctx, data_scope).await,
+        DbFunction::Increment => increment(scope, db, ctx, data_scope).await,
+        DbFunction::Remove => remove(scope, db, ctx, data_scope).await,
+        DbFunction::List => list(scope, db, ctx, data_scope).await,
+    }
+}
+
+#[instrument(name = "hebi.db.get", skip(scope, db, ctx))]
+async fn get(
+    scope: Scope<'_>,
+    db: Database,
+    ctx: HebiContext,
+    data_scope: HebiDataScope,
+) -> hebi::Result<Value<'_>> {
     let key = scope.param::<String>(0)?;
-    let value = db.get_hebi_data(ctx.channel_id, &key).map_err(|err| {
-        error!("DB error: {err}");
-        hebi::Error::User("Database error".into())
-    })?;
+    let value = db
+        .run(move |db| db.get_hebi_data(ctx.channel_id, data_scope, &key))
+        .await
+        .map_err(db_error)?;
+
+    match value {
+        Some(raw_value) => {
+            let value = serde_json::from_str(&raw_value).unwrap_or(raw_value.into());
+            to_hebi_value(&scope, value)
+        }
+        None => None::<String>.into_value(scope.global()),
+    }
+}
+
+/// Sets a value of any type that can be represented as JSON, with an optional TTL in seconds.
+#[instrument(name = "hebi.db.set", skip(scope, db, ctx))]
+async fn set(
+    scope: Scope<'_>,
+    db: Database,
+    ctx: HebiContext,
+    data_scope: HebiDataScope,
+) -> hebi::Result<Value<'_>> {
+    let key = scope.param::<String>(0)?;
+    let value = scope.param::<Value>(1)?;
+    let expires_at = get_expiry(&scope, 2)?;
+
+    let value = serde_json::to_string(&value).map_err(hebi::Error::user)?;
 
-    value.into_value(scope.global())
+    db.run(move |db| db.set_hebi_data(ctx.channel_id, data_scope, &key, &value, expires_at))
+        .await
+        .map_err(db_error)?;
+
+    None::<String>.into_value(scope.global())
 }
 
-#[instrument(name = "hebi.db.get", skip_all)]
-pub fn set(scope: Scope<'_>, db: Database, ctx: HebiContext) -> hebi::Result<()> {
+#[instrument(name = "hebi.db.increment", skip(scope, db, ctx))]
+async fn increment(
+    scope: Scope<'_>,
+    db: Database,
+    ctx: HebiContext,
+    data_scope: HebiDataScope,
+) -> hebi::Result<Value<'_>> {
     let key = scope.param::<String>(0)?;
-    let value = scope.param::<String>(1)?;
-    db.set_hebi_data(ctx.channel_id, &key, &value)
-        .map_err(|err| {
-            error!("DB error: {err}");
-            hebi::Error::User("Database error".into())
+    let amount = scope.param::<i32>(1).unwrap_or(1);
+
+    let value = db
+        .run({
+            let key = key.clone();
+            // Hebi integers are 32-bit, so the value has to stay in their range
+            let bounds = i32::MIN.into()..=i32::MAX.into();
+            move |db| {
+                db.increment_hebi_data(ctx.channel_id, data_scope, &key, amount.into(), bounds)
+            }
+        })
+        .await
+        .map_err(|err| match err {
+            DatabaseError::InvalidValue => {
+                hebi::Error::User(format!("value of `{key}` is not an integer").into())
+            }
+            DatabaseError::OutOfRange => {
+                hebi::Error::User(format!("value of `{key}` would not fit into an integer").into())
+            }
+            err => db_error(err),
         })?;
 
-    Ok(())
+    (value as i32).into_value(scope.global())
 }
 
-#[instrument(name = "hebi.db.remove", skip_all)]
-pub fn remove(scope: Scope<'_>, db: Database, ctx: HebiContext) -> hebi::Result<()> {
+#[instrument(name = "hebi.db.remove", skip(scope, db, ctx))]
+async fn remove(
+    scope: Scope<'_>,
+    db: Database,
+    ctx: HebiContext,
+    data_scope: HebiDataScope,
+) -> hebi::Result<Value<'_>> {
     let key = scope.param::<String>(0)?;
-    db.remove_hebi_data(ctx.channel_id, &key).map_err(|err| {
-        error!("DB error: {err}");
-        hebi::Error::User("Database error".into())
-    })?;
 
-    Ok(())
+    db.run(move |db| db.remove_hebi_data(ctx.channel_id, data_scope, &key))
+        .await
+        .map_err(db_error)?;
+
+    None::<String>.into_value(scope.global())
+}
+
+/// Returns the keys starting with the given prefix.
+#[instrument(name = "hebi.db.list", skip(scope, db, ctx))]
+async fn list(
+    scope: Scope<'_>,
+    db: Database,
+    ctx: HebiContext,
+    data_scope: HebiDataScope,
+) -> hebi::Result<Value<'_>> {
+    let prefix = scope.param::<String>(0).unwrap_or_default();
+
+    let keys = db
+        .run(move |db| db.list_hebi_data(ctx.channel_id, Some(data_scope), &prefix, LIST_LIMIT))
+        .await
+        .map_err(db_error)?
+        .into_iter()
+        .map(|data| data.name)
+        .collect::<Vec<_>>();
+
+    to_hebi_value(&scope, keys.into())
+}
+
+fn get_expiry(scope: &Scope<'_>, param: usize) -> hebi::Result<Option<u64>> {
+    match scope.param::<i32>(param) {
+        Ok(ttl) if ttl > 0 => Ok(Some(Utc::now().timestamp() as u64 + ttl as u64)),
+        Ok(_) => Err(hebi::Error::User("TTL must be positive".into())),
+        Err(_) => Ok(None),
+    }
+}
+
+fn db_error(err: DatabaseError) -> hebi::Error {
+    error!("DB error: {err}");
+    hebi::Error::User("Database error".into())
 }
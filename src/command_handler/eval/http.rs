@@ -1,12 +1,44 @@
-use ::serde::de::DeserializeSeed;
+use super::{
+    limits::{LimitError, Sandbox},
+    utils::to_hebi_value,
+};
+use crate::command_handler::egress::EgressPolicy;
 use hebi::prelude::*;
 use http::Method;
-use reqwest::Client;
+use serde_json::{json, Map};
 use std::str::FromStr;
 use tracing::{debug, instrument, Span};
 
+struct HttpResponse {
+    status: u16,
+    headers: Map<String, serde_json::Value>,
+    body: serde_json::Value,
+}
+
+/// Returns only the response body, formatted according to the `format` option.
 #[instrument(name = "hebi.http.fetch", skip_all)]
-pub async fn request(scope: Scope<'_>, client: Client) -> hebi::Result<Value<'_>> {
+pub async fn fetch(scope: Scope<'_>, egress: EgressPolicy) -> hebi::Result<Value<'_>> {
+    let response = send(&scope, &egress).await?;
+
+    to_hebi_value(&scope, response.body)
+}
+
+/// Returns a table with the `status`, `headers` and `body` of the response.
+#[instrument(name = "hebi.http.request", skip_all)]
+pub async fn request(scope: Scope<'_>, egress: EgressPolicy) -> hebi::Result<Value<'_>> {
+    let response = send(&scope, &egress).await?;
+
+    to_hebi_value(
+        &scope,
+        json!({
+            "status": response.status,
+            "headers": response.headers,
+            "body": response.body,
+        }),
+    )
+}
+
+async fn send(scope: &Scope<'_>, egress: &EgressPolicy) -> hebi::Result<HttpResponse> {
     let span = Span::current();
 
     let url = scope.param::<Str>(0)?;
@@ -14,41 +46,98 @@ pub async fn request(scope: Scope<'_>, client: Client) -> hebi::Result<Value<'_>
         .param::<Table>(1)
         .unwrap_or_else(|_| scope.new_table(0));
 
-    let raw_method = get_str_param(&request_params, &scope, "method", "GET");
-    let format = get_str_param(&request_params, &scope, "format", "plain");
+    let raw_method = get_str_param(&request_params, scope, "method", "GET");
+    let format = get_str_param(&request_params, scope, "format", "plain");
 
     let method =
         Method::from_str(raw_method.as_str()).map_err(|err| hebi::Error::User(Box::new(err)))?;
 
+    let sandbox = Sandbox::current();
+
+    if let Some(sandbox) = &sandbox {
+        sandbox.start_http_request().map_err(hebi::Error::user)?;
+    }
+
+    let (url, client) = egress
+        .check(
+            sandbox.as_ref().map(|sandbox| sandbox.channel_id),
+            url.as_str(),
+        )
+        .await
+        .map_err(hebi::Error::user)?;
+
     span.record("url", url.as_str());
     span.record("method", method.as_str());
     debug!("Sending {method} request to {url}");
 
-    let response = client
-        .request(method, url.as_str())
+    let mut request = client.request(method, url);
+
+    for (name, value) in get_string_pairs(&request_params, "headers")? {
+        request = request.header(name, value);
+    }
+
+    let query = get_string_pairs(&request_params, "query")?;
+    if !query.is_empty() {
+        request = request.query(&query);
+    }
+
+    if let Some(json) = get_json_param(&request_params, "json")? {
+        request = request.json(&json);
+    } else if let Some(body) = request_params
+        .get("body")
+        .and_then(|obj| obj.as_object::<Str>(scope.global()))
+    {
+        request = request.body(body.as_str().to_owned());
+    }
+
+    let mut response = request
         .send()
         .await
         .map_err(|err| hebi::Error::User(Box::new(err)))?;
 
-    let text = response
-        .text()
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            let value = value.to_str().ok()?;
+            Some((name.to_string(), json!(value)))
+        })
+        .collect();
+
+    let max_size = sandbox.map(|sandbox| sandbox.limits.response_bytes);
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response
+        .chunk()
         .await
-        .map_err(|err| hebi::Error::User(Box::new(err)))?;
+        .map_err(|err| hebi::Error::User(Box::new(err)))?
+    {
+        body.extend_from_slice(&chunk);
 
-    match format.as_str() {
-        "plain" | "text" => scope.new_string(text).into_value(scope.global()),
-        "json" => {
-            let mut json_deserializer = serde_json::Deserializer::from_str(&text);
-            let hebi_deserializer = ValueDeserializer::new(scope.global());
-            let value = hebi_deserializer
-                .deserialize(&mut json_deserializer)
-                .map_err(|err| hebi::Error::User(format!("Deserialization error: {err}").into()))?;
-            Ok(value)
+        if let Some(max_size) = max_size {
+            if body.len() > max_size {
+                return Err(hebi::Error::user(LimitError::ResponseSize(max_size)));
+            }
         }
-        other => Err(hebi::Error::User(
-            format!("Unsupported format `{other}`").into(),
-        )),
     }
+
+    let body = match format.as_str() {
+        "plain" | "text" => json!(String::from_utf8_lossy(&body)),
+        "json" => serde_json::from_slice(&body)
+            .map_err(|err| hebi::Error::User(format!("Deserialization error: {err}").into()))?,
+        other => {
+            return Err(hebi::Error::User(
+                format!("Unsupported format `{other}`").into(),
+            ))
+        }
+    };
+
+    Ok(HttpResponse {
+        status,
+        headers,
+        body,
+    })
 }
 
 fn get_str_param<'a>(table: &Table<'a>, scope: &Scope<'a>, key: &str, default: &str) -> Str<'a> {
@@ -57,3 +146,25 @@ fn get_str_param<'a>(table: &Table<'a>, scope: &Scope<'a>, key: &str, default: &
         .and_then(|obj| obj.as_object::<Str>(scope.global()))
         .unwrap_or_else(|| scope.new_string(default))
 }
+
+fn get_json_param(table: &Table<'_>, key: &str) -> hebi::Result<Option<serde_json::Value>> {
+    table
+        .get(key)
+        .map(|value| serde_json::to_value(&value).map_err(hebi::Error::user))
+        .transpose()
+}
+
+/// Reads a table of strings (e.g. headers), non-string values are converted to strings.
+fn get_string_pairs(table: &Table<'_>, key: &str) -> hebi::Result<Vec<(String, String)>> {
+    match get_json_param(table, key)? {
+        None => Ok(Vec::new()),
+        Some(serde_json::Value::Object(map)) => Ok(map
+            .into_iter()
+            .map(|(name, value)| match value {
+                serde_json::Value::String(value) => (name, value),
+                other => (name, other.to_string()),
+            })
+            .collect()),
+        Some(_) => Err(hebi::Error::User(format!("`{key}` must be a table").into())),
+    }
+}
@@ -0,0 +1,429 @@
+use super::limits::SANDBOX_MODULE;
+
+/// Statements that start a block which can be executed repeatedly
+const METERED_BLOCKS: &[&str] = &["fn", "loop", "while", "for"];
+/// Indentation of single-line bodies once they are moved to their own lines
+const BODY_INDENT: &str = "    ";
+
+/// Script source with fuel checks inserted, and the line of the original source that every line comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrumentedSource {
+    pub source: String,
+    line_map: Vec<usize>,
+}
+
+impl InstrumentedSource {
+    /// Maps a 1-based line of the instrumented source to the line of the original source.
+    pub fn original_line(&self, line: usize) -> Option<usize> {
+        line.checked_sub(1)
+            .and_then(|index| self.line_map.get(index))
+            .copied()
+    }
+
+    /// Maps a byte offset in the instrumented source to the original line.
+    pub fn original_line_at(&self, offset: usize) -> Option<usize> {
+        let line = self.source[..offset.min(self.source.len())]
+            .matches('\n')
+            .count();
+
+        self.original_line(line + 1)
+    }
+
+    fn push_line(&mut self, line: &str, original_line: usize) {
+        self.source.push_str(line);
+        self.source.push('\n');
+        self.line_map.push(original_line);
+    }
+
+    /// Pushes a piece of a logical line, which can span multiple lines
+    fn push_lines(&mut self, text: &str, first_line: usize) {
+        for (i, line) in text.split('\n').enumerate() {
+            self.push_line(line, first_line + i);
+        }
+    }
+}
+
+/// Inserts a fuel check at the start of every function and loop body, and into every comprehension.
+///
+/// Hebi does not yield or count instructions by itself, so this is what stops runaway scripts.
+/// Every compiled unit has to go through this, including imported modules.
+/// The source is split into tokens, so keywords in strings and comments or
+/// inside brackets that span multiple lines are not mistaken for statements.
+pub fn instrument_source(source: &str) -> InstrumentedSource {
+    let mut output = InstrumentedSource {
+        source: String::with_capacity(source.len() * 2),
+        line_map: Vec::new(),
+    };
+    output.push_line(&format!("import {SANDBOX_MODULE}"), 1);
+
+    // Indentation of the block header whose body starts on the next line
+    let mut block_indent: Option<usize> = None;
+
+    for logical_line in split_logical_lines(source) {
+        let line_number = logical_line.first_line;
+        let trimmed = logical_line.text.trim_start();
+        let indent = &logical_line.text[..logical_line.text.len() - trimmed.len()];
+
+        if let Some(header_indent) = block_indent {
+            if !trimmed.is_empty() && !trimmed.starts_with('#') {
+                if indent.len() > header_indent {
+                    output.push_line(&format!("{indent}{SANDBOX_MODULE}.tick()"), line_number);
+                }
+                block_indent = None;
+            }
+        }
+
+        let text = meter_comprehensions(logical_line.text);
+
+        match find_block_body(&text) {
+            Some(body_start) if has_code(&text[body_start..]) => {
+                // A body on the same line as the header is moved to its own lines, after the fuel check
+                let header = &text[..body_start];
+                output.push_lines(header, line_number);
+
+                let body_line = line_number + header.matches('\n').count();
+                let body_indent = format!("{indent}{BODY_INDENT}");
+                output.push_line(&format!("{body_indent}{SANDBOX_MODULE}.tick()"), body_line);
+                output.push_lines(
+                    &format!("{body_indent}{}", text[body_start..].trim_start()),
+                    body_line,
+                );
+            }
+            Some(_) => {
+                output.push_lines(&text, line_number);
+                block_indent = Some(indent.len());
+            }
+            None => output.push_lines(&text, line_number),
+        }
+    }
+
+    output
+}
+
+/// Scripts can't refer to the sandbox module, as shadowing or reassigning it would turn off the fuel checks.
+pub fn check_reserved_names(source: &str) -> Result<(), String> {
+    let uses_sandbox = scan(source)
+        .iter()
+        .any(|token| token.kind == TokenKind::Word && token.text(source) == SANDBOX_MODULE);
+
+    if uses_sandbox {
+        Err(format!(
+            "`{SANDBOX_MODULE}` is reserved and can't be used in scripts"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// 1-based line and column of a byte offset in the source.
+pub fn line_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..floor_char_boundary(source, offset)];
+    let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+struct LogicalLine<'a> {
+    /// 1-based line number where the logical line starts
+    first_line: usize,
+    text: &'a str,
+}
+
+/// Splits the source into statements, joining lines that are inside brackets.
+fn split_logical_lines(source: &str) -> Vec<LogicalLine<'_>> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut first_line = 1;
+    let mut line_number = 1;
+
+    for token in scan(source) {
+        if token.kind == TokenKind::Newline {
+            if token.depth == 0 {
+                lines.push(LogicalLine {
+                    first_line,
+                    text: &source[start..token.offset],
+                });
+                start = token.offset + 1;
+                first_line = line_number + 1;
+            }
+            line_number += 1;
+        }
+    }
+
+    if start < source.len() {
+        lines.push(LogicalLine {
+            first_line,
+            text: &source[start..],
+        });
+    }
+
+    lines
+}
+
+/// If the line is the header of a metered block, returns the offset right after the colon that ends the header.
+fn find_block_body(text: &str) -> Option<usize> {
+    let tokens = scan(text);
+    let first_word = tokens.iter().find(|token| token.kind != TokenKind::Space)?;
+
+    if !(first_word.kind == TokenKind::Word && METERED_BLOCKS.contains(&first_word.text(text))) {
+        return None;
+    }
+
+    tokens
+        .iter()
+        .find(|token| token.depth == 0 && token.kind == TokenKind::Symbol(':'))
+        .map(|colon| colon.offset + 1)
+}
+
+fn has_code(text: &str) -> bool {
+    scan(text)
+        .iter()
+        .any(|token| !matches!(token.kind, TokenKind::Space | TokenKind::Newline))
+}
+
+/// Wraps the element and the conditions of every comprehension in a fuel check,
+/// as they are evaluated once per iteration.
+fn meter_comprehensions(text: &str) -> String {
+    let tokens = scan(text);
+    let mut insertions = Vec::new();
+    let mut metered_groups = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.depth == 0 || token.kind != TokenKind::Word || token.text(text) != "for" {
+            continue;
+        }
+
+        let Some(open) = tokens[..i]
+            .iter()
+            .rposition(|other| other.depth == token.depth - 1 && other.is_opening())
+        else {
+            continue;
+        };
+        if metered_groups.contains(&open) {
+            continue;
+        }
+        metered_groups.push(open);
+
+        let group_end = tokens[i..]
+            .iter()
+            .position(|other| other.depth == token.depth - 1)
+            .map_or(tokens.len(), |position| i + position);
+        let in_group = |index: usize| tokens[index].depth == token.depth;
+
+        // The key of a dict comprehension is metered instead of the whole `key: value` pair
+        let element_end = (open + 1..i)
+            .find(|&index| in_group(index) && tokens[index].kind == TokenKind::Symbol(':'))
+            .unwrap_or(i);
+        wrap_tokens(&tokens, open + 1, element_end, &mut insertions);
+
+        let conditions = (i..group_end)
+            .filter(|&index| in_group(index) && tokens[index].kind == TokenKind::Word)
+            .filter(|&index| matches!(tokens[index].text(text), "if" | "for"))
+            .collect::<Vec<_>>();
+
+        for (n, &keyword) in conditions.iter().enumerate() {
+            if tokens[keyword].text(text) == "if" {
+                let end = conditions.get(n + 1).copied().unwrap_or(group_end);
+                wrap_tokens(&tokens, keyword + 1, end, &mut insertions);
+            }
+        }
+    }
+
+    let mut output = text.to_owned();
+    insertions.sort_by_key(|(offset, _)| *offset);
+
+    for (offset, insertion) in insertions.into_iter().rev() {
+        output.insert_str(offset, &insertion);
+    }
+
+    output
+}
+
+/// Wraps the code of the tokens in the range into a `tick_value` call, leaving out surrounding whitespace.
+fn wrap_tokens(tokens: &[Token], start: usize, end: usize, insertions: &mut Vec<(usize, String)>) {
+    let is_code = |token: &&Token| !matches!(token.kind, TokenKind::Space | TokenKind::Newline);
+
+    let first = tokens[start..end].iter().find(is_code);
+    let last = tokens[start..end].iter().rev().find(is_code);
+
+    if let (Some(first), Some(last)) = (first, last) {
+        insertions.push((first.offset, format!("{SANDBOX_MODULE}.tick_value(")));
+        insertions.push((last.offset + last.len, ")".to_owned()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Word,
+    String,
+    Symbol(char),
+    Space,
+    Newline,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: TokenKind,
+    offset: usize,
+    len: usize,
+    /// Bracket nesting level, brackets have the level of their surroundings
+    depth: usize,
+}
+
+impl Token {
+    fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.offset..self.offset + self.len]
+    }
+
+    fn is_opening(&self) -> bool {
+        matches!(self.kind, TokenKind::Symbol('(' | '[' | '{'))
+    }
+}
+
+/// Splits the source into words, strings, symbols and whitespace. Comments are skipped.
+fn scan(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    let mut depth: usize = 0;
+
+    while let Some((offset, c)) = chars.next() {
+        let mut end = offset + c.len_utf8();
+
+        let kind = match c {
+            '\n' => TokenKind::Newline,
+            '#' => {
+                while chars.next_if(|(_, next)| *next != '\n').is_some() {}
+                continue;
+            }
+            '"' | '\'' => {
+                let mut escaped = false;
+
+                // Strings don't continue past the end of the line
+                while let Some((next_offset, next)) = chars.next_if(|(_, next)| *next != '\n') {
+                    end = next_offset + next.len_utf8();
+
+                    match next {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        _ if next == c => break,
+                        _ => (),
+                    }
+                }
+                TokenKind::String
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                while let Some((next_offset, next)) =
+                    chars.next_if(|(_, next)| next.is_alphanumeric() || *next == '_')
+                {
+                    end = next_offset + next.len_utf8();
+                }
+                TokenKind::Word
+            }
+            c if c.is_whitespace() => TokenKind::Space,
+            c => TokenKind::Symbol(c),
+        };
+
+        if matches!(kind, TokenKind::Symbol(')' | ']' | '}')) {
+            depth = depth.saturating_sub(1);
+        }
+
+        tokens.push(Token {
+            kind,
+            offset,
+            len: end - offset,
+            depth,
+        });
+
+        if matches!(kind, TokenKind::Symbol('(' | '[' | '{')) {
+            depth += 1;
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_reserved_names, instrument_source, line_column};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn loops_and_functions() {
+        let source = "fn f(n):\n\n  # comment\n  return n\nwhile true:\n    for i in 0..10:\n        print(i)\nformat = 1\n";
+        let instrumented = instrument_source(source);
+
+        assert_eq!(
+            instrumented.source,
+            "import __sandbox\nfn f(n):\n\n  # comment\n  __sandbox.tick()\n  return n\nwhile true:\n    __sandbox.tick()\n    for i in 0..10:\n        __sandbox.tick()\n        print(i)\nformat = 1\n"
+        );
+        assert_eq!(instrumented.original_line(6), Some(4));
+        assert_eq!(instrumented.original_line(11), Some(7));
+        assert_eq!(instrumented.original_line(12), Some(8));
+    }
+
+    #[test]
+    fn single_line_bodies() {
+        let instrumented = instrument_source("x = 0\nwhile true: x = x + 1\nprint(x)\n");
+
+        assert_eq!(
+            instrumented.source,
+            "import __sandbox\nx = 0\nwhile true:\n    __sandbox.tick()\n    x = x + 1\nprint(x)\n"
+        );
+        assert_eq!(instrumented.original_line(5), Some(2));
+        assert_eq!(instrumented.original_line(6), Some(3));
+    }
+
+    #[test]
+    fn keywords_in_strings_and_brackets() {
+        let source =
+            "print(\"while true:\") # for x in y:\nvalues = [\n  1,\n  2,\n]\nloop:\n  break\n";
+        let instrumented = instrument_source(source);
+
+        assert_eq!(
+            instrumented.source,
+            "import __sandbox\nprint(\"while true:\") # for x in y:\nvalues = [\n  1,\n  2,\n]\nloop:\n  __sandbox.tick()\n  break\n"
+        );
+        assert_eq!(instrumented.original_line(7), Some(6));
+        assert_eq!(instrumented.original_line(8), Some(7));
+    }
+
+    #[test]
+    fn sandbox_module_is_reserved() {
+        assert!(check_reserved_names("__sandbox = {}\nloop:\n  x = 1\n").is_err());
+        assert!(check_reserved_names("fn f(__sandbox):\n  return 1\n").is_err());
+        assert!(check_reserved_names("import __sandbox as s\n").is_err());
+        assert!(check_reserved_names("print(\"__sandbox\") # __sandbox\n").is_ok());
+        assert!(check_reserved_names("__sandbox_x = 1\n").is_ok());
+    }
+
+    #[test]
+    fn line_columns() {
+        let source = "x = 1\nprint(\"ā\", y)\n";
+
+        assert_eq!(line_column(source, 0), (1, 1));
+        assert_eq!(line_column(source, 15), (2, 9));
+        assert_eq!(line_column(source, 14), (2, 8));
+        assert_eq!(line_column(source, 100), (3, 1));
+    }
+
+    #[test]
+    fn comprehensions() {
+        let source = "a = [x * 2 for x in xs if x > 1]\nb = {k: v for k in ks}\n";
+
+        assert_eq!(
+            instrument_source(source).source,
+            "import __sandbox\na = [__sandbox.tick_value(x * 2) for x in xs if __sandbox.tick_value(x > 1)]\nb = {__sandbox.tick_value(k): v for k in ks}\n"
+        );
+    }
+}
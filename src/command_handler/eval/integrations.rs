@@ -0,0 +1,138 @@
+use super::{context::HebiContext, utils::to_hebi_value};
+use crate::command_handler::services::{forsencode, ServiceError, Services};
+use hebi::prelude::*;
+use tracing::instrument;
+
+/// `weather.current(place?)`, uses the location of the user when there is no place.
+#[instrument(name = "hebi.weather.current", skip(scope, services, ctx))]
+pub async fn weather_current(
+    scope: Scope<'_>,
+    services: Services,
+    ctx: HebiContext,
+) -> hebi::Result<Str<'_>> {
+    let place = scope.param::<String>(0).ok();
+
+    let weather = services
+        .weather(ctx.user_id, place.as_deref())
+        .await
+        .map_err(service_error)?;
+
+    Ok(scope.new_string(weather))
+}
+
+/// `music.now_playing(user?)`, from Last.FM or Spotify.
+#[instrument(name = "hebi.music.now_playing", skip(scope, services, ctx))]
+pub async fn music_now_playing(
+    scope: Scope<'_>,
+    services: Services,
+    ctx: HebiContext,
+) -> hebi::Result<Str<'_>> {
+    let user_id = get_user_id(&scope, &services, &ctx).await?;
+    let song = services.now_playing(user_id).await.map_err(service_error)?;
+
+    Ok(scope.new_string(song))
+}
+
+#[instrument(name = "hebi.music.playlist", skip(scope, services, ctx))]
+pub async fn music_playlist(
+    scope: Scope<'_>,
+    services: Services,
+    ctx: HebiContext,
+) -> hebi::Result<Str<'_>> {
+    let user_id = get_user_id(&scope, &services, &ctx).await?;
+    let playlist = services
+        .spotify_playlist(user_id)
+        .await
+        .map_err(service_error)?;
+
+    Ok(scope.new_string(playlist))
+}
+
+#[instrument(name = "hebi.music.last_played", skip(scope, services, ctx))]
+pub async fn music_last_played(
+    scope: Scope<'_>,
+    services: Services,
+    ctx: HebiContext,
+) -> hebi::Result<Str<'_>> {
+    let user_id = get_user_id(&scope, &services, &ctx).await?;
+    let song = services
+        .spotify_last_played(user_id)
+        .await
+        .map_err(service_error)?;
+
+    Ok(scope.new_string(song))
+}
+
+/// `translate.text(text, to?, from?)`, translates to English from a detected language by default.
+#[instrument(name = "hebi.translate.text", skip(scope, services))]
+pub async fn translate_text(scope: Scope<'_>, services: Services) -> hebi::Result<Str<'_>> {
+    let text = scope.param::<String>(0)?;
+    let target = scope.param::<String>(1).unwrap_or_else(|_| "en".to_owned());
+    let source = scope
+        .param::<String>(2)
+        .unwrap_or_else(|_| "auto".to_owned());
+
+    let translation = services
+        .translate(&text, &source, &target)
+        .await
+        .map_err(service_error)?;
+
+    Ok(scope.new_string(translation))
+}
+
+#[instrument(name = "hebi.stock.quote", skip(scope, services))]
+pub async fn stock_quote(scope: Scope<'_>, services: Services) -> hebi::Result<Str<'_>> {
+    let symbol = scope.param::<String>(0)?;
+    let quote = services.stock_quote(&symbol).await.map_err(service_error)?;
+
+    Ok(scope.new_string(quote))
+}
+
+#[instrument(name = "hebi.ukraine.alerts", skip(scope, services))]
+pub async fn ukraine_alerts(scope: Scope<'_>, services: Services) -> hebi::Result<Str<'_>> {
+    let alerts = services.ukraine_alerts().await.map_err(service_error)?;
+
+    Ok(scope.new_string(alerts))
+}
+
+/// `twitch.user(login)`, returns the user from the Helix API as a table.
+#[instrument(name = "hebi.twitch.user", skip(scope, services))]
+pub async fn twitch_user(scope: Scope<'_>, services: Services) -> hebi::Result<Value<'_>> {
+    let login = scope.param::<String>(0)?;
+    let user = services
+        .twitch_user(Some(&login), None)
+        .await
+        .map_err(service_error)?;
+
+    to_hebi_value(&scope, user)
+}
+
+pub fn forsencode_encode(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let text = scope.param::<Str>(0)?;
+    Ok(scope.new_string(forsencode::encode(text.as_str())))
+}
+
+pub fn forsencode_decode(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let code = scope.param::<Str>(0)?;
+    let text = forsencode::decode(code.as_str()).map_err(|err| hebi::Error::User(err.into()))?;
+
+    Ok(scope.new_string(text))
+}
+
+/// The user can be given as the first parameter, defaults to the user running the command.
+async fn get_user_id(
+    scope: &Scope<'_>,
+    services: &Services,
+    ctx: &HebiContext,
+) -> hebi::Result<u64> {
+    let identifier = scope.param::<String>(0).ok();
+
+    services
+        .resolve_user(identifier.as_deref(), ctx.user_id)
+        .await
+        .map_err(service_error)
+}
+
+fn service_error(err: ServiceError) -> hebi::Error {
+    hebi::Error::User(err.to_string().into())
+}
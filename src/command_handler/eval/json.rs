@@ -0,0 +1,46 @@
+use super::{limits::charge_native_output, utils::to_hebi_value};
+use hebi::prelude::*;
+
+/// `json.encode(value, pretty?)`
+pub fn encode(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let value = scope.param::<Value>(0)?;
+    let pretty = scope.param::<bool>(1).unwrap_or(false);
+
+    let output = if pretty {
+        serde_json::to_string_pretty(&value)
+    } else {
+        serde_json::to_string(&value)
+    }
+    .map_err(hebi::Error::user)?;
+
+    charge_native_output(output.len())?;
+    Ok(scope.new_string(output))
+}
+
+pub fn decode(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let input = scope.param::<Str>(0)?;
+
+    let value = decode_str(input.as_str()).map_err(|err| hebi::Error::User(err.into()))?;
+    to_hebi_value(&scope, value)
+}
+
+fn decode_str(input: &str) -> Result<serde_json::Value, String> {
+    serde_json::from_str(input).map_err(|err| format!("invalid JSON: {err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_str;
+    use serde_json::json;
+
+    #[test]
+    fn decode() {
+        assert_eq!(
+            decode_str(r#"{"a": [1, "b", null]}"#).unwrap(),
+            json!({"a": [1, "b", null]})
+        );
+        assert!(decode_str("{a: 1}")
+            .unwrap_err()
+            .starts_with("invalid JSON: "));
+    }
+}
@@ -0,0 +1,304 @@
+use crate::database::models::HebiLimits;
+use hebi::prelude::*;
+use std::{
+    cell::Cell,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+const DEFAULT_FUEL: u32 = 1_000_000;
+const DEFAULT_NATIVE_OUTPUT_KB: u32 = 64 * 1024;
+const DEFAULT_HTTP_REQUESTS: u32 = 5;
+const DEFAULT_RESPONSE_KB: u32 = 1024;
+const DEFAULT_OUTPUT_LENGTH: u32 = 2000;
+const DEFAULT_TIMEOUT_SECS: u32 = 10;
+/// How many messages an invocation can send with `chat.say` and `chat.reply`, including the commands it runs
+pub const MAX_MESSAGES: u32 = 5;
+/// How often a running script yields back to the runtime, so the timeout can fire even in busy loops
+const YIELD_INTERVAL: u64 = 1000;
+
+/// Name of the native module the fuel metering calls are routed through
+pub const SANDBOX_MODULE: &str = "__sandbox";
+
+thread_local! {
+    static CURRENT_SANDBOX: Cell<*const Sandbox> = const { Cell::new(std::ptr::null()) };
+}
+
+#[derive(Debug, Clone)]
+pub struct SandboxLimits {
+    pub fuel: u64,
+    /// Budget for the data that native functions hand to the script, see [`Sandbox::charge_native_output`]
+    pub native_output_bytes: usize,
+    pub http_requests: u32,
+    pub response_bytes: usize,
+    pub output_length: usize,
+    pub timeout: Duration,
+}
+
+impl SandboxLimits {
+    pub fn new(overrides: Option<&HebiLimits>) -> Self {
+        let get = |field: fn(&HebiLimits) -> Option<u32>, default: u32| {
+            overrides.and_then(field).unwrap_or(default)
+        };
+
+        Self {
+            fuel: get(|limits| limits.fuel, DEFAULT_FUEL) as u64,
+            native_output_bytes: get(|limits| limits.native_output_kb, DEFAULT_NATIVE_OUTPUT_KB)
+                as usize
+                * 1024,
+            http_requests: get(|limits| limits.http_requests, DEFAULT_HTTP_REQUESTS),
+            response_bytes: get(|limits| limits.response_kb, DEFAULT_RESPONSE_KB) as usize * 1024,
+            output_length: get(|limits| limits.output_length, DEFAULT_OUTPUT_LENGTH) as usize,
+            timeout: Duration::from_secs(
+                get(|limits| limits.timeout_secs, DEFAULT_TIMEOUT_SECS).into(),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LimitError {
+    #[error("instruction limit of {0} exceeded")]
+    Fuel(u64),
+    #[error("native functions returned more than the {} KB limit", .0 / 1024)]
+    NativeOutput(usize),
+    #[error("limit of {0} HTTP requests per run exceeded")]
+    HttpRequests(u32),
+    #[error("HTTP response is larger than the {} KB limit", .0 / 1024)]
+    ResponseSize(usize),
+    #[error("cannot send more than {0} messages per run")]
+    Messages(u32),
+    #[error("output is longer than the {0} character limit")]
+    OutputLength(usize),
+    #[error("execution timed out after {} seconds", .0.as_secs())]
+    Timeout(Duration),
+}
+
+/// Resource accounting of a single Hebi evaluation.
+#[derive(Debug)]
+pub struct Sandbox {
+    pub limits: SandboxLimits,
+    pub channel_id: u64,
+    /// How deeply this evaluation is nested in other commands, see `chat.run`
+    pub depth: u32,
+    usage: Arc<Usage>,
+}
+
+/// Resources used by an invocation, shared with the commands it runs through `chat.run`
+#[derive(Debug, Default)]
+struct Usage {
+    fuel_used: AtomicU64,
+    http_requests: AtomicU32,
+    /// Size of the data that native functions returned to the script
+    native_output: AtomicUsize,
+    messages_sent: AtomicU32,
+}
+
+impl Sandbox {
+    /// Nested evaluations use up the budget of their parent, so that running commands in a loop can't get around the limits.
+    pub fn new(limits: SandboxLimits, channel_id: u64, parent: Option<&Sandbox>) -> Arc<Self> {
+        Arc::new(Self {
+            limits,
+            channel_id,
+            depth: parent.map_or(0, |parent| parent.depth + 1),
+            usage: parent.map_or_else(Arc::default, |parent| parent.usage.clone()),
+        })
+    }
+
+    /// Returns the sandbox of the evaluation that is currently being polled on this thread.
+    pub fn current() -> Option<Arc<Self>> {
+        CURRENT_SANDBOX.with(|current| {
+            let ptr = current.get();
+
+            (!ptr.is_null()).then(|| unsafe {
+                // The pointer comes from `Arc::as_ptr` and the `Metered` future keeps the Arc alive while it is set
+                Arc::increment_strong_count(ptr);
+                Arc::from_raw(ptr)
+            })
+        })
+    }
+
+    /// Consumes a unit of fuel.
+    pub async fn tick(&self) -> Result<(), LimitError> {
+        let used = self.usage.fuel_used.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if used > self.limits.fuel {
+            return Err(LimitError::Fuel(self.limits.fuel));
+        }
+
+        if used % YIELD_INTERVAL == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        Ok(())
+    }
+
+    /// Counts data that a native function is about to hand to the script against the native output budget.
+    ///
+    /// This is not a memory limit: Hebi doesn't expose the size of its heap, so the lists and strings
+    /// that the script builds itself are not counted. Only the values created by native functions
+    /// (HTTP responses, storage, JSON and string helpers) are, and they are never subtracted.
+    pub fn charge_native_output(&self, bytes: usize) -> Result<(), LimitError> {
+        let total = self.usage.native_output.fetch_add(bytes, Ordering::Relaxed) + bytes;
+
+        if total > self.limits.native_output_bytes {
+            Err(LimitError::NativeOutput(self.limits.native_output_bytes))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn start_http_request(&self) -> Result<(), LimitError> {
+        let count = self.usage.http_requests.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if count > self.limits.http_requests {
+            Err(LimitError::HttpRequests(self.limits.http_requests))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn start_message(&self) -> Result<(), LimitError> {
+        let count = self.usage.messages_sent.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if count > MAX_MESSAGES {
+            Err(LimitError::Messages(MAX_MESSAGES))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_output(&self, output: &str) -> Result<(), LimitError> {
+        if output.chars().count() > self.limits.output_length {
+            Err(LimitError::OutputLength(self.limits.output_length))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Wraps the evaluation future, so that native functions called while polling it can find this sandbox.
+    pub fn meter<F: Future>(self: &Arc<Self>, future: F) -> Metered<F> {
+        Metered {
+            future: Box::pin(future),
+            sandbox: self.clone(),
+        }
+    }
+}
+
+pub struct Metered<F: Future> {
+    future: Pin<Box<F>>,
+    sandbox: Arc<Sandbox>,
+}
+
+impl<F: Future> Future for Metered<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let sandbox_ptr = Arc::as_ptr(&self.sandbox);
+        let previous = CURRENT_SANDBOX.with(|current| current.replace(sandbox_ptr));
+
+        let result = self.future.as_mut().poll(cx);
+
+        CURRENT_SANDBOX.with(|current| current.set(previous));
+
+        result
+    }
+}
+
+/// Native function that the instrumented code calls at the start of every function and loop body.
+pub async fn tick(_: Scope<'_>) -> hebi::Result<()> {
+    match Sandbox::current() {
+        Some(sandbox) => sandbox.tick().await.map_err(hebi::Error::user),
+        None => Ok(()),
+    }
+}
+
+/// Consumes fuel and returns its argument, used for expressions that are evaluated repeatedly.
+pub async fn tick_value(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let value = scope.param::<Value>(0)?;
+
+    if let Some(sandbox) = Sandbox::current() {
+        sandbox.tick().await.map_err(hebi::Error::user)?;
+    }
+
+    Ok(value)
+}
+
+/// Charges the native output budget of the sandbox that is currently being polled, see [`Sandbox::charge_native_output`].
+pub fn charge_native_output(bytes: usize) -> hebi::Result<()> {
+    match Sandbox::current() {
+        Some(sandbox) => sandbox
+            .charge_native_output(bytes)
+            .map_err(hebi::Error::user),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LimitError, Sandbox, SandboxLimits, MAX_MESSAGES};
+
+    #[tokio::test]
+    async fn fuel_runs_out() {
+        let sandbox = Sandbox::new(
+            SandboxLimits {
+                fuel: 3,
+                ..SandboxLimits::new(None)
+            },
+            0,
+            None,
+        );
+
+        for _ in 0..3 {
+            sandbox.tick().await.unwrap();
+        }
+        assert!(matches!(sandbox.tick().await, Err(LimitError::Fuel(3))));
+    }
+
+    #[test]
+    fn native_output_runs_out() {
+        let sandbox = Sandbox::new(
+            SandboxLimits {
+                native_output_bytes: 1024,
+                ..SandboxLimits::new(None)
+            },
+            0,
+            None,
+        );
+
+        sandbox.charge_native_output(1000).unwrap();
+        assert!(matches!(
+            sandbox.charge_native_output(100),
+            Err(LimitError::NativeOutput(1024))
+        ));
+    }
+
+    #[tokio::test]
+    async fn nested_runs_share_the_budget() {
+        let limits = SandboxLimits {
+            fuel: 2,
+            ..SandboxLimits::new(None)
+        };
+        let parent = Sandbox::new(limits.clone(), 0, None);
+        parent.tick().await.unwrap();
+
+        let nested = Sandbox::new(limits, 0, Some(&parent));
+        assert_eq!(nested.depth, 1);
+        nested.tick().await.unwrap();
+        assert!(matches!(nested.tick().await, Err(LimitError::Fuel(2))));
+
+        for _ in 0..MAX_MESSAGES {
+            nested.start_message().unwrap();
+        }
+        assert!(matches!(
+            parent.start_message(),
+            Err(LimitError::Messages(MAX_MESSAGES))
+        ));
+    }
+}
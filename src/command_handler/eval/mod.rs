@@ -1,30 +1,55 @@
+pub mod cache;
+mod chat;
 pub mod context;
 mod db;
 mod http;
+pub mod instrument;
+mod integrations;
+mod json;
+pub mod limits;
+mod random;
+mod regex;
 pub mod storage;
+mod text;
+pub mod time;
+mod twitch;
 mod utils;
 
-use self::{context::HebiContext, storage::ModuleStorage};
-use super::error::CommandError;
-use crate::database::Database;
+pub use self::chat::ChatContext;
+use self::{
+    cache::PreparedScript,
+    context::HebiContext,
+    instrument::InstrumentedSource,
+    limits::{LimitError, Sandbox, SandboxLimits},
+    storage::{ChannelModuleLoader, ModuleStorage},
+};
+use super::{egress::EgressPolicy, error::CommandError, services::Services};
+use crate::database::{models::HebiDataScope, Database};
 use hebi::prelude::*;
-use reqwest::Client;
-use std::time::Duration;
 use tokio::time::timeout;
 use tracing::instrument;
 
-const TIMEOUT_SECS: u64 = 10;
-
-#[instrument(skip(native_modules, module_storage))]
+#[instrument(skip(native_modules, module_storage, services, chat))]
+#[allow(clippy::too_many_arguments)]
 pub async fn eval_hebi(
-    source: String,
+    script: PreparedScript,
     native_modules: &[NativeModule],
     module_storage: ModuleStorage,
     db: Database,
     args: &[String],
     ctx: HebiContext,
+    services: Services,
+    chat: Option<ChatContext>,
 ) -> Result<Option<String>, CommandError> {
-    let mut hebi = Hebi::builder().module_loader(module_storage).finish();
+    let channel_id = ctx.channel_id;
+    let limits = db.run(move |db| db.get_hebi_limits(channel_id)).await?;
+    let limits = SandboxLimits::new(limits.as_ref());
+    // When this is a command ran from another Hebi command, the parent evaluation is still being polled
+    let parent = Sandbox::current();
+    let sandbox = Sandbox::new(limits, ctx.channel_id, parent.as_deref());
+
+    let module_loader = ChannelModuleLoader::new(ctx.channel_id, &db, module_storage).await?;
+    let mut hebi = Hebi::builder().module_loader(module_loader).finish();
 
     {
         let args_list = hebi.new_list(args.len());
@@ -42,50 +67,191 @@ pub async fn eval_hebi(
         hebi.register(module);
     }
 
-    let db_module = NativeModule::builder("db")
-        .function("get", {
-            let db = db.clone();
+    let mut db_module = NativeModule::builder("db");
+
+    for &(channel_name, user_name, function) in db::FUNCTIONS {
+        for (name, data_scope) in [
+            (channel_name, HebiDataScope::Channel),
+            (user_name, HebiDataScope::User(ctx.user_id)),
+        ] {
+            db_module = db_module.async_function(name, {
+                let db = db.clone();
+                let ctx = ctx.clone();
+                move |scope| db::call(scope, function, db.clone(), ctx.clone(), data_scope)
+            });
+        }
+    }
+
+    let db_module = db_module.finish();
+
+    hebi.register(&db_module);
+
+    if let Some(chat) = chat {
+        let chat_module = NativeModule::builder("chat")
+            .async_function("say", {
+                let chat = chat.clone();
+                move |scope| chat::say(scope, chat.clone())
+            })
+            .async_function("reply", {
+                let chat = chat.clone();
+                move |scope| chat::reply(scope, chat.clone())
+            })
+            .async_function("run", move |scope| chat::run(scope, chat.clone()))
+            .finish();
+
+        hebi.register(&chat_module);
+    }
+
+    let weather_module = NativeModule::builder("weather")
+        .async_function("current", {
+            let services = services.clone();
+            let ctx = ctx.clone();
+            move |scope| integrations::weather_current(scope, services.clone(), ctx.clone())
+        })
+        .finish();
+    hebi.register(&weather_module);
+
+    let music_module = NativeModule::builder("music")
+        .async_function("now_playing", {
+            let services = services.clone();
             let ctx = ctx.clone();
-            move |scope| db::get(scope, db.clone(), ctx.clone())
+            move |scope| integrations::music_now_playing(scope, services.clone(), ctx.clone())
         })
-        .function("set", {
-            let db = db.clone();
+        .async_function("playlist", {
+            let services = services.clone();
             let ctx = ctx.clone();
-            move |scope| db::set(scope, db.clone(), ctx.clone())
+            move |scope| integrations::music_playlist(scope, services.clone(), ctx.clone())
         })
-        .function("remove", {
-            let db = db.clone();
+        .async_function("last_played", {
+            let services = services.clone();
             let ctx = ctx.clone();
-            move |scope| db::remove(scope, db.clone(), ctx.clone())
+            move |scope| integrations::music_last_played(scope, services.clone(), ctx.clone())
         })
         .finish();
+    hebi.register(&music_module);
 
-    hebi.register(&db_module);
+    if services.twitch_api.is_some() {
+        let mut twitch_module = NativeModule::builder("twitch").async_function("user", {
+            let services = services.clone();
+            move |scope| integrations::twitch_user(scope, services.clone())
+        });
+
+        for &(name, action) in twitch::MODERATION_FUNCTIONS {
+            twitch_module = twitch_module.async_function(name, {
+                let db = db.clone();
+                let ctx = ctx.clone();
+                move |scope| twitch::moderate(scope, action, db.clone(), ctx.clone())
+            });
+        }
+
+        hebi.register(&twitch_module.finish());
+    }
 
     hebi.global()
         .set(hebi.new_string("context"), hebi.new_instance(ctx).unwrap());
 
-    let eval_future = hebi.eval_async(&source);
+    let chunk = script
+        .compile(&mut hebi)
+        .map_err(|err| CommandError::GenericError(describe_error(&err, script.instrumented())))?;
+    let eval_future = sandbox.meter(hebi.run_async(chunk));
+
+    let output = match timeout(sandbox.limits.timeout, eval_future).await {
+        Ok(Ok(value)) => value.to_string(),
+        Ok(Err(err)) => {
+            return Err(CommandError::GenericError(describe_error(
+                &err,
+                script.instrumented(),
+            )))
+        }
+        Err(_) => return Err(LimitError::Timeout(sandbox.limits.timeout).into()),
+    };
 
-    match timeout(Duration::from_secs(TIMEOUT_SECS), eval_future).await {
-        Ok(Ok(value)) => Ok(Some(value.to_string())),
-        Ok(Err(err)) => Err(CommandError::GenericError(err.to_string())),
-        Err(_) => Err(CommandError::GenericError("Execution timed out".to_owned())),
+    sandbox.check_output(&output)?;
+
+    Ok(Some(output))
+}
+
+/// Byte offsets and messages of the syntax errors in a compilation error.
+pub fn syntax_errors(err: &hebi::Error) -> Vec<(usize, String)> {
+    match err {
+        hebi::Error::Syntax(errors) => errors
+            .iter()
+            .map(|error| (error.span.start, error.message.to_string()))
+            .collect(),
+        _ => Vec::new(),
     }
 }
 
-pub fn create_native_modules(_: Database) -> Vec<NativeModule> {
-    let mut modules = Vec::new();
+/// Syntax errors are reported at the line of the original script, not of the instrumented one.
+fn describe_error(err: &hebi::Error, source: &InstrumentedSource) -> String {
+    let errors = syntax_errors(err);
+
+    if errors.is_empty() {
+        return err.to_string();
+    }
+
+    errors
+        .into_iter()
+        .map(|(offset, message)| match source.original_line_at(offset) {
+            Some(line) => format!("line {line}: {message}"),
+            None => message,
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Modules that are the same for every evaluation, so they are only built once.
+///
+/// The VM itself is still created for every evaluation, as scripts can change its globals.
+pub fn create_native_modules(egress: EgressPolicy, services: &Services) -> Vec<NativeModule> {
+    let mut modules = create_stdlib_modules();
+
+    let translate = NativeModule::builder("translate")
+        .async_function("text", {
+            let services = services.clone();
+            move |scope| integrations::translate_text(scope, services.clone())
+        })
+        .finish();
+    modules.push(translate);
 
-    let http_client = Client::new();
+    let stock = NativeModule::builder("stock")
+        .async_function("quote", {
+            let services = services.clone();
+            move |scope| integrations::stock_quote(scope, services.clone())
+        })
+        .finish();
+    modules.push(stock);
+
+    let ukraine = NativeModule::builder("ukraine")
+        .async_function("alerts", {
+            let services = services.clone();
+            move |scope| integrations::ukraine_alerts(scope, services.clone())
+        })
+        .finish();
+    modules.push(ukraine);
 
     let http = NativeModule::builder("http")
-        .async_function("fetch", move |scope| {
-            http::request(scope, http_client.clone())
+        .async_function("fetch", {
+            let egress = egress.clone();
+            move |scope| http::fetch(scope, egress.clone())
         })
+        .async_function("request", move |scope| http::request(scope, egress.clone()))
         .finish();
     modules.push(http);
 
+    modules
+}
+
+/// Modules that don't depend on any services
+pub fn create_stdlib_modules() -> Vec<NativeModule> {
+    let mut modules = Vec::new();
+
+    let sandbox = NativeModule::builder(limits::SANDBOX_MODULE)
+        .async_function("tick", limits::tick)
+        .async_function("tick_value", limits::tick_value)
+        .finish();
+    modules.push(sandbox);
+
     let utils = NativeModule::builder("utils")
         .function("format", utils::format_string)
         .function("to_int", utils::to_int)
@@ -93,10 +259,70 @@ pub fn create_native_modules(_: Database) -> Vec<NativeModule> {
         .finish();
     modules.push(utils);
 
+    let random = NativeModule::builder("random")
+        .function("int", random::int)
+        .function("choice", random::choice)
+        .function("shuffle", random::shuffle)
+        .finish();
+    modules.push(random);
+
+    let time = NativeModule::builder("time")
+        .function("now", time::now)
+        .function("format", time::format)
+        .function("duration", time::duration)
+        .function("parse_duration", time::parse_duration)
+        .finish();
+    modules.push(time);
+
+    let json = NativeModule::builder("json")
+        .function("encode", json::encode)
+        .function("decode", json::decode)
+        .finish();
+    modules.push(json);
+
+    let regex = NativeModule::builder("regex")
+        .function("is_match", regex::is_match)
+        .function("find", regex::find)
+        .function("replace", regex::replace)
+        .function("captures", regex::captures)
+        .finish();
+    modules.push(regex);
+
+    let text = NativeModule::builder("text")
+        .function("upper", text::upper)
+        .function("lower", text::lower)
+        .function("capitalize", text::capitalize)
+        .function("split", text::split)
+        .function("unping", text::unping)
+        .finish();
+    modules.push(text);
+
+    let forsencode = NativeModule::builder("forsencode")
+        .function("encode", integrations::forsencode_encode)
+        .function("decode", integrations::forsencode_decode)
+        .finish();
+    modules.push(forsencode);
+
     let context_module = NativeModule::builder("context")
         .class::<HebiContext>("Context", |class| {
             class
-                .field("channel_id", |_, this| this.channel_id as i32)
+                // Ids don't fit into an int, but are exact as floats
+                .field("channel_id", |_, this| this.channel_id as f64)
+                .field("user_id", |_, this| this.user_id as f64)
+                .field("display_name", |scope, this| {
+                    scope.new_string(&this.display_name)
+                })
+                .field("user_identifier", |scope, this| {
+                    scope.new_string(&this.user_identifier)
+                })
+                .field("platform", |scope, this| scope.new_string(&this.platform))
+                .field("channel", |scope, this| scope.new_string(&this.channel))
+                .field("permissions", |_, this| this.permissions)
+                // Milliseconds don't fit into an int
+                .field("processing_timestamp", |_, this| {
+                    this.processing_timestamp as f64
+                })
+                .field("timezone", |scope, this| scope.new_string(&this.timezone))
                 .finish()
         })
         .finish();
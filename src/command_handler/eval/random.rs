@@ -0,0 +1,62 @@
+use hebi::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+
+/// Returns a random integer between `min` and `max`, both inclusive.
+pub fn int(scope: Scope<'_>) -> hebi::Result<i32> {
+    let min = scope.param::<i32>(0)?;
+    let max = scope.param::<i32>(1)?;
+
+    random_int(min, max).map_err(|err| hebi::Error::User(err.into()))
+}
+
+/// Returns a random element of the list, or none if it's empty.
+pub fn choice(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let list = scope.param::<List>(0)?;
+
+    if list.is_empty() {
+        return None::<String>.into_value(scope.global());
+    }
+
+    let index = rand::thread_rng().gen_range(0..list.len());
+    list.get(index)
+        .ok_or_else(|| hebi::Error::User("index out of bounds".into()))
+}
+
+/// Returns a shuffled copy of the list.
+pub fn shuffle(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let list = scope.param::<List>(0)?;
+
+    let mut items: Vec<_> = (0..list.len()).filter_map(|i| list.get(i)).collect();
+    items.shuffle(&mut rand::thread_rng());
+
+    let shuffled = scope.new_list(items.len());
+    for item in items {
+        shuffled.push(item);
+    }
+
+    shuffled.into_value(scope.global())
+}
+
+fn random_int(min: i32, max: i32) -> Result<i32, String> {
+    if min > max {
+        return Err(format!("invalid range: {min} is larger than {max}"));
+    }
+
+    Ok(rand::thread_rng().gen_range(min..=max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::random_int;
+
+    #[test]
+    fn int_range() {
+        for _ in 0..100 {
+            let value = random_int(-2, 2).unwrap();
+            assert!((-2..=2).contains(&value));
+        }
+
+        assert_eq!(random_int(5, 5), Ok(5));
+        assert!(random_int(3, 1).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use super::{limits::charge_native_output, utils::to_hebi_value};
+use ::regex::{Regex, RegexBuilder};
+use hebi::prelude::*;
+
+/// Compiled size limit, so user-provided patterns can't use up a lot of memory
+const SIZE_LIMIT: usize = 1024 * 1024;
+
+pub fn is_match(scope: Scope<'_>) -> hebi::Result<bool> {
+    let regex = compile(&scope)?;
+    let text = scope.param::<Str>(1)?;
+
+    Ok(regex.is_match(text.as_str()))
+}
+
+/// Returns the first match, or none.
+pub fn find(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let regex = compile(&scope)?;
+    let text = scope.param::<Str>(1)?;
+
+    regex
+        .find(text.as_str())
+        .map(|found| found.as_str().to_owned())
+        .into_value(scope.global())
+}
+
+/// Replaces all matches, the replacement can reference groups with `$1` or `$name`.
+pub fn replace(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let regex = compile(&scope)?;
+    let text = scope.param::<Str>(1)?;
+    let replacement = scope.param::<Str>(2)?;
+
+    let output = regex.replace_all(text.as_str(), replacement.as_str());
+
+    charge_native_output(output.len())?;
+    Ok(scope.new_string(output))
+}
+
+/// Returns a list of the groups of the first match (with the whole match first), or none.
+/// Groups that didn't participate in the match are none.
+pub fn captures(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let regex = compile(&scope)?;
+    let text = scope.param::<Str>(1)?;
+
+    match get_captures(&regex, text.as_str()) {
+        Some(groups) => to_hebi_value(&scope, groups.into()),
+        None => None::<String>.into_value(scope.global()),
+    }
+}
+
+fn compile(scope: &Scope<'_>) -> hebi::Result<Regex> {
+    let pattern = scope.param::<Str>(0)?;
+
+    RegexBuilder::new(pattern.as_str())
+        .size_limit(SIZE_LIMIT)
+        .build()
+        .map_err(hebi::Error::user)
+}
+
+fn get_captures(regex: &Regex, text: &str) -> Option<Vec<Option<String>>> {
+    regex.captures(text).map(|captures| {
+        captures
+            .iter()
+            .map(|group| group.map(|group| group.as_str().to_owned()))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_captures;
+    use ::regex::Regex;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn captures() {
+        let regex = Regex::new(r"(\w+)@(\w+)(\.com)?").unwrap();
+
+        assert_eq!(
+            get_captures(&regex, "mail me at user@example"),
+            Some(vec![
+                Some("user@example".to_owned()),
+                Some("user".to_owned()),
+                Some("example".to_owned()),
+                None
+            ])
+        );
+        assert_eq!(get_captures(&regex, "nothing here"), None);
+    }
+}
@@ -1,14 +1,34 @@
+use super::instrument::{check_reserved_names, instrument_source};
+use super::limits::SANDBOX_MODULE;
+use crate::database::{models::HebiModule, Database, DatabaseError};
 use anyhow::anyhow;
 use arc_swap::ArcSwap;
 use hebi::prelude::*;
-use std::{collections::HashMap, env, fs, path::Path, process::Command, sync::Arc};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 use tempfile::{tempdir, TempDir};
-use tracing::info;
+use tracing::{error, info};
+
+/// How often a local modules directory is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug, Clone)]
 pub struct ModuleStorage {
     pub modules: Arc<ArcSwap<HashMap<String, String>>>,
-    temp_dir: Arc<TempDir>,
+    source: ModuleSource,
+}
+
+#[derive(Debug, Clone)]
+enum ModuleSource {
+    Git(Arc<TempDir>),
+    Local(PathBuf),
+    None,
 }
 
 impl ModuleStorage {
@@ -27,41 +47,114 @@ impl ModuleStorage {
             return Err(anyhow!("Could not clone git repo: {stderr}"));
         }
 
-        let modules = Arc::new(load_modules_from_path(temp_dir.path())?.into());
+        let modules = Arc::new(load_initial_modules(temp_dir.path()).into());
 
-        Ok(Self { modules, temp_dir })
+        Ok(Self {
+            modules,
+            source: ModuleSource::Git(temp_dir),
+        })
     }
 
+    pub fn from_path(path: PathBuf) -> anyhow::Result<ModuleStorage> {
+        if !path.is_dir() {
+            return Err(anyhow!("{} is not a directory", path.display()));
+        }
+
+        let modules = load_initial_modules(&path);
+
+        Ok(Self {
+            modules: Arc::new(modules.into()),
+            source: ModuleSource::Local(path),
+        })
+    }
+
+    /// Reloads the modules, returning a description of the new version if anything changed.
+    /// The currently loaded modules are kept if the new ones are not valid.
     pub fn update(&self) -> anyhow::Result<Option<String>> {
-        let old_commit = get_current_commmit(self.temp_dir.path())?;
+        match &self.source {
+            ModuleSource::Git(temp_dir) => {
+                let old_commit = get_current_commmit(temp_dir.path())?;
 
-        let output = Command::new("git")
-            .arg("pull")
-            .current_dir(self.temp_dir.path())
-            .output()?;
+                let output = Command::new("git")
+                    .arg("pull")
+                    .current_dir(temp_dir.path())
+                    .output()?;
 
-        if !output.status.success() {
-            let stderr = String::from_utf8(output.stderr)?;
-            return Err(anyhow!("Could not update git repo: {stderr}"));
-        }
+                if !output.status.success() {
+                    let stderr = String::from_utf8(output.stderr)?;
+                    return Err(anyhow!("Could not update git repo: {stderr}"));
+                }
 
-        let new_commit = get_current_commmit(self.temp_dir.path())?;
+                let new_commit = get_current_commmit(temp_dir.path())?;
 
-        let new_modules = load_modules_from_path(self.temp_dir.path())?;
-        self.modules.store(new_modules);
+                let new_modules = load_modules_from_path(temp_dir.path())?;
+                self.modules.store(new_modules);
 
-        if new_commit != old_commit {
-            Ok(Some(new_commit))
-        } else {
-            Ok(None)
+                if new_commit != old_commit {
+                    Ok(Some(format!("commit {new_commit}")))
+                } else {
+                    Ok(None)
+                }
+            }
+            ModuleSource::Local(path) => {
+                let new_modules = load_modules_from_path(path)?;
+
+                if **self.modules.load() != *new_modules {
+                    let description = format!("{} modules", new_modules.len());
+                    self.modules.store(new_modules);
+                    Ok(Some(description))
+                } else {
+                    Ok(None)
+                }
+            }
+            ModuleSource::None => Ok(None),
         }
     }
 
+    /// Periodically checks a local modules directory for changes, and reloads the modules when it changes.
+    pub fn watch(&self) {
+        let ModuleSource::Local(path) = &self.source else {
+            return;
+        };
+
+        let storage = self.clone();
+        let path = path.clone();
+
+        tokio::spawn(async move {
+            let mut last_snapshot = snapshot_path(&path).ok();
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+
+            loop {
+                interval.tick().await;
+
+                let snapshot = match snapshot_path(&path) {
+                    Ok(snapshot) => Some(snapshot),
+                    Err(err) => {
+                        error!("Could not read hebi modules directory: {err}");
+                        None
+                    }
+                };
+
+                if snapshot.is_some() && snapshot != last_snapshot {
+                    info!("Hebi modules changed, reloading");
+
+                    match storage.update() {
+                        Ok(Some(description)) => info!("Reloaded hebi modules ({description})"),
+                        Ok(None) => (),
+                        Err(err) => error!("Not reloading hebi modules: {err:#}"),
+                    }
+
+                    last_snapshot = snapshot;
+                }
+            }
+        });
+    }
+
     pub fn empty() -> Self {
         info!("Creating empty hebi module storage");
         Self {
             modules: Default::default(),
-            temp_dir: Arc::new(TempDir::new().unwrap()),
+            source: ModuleSource::None,
         }
     }
 }
@@ -70,12 +163,78 @@ impl ModuleLoader for ModuleStorage {
     fn load(&self, path: &str) -> hebi::Result<Cow<'static, str>> {
         let modules = self.modules.load();
         match modules.get(path) {
-            Some(code) => Ok(Cow::owned(code.clone())),
+            Some(code) => load_source(code),
             None => Err(hebi::Error::User(format!("Module {path} not found").into())),
         }
     }
 }
 
+/// Imported modules are metered just like the script itself.
+fn load_source(source: &str) -> hebi::Result<Cow<'static, str>> {
+    check_reserved_names(source).map_err(|err| hebi::Error::User(err.into()))?;
+
+    Ok(Cow::owned(instrument_source(source).source))
+}
+
+/// Resolves imports against the modules of the channel first, and the global modules second.
+///
+/// The modules of the channel are fetched up front, as imports are resolved synchronously while the script runs.
+pub struct ChannelModuleLoader {
+    channel_modules: Arc<Vec<HebiModule>>,
+    global: ModuleStorage,
+}
+
+impl ChannelModuleLoader {
+    pub async fn new(
+        channel_id: u64,
+        db: &Database,
+        global: ModuleStorage,
+    ) -> Result<Self, DatabaseError> {
+        let channel_modules = db
+            .run(move |db| db.get_cached_hebi_modules(channel_id))
+            .await?;
+
+        Ok(Self {
+            channel_modules,
+            global,
+        })
+    }
+}
+
+impl ModuleLoader for ChannelModuleLoader {
+    fn load(&self, path: &str) -> hebi::Result<Cow<'static, str>> {
+        let channel_module = self
+            .channel_modules
+            .iter()
+            // A channel module can't take the place of the fuel checks
+            .find(|module| module.name == path && module.name != SANDBOX_MODULE);
+
+        match channel_module {
+            Some(module) => load_source(&module.source),
+            None => self.global.load(path),
+        }
+    }
+}
+
+/// Checks the name and the source of a module before it is saved to a channel.
+pub fn validate_channel_module(name: &str, source: &str) -> Result<(), String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "Invalid module name `{name}`, only letters, numbers and underscores are allowed"
+        ));
+    }
+
+    if name == SANDBOX_MODULE {
+        return Err(format!("Module name `{name}` is reserved"));
+    }
+
+    if source.trim().is_empty() {
+        return Err("Module source is empty".to_owned());
+    }
+
+    validate_module(source).map_err(|err| format!("Module {name} is not valid: {err}"))
+}
+
 fn get_current_commmit(path: &Path) -> anyhow::Result<String> {
     let output = Command::new("git")
         .args(["rev-parse", "--short", "HEAD"])
@@ -85,23 +244,49 @@ fn get_current_commmit(path: &Path) -> anyhow::Result<String> {
     Ok(commit)
 }
 
+/// Checks that the source can be compiled, without running it.
+pub fn validate_module(source: &str) -> Result<(), String> {
+    compile_module(source).map_err(|err| err.to_string())
+}
+
+pub fn compile_module(source: &str) -> hebi::Result<()> {
+    check_reserved_names(source).map_err(|err| hebi::Error::User(err.into()))?;
+
+    let hebi = Hebi::builder().finish();
+
+    hebi.compile(source).map(|_| ())
+}
+
+/// Broken modules don't prevent startup, they are reported and the storage starts out empty until they are fixed.
+fn load_initial_modules(path: &Path) -> Arc<HashMap<String, String>> {
+    load_modules_from_path(path).unwrap_or_else(|err| {
+        error!("Could not load hebi modules: {err:#}");
+        Arc::default()
+    })
+}
+
+/// Loads all `.hebi` files in the directory and its subdirectories.
+/// Modules in subdirectories are named by their relative path, e.g. `utils/strings`.
 fn load_modules_from_path(path: &Path) -> anyhow::Result<Arc<HashMap<String, String>>> {
     let mut modules = HashMap::new();
-    let read_dir = fs::read_dir(path)?;
+    collect_module_files(path, "", &mut |module_name, file_path| {
+        let contents = fs::read_to_string(file_path)?;
+        modules.insert(module_name, contents);
+        Ok(())
+    })?;
 
-    for item in read_dir {
-        let item = item?;
-        if item.metadata()?.is_file() {
-            let file_name = item
-                .file_name()
-                .into_string()
-                .map_err(|err| anyhow!("Module {err:?} does not have a valid name"))?;
-
-            if let Some(module_name) = file_name.strip_suffix(".hebi") {
-                let contents = fs::read_to_string(item.path())?;
-                modules.insert(module_name.to_owned(), contents);
-            }
-        }
+    let mut errors: Vec<String> = modules
+        .iter()
+        .filter_map(|(name, source)| {
+            validate_module(source)
+                .err()
+                .map(|err| format!("{name}: {err}"))
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        errors.sort();
+        return Err(anyhow!("Invalid hebi modules:\n{}", errors.join("\n")));
     }
 
     info!("Loaded {} hebi modules", modules.len());
@@ -109,9 +294,96 @@ fn load_modules_from_path(path: &Path) -> anyhow::Result<Arc<HashMap<String, Str
     Ok(Arc::new(modules))
 }
 
+fn collect_module_files(
+    dir: &Path,
+    prefix: &str,
+    on_module: &mut dyn FnMut(String, &Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let file_name = item
+            .file_name()
+            .into_string()
+            .map_err(|err| anyhow!("Module {err:?} does not have a valid name"))?;
+
+        // Skips `.git` and other hidden files
+        if file_name.starts_with('.') {
+            continue;
+        }
+
+        let metadata = item.metadata()?;
+
+        if metadata.is_dir() {
+            collect_module_files(&item.path(), &format!("{prefix}{file_name}/"), on_module)?;
+        } else if let Some(module_name) = file_name.strip_suffix(".hebi") {
+            on_module(format!("{prefix}{module_name}"), &item.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Modification times and sizes of the module files, used for detecting changes.
+fn snapshot_path(path: &Path) -> anyhow::Result<HashMap<String, (Option<SystemTime>, u64)>> {
+    let mut snapshot = HashMap::new();
+    collect_module_files(path, "", &mut |module_name, file_path| {
+        let metadata = fs::metadata(file_path)?;
+        snapshot.insert(module_name, (metadata.modified().ok(), metadata.len()));
+        Ok(())
+    })?;
+
+    Ok(snapshot)
+}
+
 pub fn create_module_storage_from_env() -> anyhow::Result<ModuleStorage> {
+    if let Ok(path) = env::var("HEBI_MODULES_PATH") {
+        let storage = ModuleStorage::from_path(PathBuf::from(path))?;
+        storage.watch();
+        return Ok(storage);
+    }
+
     match env::var("HEBI_MODULES_GIT_URL") {
         Ok(git_url) => ModuleStorage::new(&git_url),
         Err(_) => Ok(ModuleStorage::empty()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{collect_module_files, validate_channel_module, ModuleStorage};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn nested_module_names() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("utils/text")).unwrap();
+        fs::create_dir_all(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join("main.hebi"), "").unwrap();
+        fs::write(dir.path().join("utils/text/case.hebi"), "").unwrap();
+        fs::write(dir.path().join(".git/ignored.hebi"), "").unwrap();
+        fs::write(dir.path().join("README.md"), "").unwrap();
+
+        let mut names = Vec::new();
+        collect_module_files(dir.path(), "", &mut |name, _| {
+            names.push(name);
+            Ok(())
+        })
+        .unwrap();
+        names.sort();
+
+        assert_eq!(names, vec!["main", "utils/text/case"]);
+    }
+
+    #[test]
+    fn channel_module_names() {
+        assert!(validate_channel_module("", "x = 1").is_err());
+        assert!(validate_channel_module("../secret", "x = 1").is_err());
+        assert!(validate_channel_module("helpers", "   ").is_err());
+    }
+
+    #[test]
+    fn missing_directory() {
+        assert!(ModuleStorage::from_path("/nonexistent/hebi/modules".into()).is_err());
+    }
+}
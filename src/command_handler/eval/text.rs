@@ -0,0 +1,84 @@
+use super::{limits::charge_native_output, utils::to_hebi_value};
+use crate::command_handler::unping as unping_string;
+use hebi::prelude::*;
+
+pub fn upper(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let input = scope.param::<Str>(0)?;
+    charge_native_output(input.as_str().len())?;
+    Ok(scope.new_string(input.as_str().to_uppercase()))
+}
+
+pub fn lower(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let input = scope.param::<Str>(0)?;
+    charge_native_output(input.as_str().len())?;
+    Ok(scope.new_string(input.as_str().to_lowercase()))
+}
+
+pub fn capitalize(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let input = scope.param::<Str>(0)?;
+    charge_native_output(input.as_str().len())?;
+    Ok(scope.new_string(capitalize_str(input.as_str())))
+}
+
+/// `text.split(text, separator?)`, splits on whitespace when there is no separator.
+pub fn split(scope: Scope<'_>) -> hebi::Result<Value<'_>> {
+    let input = scope.param::<Str>(0)?;
+    let separator = scope.param::<Str>(1).ok();
+
+    let parts = split_str(input.as_str(), separator.as_ref().map(|sep| sep.as_str()));
+    to_hebi_value(&scope, parts.into())
+}
+
+/// Inserts an invisible character into the text, so that mentioning a username doesn't ping them.
+pub fn unping(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let mut input = scope.param::<String>(0)?;
+    unping_string(&mut input);
+
+    charge_native_output(input.len())?;
+    Ok(scope.new_string(input))
+}
+
+fn capitalize_str(input: &str) -> String {
+    let mut chars = input.chars();
+
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn split_str(input: &str, separator: Option<&str>) -> Vec<String> {
+    match separator {
+        Some(separator) if !separator.is_empty() => {
+            input.split(separator).map(str::to_owned).collect()
+        }
+        _ => input.split_whitespace().map(str::to_owned).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{capitalize_str, split_str};
+    use crate::command_handler::unping;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn capitalize() {
+        assert_eq!(capitalize_str("ärge"), "Ärge");
+        assert_eq!(capitalize_str(""), "");
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_str(" a  b c ", None), vec!["a", "b", "c"]);
+        assert_eq!(split_str("a,,b", Some(",")), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn unping_multibyte() {
+        let mut name = "ķēķis".to_owned();
+        unping(&mut name);
+
+        assert_eq!(name, "ķē\u{E0000}ķis");
+    }
+}
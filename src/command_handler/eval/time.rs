@@ -0,0 +1,254 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, FixedOffset, Offset, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use hebi::prelude::*;
+use std::fmt::Display;
+
+pub const DEFAULT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Current Unix timestamp in seconds (with millisecond precision).
+/// A float is used, as timestamps in milliseconds don't fit into an int.
+pub fn now(_: Scope<'_>) -> hebi::Result<f64> {
+    Ok(Utc::now().timestamp_millis() as f64 / 1000.0)
+}
+
+/// `time.format(timestamp, format?, timezone?)`, where the timezone is a name like `Europe/Riga` or an offset like `+02:00`
+pub fn format(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let timestamp = scope.param::<f64>(0)?;
+    let format = param_or(&scope, 1, DEFAULT_FORMAT);
+    let timezone = param_or(&scope, 2, "UTC");
+
+    let output = format_timestamp((timestamp * 1000.0) as i64, &format, &timezone)
+        .map_err(|err| hebi::Error::User(err.into()))?;
+
+    Ok(scope.new_string(output))
+}
+
+/// Formats a duration in seconds as e.g. `1d 2h 5m 3s`.
+pub fn duration(scope: Scope<'_>) -> hebi::Result<Str<'_>> {
+    let seconds = scope.param::<f64>(0)?;
+
+    Ok(scope.new_string(format_duration(seconds as i64)))
+}
+
+/// Parses a duration like `1h 30m` (or `1h30m`) into seconds.
+pub fn parse_duration(scope: Scope<'_>) -> hebi::Result<i32> {
+    let input = scope.param::<String>(0)?;
+
+    let seconds = parse_duration_str(&input).map_err(|err| hebi::Error::User(err.into()))?;
+    i32::try_from(seconds).map_err(hebi::Error::user)
+}
+
+fn param_or(scope: &Scope<'_>, index: usize, default: &str) -> String {
+    scope
+        .param::<String>(index)
+        .unwrap_or_else(|_| default.to_owned())
+}
+
+/// A timezone given either by its IANA name, or as a fixed UTC offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+
+        if let Ok(offset) = parse_offset(input) {
+            return Ok(Self::Fixed(offset));
+        }
+
+        input.parse::<Tz>().map(Self::Named).map_err(|_| {
+            format!("unknown timezone `{input}`, expected a name like Europe/Riga or an offset like +02:00")
+        })
+    }
+
+    /// The time with the offset that the timezone had at that moment
+    fn at(&self, timestamp_millis: i64) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Self::Named(tz) => tz
+                .timestamp_millis_opt(timestamp_millis)
+                .single()
+                .map(|time| time.with_timezone(&time.offset().fix())),
+            Self::Fixed(offset) => offset.timestamp_millis_opt(timestamp_millis).single(),
+        }
+    }
+}
+
+impl Display for Timezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Named(tz) => f.write_str(tz.name()),
+            Self::Fixed(offset) => write!(f, "{offset}"),
+        }
+    }
+}
+
+pub fn format_timestamp(
+    timestamp_millis: i64,
+    format: &str,
+    timezone: &str,
+) -> Result<String, String> {
+    let timezone = Timezone::parse(timezone)?;
+
+    let items: Vec<Item> = StrftimeItems::new(format).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(format!("invalid time format `{format}`"));
+    }
+
+    let time = timezone
+        .at(timestamp_millis)
+        .ok_or_else(|| "timestamp out of range".to_owned())?;
+
+    Ok(time.format_with_items(items.into_iter()).to_string())
+}
+
+/// Parses `UTC` or an offset like `+02:00`.
+fn parse_offset(offset: &str) -> Result<FixedOffset, String> {
+    let offset = offset.trim();
+
+    if offset.eq_ignore_ascii_case("UTC") || offset.eq_ignore_ascii_case("Z") {
+        return Ok(FixedOffset::east_opt(0).unwrap());
+    }
+
+    let invalid = || format!("invalid UTC offset `{offset}`, expected something like +02:00");
+
+    let (sign, rest) = if let Some(rest) = offset.strip_prefix('+') {
+        (1, rest)
+    } else if let Some(rest) = offset.strip_prefix('-') {
+        (-1, rest)
+    } else {
+        return Err(invalid());
+    };
+
+    let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+
+    if minutes >= 60 {
+        return Err(invalid());
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
+
+const DURATION_UNITS: &[(char, i64)] = &[('d', 86400), ('h', 3600), ('m', 60), ('s', 1)];
+
+fn format_duration(seconds: i64) -> String {
+    if seconds == 0 {
+        return "0s".to_owned();
+    }
+
+    let mut parts = Vec::new();
+
+    if seconds < 0 {
+        parts.push("-".to_owned());
+    }
+    // `i64::MIN` has no positive counterpart
+    let mut seconds = seconds.unsigned_abs();
+
+    for &(unit, unit_seconds) in DURATION_UNITS {
+        let unit_seconds = unit_seconds as u64;
+        let amount = seconds / unit_seconds;
+        if amount > 0 {
+            parts.push(format!("{amount}{unit}"));
+            seconds %= unit_seconds;
+        }
+    }
+
+    parts.join(" ").replacen("- ", "-", 1)
+}
+
+fn parse_duration_str(input: &str) -> Result<i64, String> {
+    let mut total = 0i64;
+    let mut number = String::new();
+
+    for c in input.chars().filter(|c| !c.is_whitespace()) {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit_seconds = DURATION_UNITS
+            .iter()
+            .find(|(unit, _)| *unit == c.to_ascii_lowercase())
+            .map(|(_, seconds)| *seconds)
+            .ok_or_else(|| format!("unknown duration unit `{c}`"))?;
+
+        let amount: i64 = number
+            .parse()
+            .map_err(|_| format!("missing amount before `{c}`"))?;
+        number.clear();
+
+        total = amount
+            .checked_mul(unit_seconds)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| "duration is too long".to_owned())?;
+    }
+
+    if !number.is_empty() {
+        return Err(format!("missing unit after `{number}`"));
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, format_timestamp, parse_duration_str, Timezone};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn format_with_offset() {
+        let timestamp = 1_693_000_000_000;
+
+        assert_eq!(
+            format_timestamp(timestamp, "%Y-%m-%d %H:%M", "UTC").unwrap(),
+            "2023-08-25 21:46"
+        );
+        assert_eq!(
+            format_timestamp(timestamp, "%H:%M %z", "+03:00").unwrap(),
+            "00:46 +0300"
+        );
+        assert!(format_timestamp(timestamp, "%Q", "UTC").is_err());
+        assert!(format_timestamp(timestamp, "%H", "Mars/Olympus").is_err());
+    }
+
+    #[test]
+    fn named_timezones_follow_dst() {
+        let summer = 1_693_000_000_000;
+        let winter = 1_700_000_000_000;
+
+        assert_eq!(
+            format_timestamp(summer, "%H:%M %z", "Europe/Riga").unwrap(),
+            "00:46 +0300"
+        );
+        assert_eq!(
+            format_timestamp(winter, "%H:%M %z", "Europe/Riga").unwrap(),
+            "00:13 +0200"
+        );
+        assert_eq!(
+            Timezone::parse(" Europe/Riga ").unwrap().to_string(),
+            "Europe/Riga"
+        );
+        assert_eq!(Timezone::parse("+02:00").unwrap().to_string(), "+02:00");
+        assert!(Timezone::parse("Ö").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(93784), "1d 2h 3m 4s");
+        assert_eq!(format_duration(-3600), "-1h");
+        assert_eq!(format_duration(i64::MIN), "-106751991167300d 15h 30m 8s");
+
+        assert_eq!(parse_duration_str("1h 30m").unwrap(), 5400);
+        assert_eq!(parse_duration_str("2d5s").unwrap(), 172805);
+        assert!(parse_duration_str("10").is_err());
+        assert!(parse_duration_str("5x").is_err());
+    }
+}
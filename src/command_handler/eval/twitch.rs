@@ -0,0 +1,89 @@
+use super::context::HebiContext;
+use crate::{
+    command_handler::twitch_api::{broadcaster_helix_api, moderation::ModerationAction},
+    database::Database,
+    platform::{ChannelIdentifier, Permissions},
+};
+use hebi::prelude::*;
+use tracing::{error, instrument, warn};
+
+/// Function names exposed in the `twitch` module and the moderation actions they map to.
+pub const MODERATION_FUNCTIONS: &[(&str, &str)] = &[
+    ("ban", "ban"),
+    ("unban", "unban"),
+    ("delete", "delete"),
+    ("clear", "clear"),
+    ("shield", "shield"),
+    ("slow", "slow"),
+    ("emote_only", "emoteonly"),
+    ("followers_only", "followersonly"),
+    ("sub_only", "subonly"),
+    ("announce", "announce"),
+];
+
+/// Moderation actions are taken as the broadcaster, and only when a moderator runs the command.
+#[instrument(name = "hebi.twitch.moderate", skip(scope, db, ctx))]
+pub async fn moderate(
+    scope: Scope<'_>,
+    action: &'static str,
+    db: Database,
+    ctx: HebiContext,
+) -> hebi::Result<()> {
+    if ctx.permissions < Permissions::ChannelMod as i32 {
+        return Err(hebi::Error::User(
+            "moderation actions can only be used by channel moderators".into(),
+        ));
+    }
+
+    let mut params = Vec::new();
+
+    let mut i = 0;
+    while let Ok(param) = scope.param::<Value>(i) {
+        params.push(param.to_string());
+        i += 1;
+    }
+
+    let action = match action {
+        // The color is a separate argument instead of being a part of the message
+        "announce" => ModerationAction::Announcement {
+            message: params
+                .first()
+                .cloned()
+                .ok_or_else(|| hebi::Error::User("announcement message not specified".into()))?,
+            color: params.get(1).cloned(),
+        },
+        _ => ModerationAction::from_params(action, params)
+            .map_err(|err| hebi::Error::User(err.into()))?,
+    };
+
+    let channel_id = ctx.channel_id;
+    let channel = db
+        .run(move |db| db.get_channel_by_id(channel_id))
+        .await
+        .map_err(|err| {
+            error!("DB error: {err}");
+            hebi::Error::User("Database error".into())
+        })?
+        .ok_or_else(|| hebi::Error::User("Channel not found".into()))?;
+
+    let broadcaster_id = match channel.get_identifier() {
+        ChannelIdentifier::TwitchChannel((id, _)) => id,
+        _ => {
+            return Err(hebi::Error::User(
+                "moderation actions cannot be used outside of Twitch".into(),
+            ))
+        }
+    };
+
+    let helix_api = broadcaster_helix_api(&db, &broadcaster_id)
+        .await
+        .map_err(|err| hebi::Error::User(err.to_string().into()))?;
+
+    helix_api
+        .moderate(&broadcaster_id, &action)
+        .await
+        .map_err(|err| {
+            warn!("Moderation action failed: {err:?}");
+            hebi::Error::User(err.to_string().into())
+        })
+}
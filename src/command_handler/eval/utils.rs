@@ -1,3 +1,5 @@
+use super::limits::charge_native_output;
+use ::serde::de::DeserializeSeed;
 use hebi::{prelude::*, Result};
 use std::time::Duration;
 
@@ -10,6 +12,7 @@ pub fn format_string(scope: Scope<'_>) -> Result<Str<'_>> {
         i += 1;
     }
 
+    charge_native_output(input.len())?;
     Ok(scope.new_string(input))
 }
 
@@ -23,3 +26,25 @@ pub async fn sleep(scope: Scope<'_>) -> Result<()> {
     tokio::time::sleep(Duration::from_millis(ms as u64)).await;
     Ok(())
 }
+
+/// Converts a JSON value into a Hebi value.
+pub fn to_hebi_value<'a>(scope: &Scope<'a>, value: serde_json::Value) -> Result<Value<'a>> {
+    charge_native_output(json_size(&value))?;
+
+    ValueDeserializer::new(scope.global())
+        .deserialize(value)
+        .map_err(|err| hebi::Error::User(format!("Deserialization error: {err}").into()))
+}
+
+/// Approximate size of the value once it's converted
+fn json_size(value: &serde_json::Value) -> usize {
+    match value {
+        serde_json::Value::String(s) => s.len(),
+        serde_json::Value::Array(values) => values.iter().map(json_size).sum(),
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| key.len() + json_size(value))
+            .sum(),
+        _ => std::mem::size_of::<serde_json::Value>(),
+    }
+}
@@ -71,7 +71,13 @@ pub async fn start_listener(
             tokio::time::sleep(interval).await;
             info!("Fetching new GeoHub leaderboard");
 
-            let links = db.get_geohub_links().expect("DB error");
+            let links = match db.run(|db| db.get_geohub_links()).await {
+                Ok(links) => links,
+                Err(err) => {
+                    error!("Could not get GeoHub links: {err}");
+                    continue;
+                }
+            };
             match client.get_leaderboard(200).await {
                 Ok(new_leaderboard) => {
                     for new_entry in &new_leaderboard.today {
@@ -92,10 +98,17 @@ pub async fn start_listener(
                                 .iter()
                                 .find(|link| link.geohub_name.to_lowercase() == entry_name)
                             {
-                                let channel = db
-                                    .get_channel_by_id(link.channel_id)
-                                    .expect("DB error")
-                                    .expect("Linked to an invalid channel");
+                                let channel_id = link.channel_id;
+                                let channel = match db
+                                    .run(move |db| db.get_channel_by_id(channel_id))
+                                    .await
+                                {
+                                    Ok(channel) => channel.expect("Linked to an invalid channel"),
+                                    Err(err) => {
+                                        error!("Could not get linked channel: {err}");
+                                        continue;
+                                    }
+                                };
 
                                 let message = format!("{} has completed the GeoHub daily challenge with the score of {} points!", new_entry.user_name, new_entry.total_points);
                                 match platform_handler
@@ -0,0 +1,426 @@
+use crate::database::{models::HebiDataScope, Database, DatabaseError};
+use async_trait::async_trait;
+use handlebars::{JsonRender, RenderError};
+use serde_json::Value as Json;
+
+use super::{AsyncHelper, HelperCall};
+
+/// How many keys are listed by `data_get` without a key
+const LIST_LIMIT: i64 = 100;
+
+// The data helpers share the channel data of Hebi, and are ordered like side effects
+// so that reading a value after setting it in the same template returns the new value.
+
+/// Storage of the channel data, which is the database outside of tests.
+#[async_trait]
+pub trait ChannelData: Send + Sync + 'static {
+    async fn get(&self, channel_id: u64, key: &str) -> Result<Option<String>, DatabaseError>;
+
+    async fn set(&self, channel_id: u64, key: &str, value: &str) -> Result<(), DatabaseError>;
+
+    /// Fails with `InvalidValue` if the existing value is not a number
+    async fn increment(
+        &self,
+        channel_id: u64,
+        key: &str,
+        amount: i64,
+    ) -> Result<i64, DatabaseError>;
+
+    async fn keys(&self, channel_id: u64, limit: i64) -> Result<Vec<String>, DatabaseError>;
+}
+
+#[async_trait]
+impl ChannelData for Database {
+    async fn get(&self, channel_id: u64, key: &str) -> Result<Option<String>, DatabaseError> {
+        let key = key.to_owned();
+
+        self.run(move |db| db.get_hebi_data(channel_id, HebiDataScope::Channel, &key))
+            .await
+    }
+
+    async fn set(&self, channel_id: u64, key: &str, value: &str) -> Result<(), DatabaseError> {
+        let key = key.to_owned();
+        let value = value.to_owned();
+
+        self.run(move |db| db.set_hebi_data(channel_id, HebiDataScope::Channel, &key, &value, None))
+            .await
+    }
+
+    async fn increment(
+        &self,
+        channel_id: u64,
+        key: &str,
+        amount: i64,
+    ) -> Result<i64, DatabaseError> {
+        let key = key.to_owned();
+
+        self.run(move |db| {
+            db.increment_hebi_data(
+                channel_id,
+                HebiDataScope::Channel,
+                &key,
+                amount,
+                i64::MIN..=i64::MAX,
+            )
+        })
+        .await
+    }
+
+    async fn keys(&self, channel_id: u64, limit: i64) -> Result<Vec<String>, DatabaseError> {
+        let data = self
+            .run(move |db| db.list_hebi_data(channel_id, Some(HebiDataScope::Channel), "", limit))
+            .await?;
+
+        Ok(data.into_iter().map(|data| data.name).collect())
+    }
+}
+
+/// `data_set key value`
+pub struct DataSetHelper<D = Database> {
+    pub db: D,
+}
+
+#[async_trait]
+impl<D: ChannelData> AsyncHelper for DataSetHelper<D> {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let channel_id = get_channel_id(call)?;
+        let mut params = words(call).into_iter();
+
+        let key = params
+            .next()
+            .ok_or_else(|| RenderError::new("key missing"))?;
+        let value = params
+            .next()
+            .ok_or_else(|| RenderError::new("value missing"))?;
+
+        tracing::info!("Set custom data {}: {}", key, value);
+
+        self.db
+            .set(channel_id, &key, &Json::String(value).to_string())
+            .await
+            .map_err(db_error)?;
+
+        Ok(Json::Null)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
+
+/// `data_get key`, or lists the keys of the channel without a key.
+pub struct DataGetHelper<D = Database> {
+    pub db: D,
+}
+
+#[async_trait]
+impl<D: ChannelData> AsyncHelper for DataGetHelper<D> {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let channel_id = get_channel_id(call)?;
+
+        let response = match words(call).into_iter().next() {
+            Some(key) => self
+                .db
+                .get(channel_id, &key)
+                .await
+                .map_err(db_error)?
+                .map(|value| render_value(&value))
+                .unwrap_or_default(),
+            None => {
+                let keys = self
+                    .db
+                    .keys(channel_id, LIST_LIMIT)
+                    .await
+                    .map_err(db_error)?;
+
+                if keys.is_empty() {
+                    return Err(RenderError::new("No data"));
+                }
+                keys.join(", ")
+            }
+        };
+
+        Ok(Json::String(response))
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
+
+/// `counter_inc key amount?`, returns the new value.
+pub struct CounterIncHelper<D = Database> {
+    pub db: D,
+}
+
+#[async_trait]
+impl<D: ChannelData> AsyncHelper for CounterIncHelper<D> {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let channel_id = get_channel_id(call)?;
+        let mut params = words(call).into_iter();
+
+        let key = params
+            .next()
+            .ok_or_else(|| RenderError::new("counter name missing"))?;
+        let amount = match params.next() {
+            Some(amount) => amount
+                .parse()
+                .map_err(|_| RenderError::new("amount is not an integer"))?,
+            None => 1,
+        };
+
+        let value = self
+            .db
+            .increment(channel_id, &key, amount)
+            .await
+            .map_err(|e| match e {
+                DatabaseError::InvalidValue => RenderError::new(format!("{key} is not a counter")),
+                DatabaseError::OutOfRange => RenderError::new(format!("{key} is out of range")),
+                e => db_error(e),
+            })?;
+
+        Ok(value.into())
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
+
+/// `counter_get key`, counters that were never incremented are 0.
+pub struct CounterGetHelper<D = Database> {
+    pub db: D,
+}
+
+#[async_trait]
+impl<D: ChannelData> AsyncHelper for CounterGetHelper<D> {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let channel_id = get_channel_id(call)?;
+
+        let key = words(call)
+            .into_iter()
+            .next()
+            .ok_or_else(|| RenderError::new("counter name missing"))?;
+
+        let value = self.db.get(channel_id, &key).await.map_err(db_error)?;
+
+        match value {
+            Some(value) => match serde_json::from_str::<Json>(&value) {
+                Ok(Json::Number(number)) => Ok(Json::Number(number)),
+                _ => Err(RenderError::new(format!("{key} is not a counter"))),
+            },
+            None => Ok(0.into()),
+        }
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
+
+fn get_channel_id(call: &HelperCall) -> Result<u64, RenderError> {
+    call.context()
+        .channel_id
+        .ok_or_else(|| RenderError::new("data can only be used in channels"))
+}
+
+fn words(call: &HelperCall) -> Vec<String> {
+    call.params()
+        .iter()
+        .map(|param| param.render())
+        .collect::<Vec<String>>()
+        .join(" ")
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Values set from Hebi can be any JSON, strings are shown without quotes.
+fn render_value(value: &str) -> String {
+    match serde_json::from_str::<Json>(value) {
+        Ok(json) => json.render(),
+        Err(_) => value.to_owned(),
+    }
+}
+
+fn db_error(e: DatabaseError) -> RenderError {
+    RenderError::new(format!("DB Error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        render_value, ChannelData, CounterGetHelper, CounterIncHelper, DataGetHelper, DataSetHelper,
+    };
+    use crate::{
+        command_handler::inquiry_helper::{render_template, Deferred, InquiryContext},
+        database::{models::User, DatabaseError},
+        platform::ChannelIdentifier,
+    };
+    use async_trait::async_trait;
+    use handlebars::Handlebars;
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value as Json};
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    #[derive(Clone, Default)]
+    struct MemoryData(Arc<Mutex<BTreeMap<(u64, String), String>>>);
+
+    #[async_trait]
+    impl ChannelData for MemoryData {
+        async fn get(&self, channel_id: u64, key: &str) -> Result<Option<String>, DatabaseError> {
+            let data = self.0.lock().unwrap();
+            Ok(data.get(&(channel_id, key.to_owned())).cloned())
+        }
+
+        async fn set(&self, channel_id: u64, key: &str, value: &str) -> Result<(), DatabaseError> {
+            let mut data = self.0.lock().unwrap();
+            data.insert((channel_id, key.to_owned()), value.to_owned());
+            Ok(())
+        }
+
+        async fn increment(
+            &self,
+            channel_id: u64,
+            key: &str,
+            amount: i64,
+        ) -> Result<i64, DatabaseError> {
+            let mut data = self.0.lock().unwrap();
+            let value = data
+                .entry((channel_id, key.to_owned()))
+                .or_insert_with(|| "0".to_owned());
+
+            let new_value = value
+                .parse::<i64>()
+                .map_err(|_| DatabaseError::InvalidValue)?
+                + amount;
+            *value = new_value.to_string();
+
+            Ok(new_value)
+        }
+
+        async fn keys(&self, channel_id: u64, limit: i64) -> Result<Vec<String>, DatabaseError> {
+            let data = self.0.lock().unwrap();
+            Ok(data
+                .keys()
+                .filter(|(id, _)| *id == channel_id)
+                .map(|(_, key)| key.clone())
+                .take(limit as usize)
+                .collect())
+        }
+    }
+
+    fn registry(data: &MemoryData) -> Handlebars<'static> {
+        let mut registry = Handlebars::new();
+        registry.register_helper(
+            "data_set",
+            Box::new(Deferred::new(DataSetHelper { db: data.clone() })),
+        );
+        registry.register_helper(
+            "data_get",
+            Box::new(Deferred::new(DataGetHelper { db: data.clone() })),
+        );
+        registry.register_helper(
+            "counter_inc",
+            Box::new(Deferred::new(CounterIncHelper { db: data.clone() })),
+        );
+        registry.register_helper(
+            "counter_get",
+            Box::new(Deferred::new(CounterGetHelper { db: data.clone() })),
+        );
+        registry
+    }
+
+    fn context(channel_id: u64) -> Json {
+        json!(InquiryContext {
+            user: User {
+                id: 1,
+                twitch_id: None,
+                discord_id: None,
+                irc_name: None,
+                local_addr: None,
+                telegram_id: None,
+                matrix_id: None,
+            },
+            arguments: Vec::new(),
+            display_name: "user".to_owned(),
+            channel: ChannelIdentifier::Anonymous,
+            channel_id: Some(channel_id),
+        })
+    }
+
+    #[tokio::test]
+    async fn scoped_to_the_channel() {
+        let data = MemoryData::default();
+        let registry = registry(&data);
+
+        let output = render_template(
+            &registry,
+            "{{data_set \"greeting\" \"hello\"}}{{data_get \"greeting\"}}",
+            &context(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output, "hello");
+
+        let output = render_template(&registry, "[{{data_get \"greeting\"}}]", &context(2))
+            .await
+            .unwrap();
+        assert_eq!(output, "[]");
+    }
+
+    #[tokio::test]
+    async fn counters() {
+        let data = MemoryData::default();
+        let registry = registry(&data);
+
+        let output = render_template(
+            &registry,
+            "{{counter_inc \"deaths\"}} {{counter_inc \"deaths\" 2}} {{counter_get \"deaths\"}}",
+            &context(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(output, "1 3 3");
+
+        let err = render_template(
+            &registry,
+            "{{data_set \"name\" \"forsen\"}}{{counter_inc \"name\"}}",
+            &context(1),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("name is not a counter"));
+    }
+
+    #[tokio::test]
+    async fn lists_keys_of_the_channel() {
+        let data = MemoryData::default();
+        let registry = registry(&data);
+
+        render_template(
+            &registry,
+            "{{data_set \"a\" \"1\"}}{{data_set \"b\" \"2\"}}",
+            &context(1),
+        )
+        .await
+        .unwrap();
+        render_template(&registry, "{{data_set \"c\" \"3\"}}", &context(2))
+            .await
+            .unwrap();
+
+        let output = render_template(&registry, "{{data_get}}", &context(1))
+            .await
+            .unwrap();
+        assert_eq!(output, "a, b");
+    }
+
+    #[test]
+    fn rendered_values() {
+        assert_eq!(render_value("\"hello there\""), "hello there");
+        assert_eq!(render_value("42"), "42");
+    }
+}
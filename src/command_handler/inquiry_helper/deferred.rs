@@ -0,0 +1,412 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output, RenderContext,
+    RenderError, ScopedJson,
+};
+use serde::Serialize;
+use serde_json::Value as Json;
+
+use super::InquiryContext;
+
+/// How many times a template can be rendered while resolving its helpers.
+/// Every level of helpers nested as parameters of other helpers needs a separate pass.
+const MAX_RENDER_PASSES: usize = 8;
+/// Output of calls that haven't been resolved yet, calls depending on it are postponed to the next pass
+const PLACEHOLDER: &str = "\u{E000}pending\u{E000}";
+
+thread_local! {
+    static RENDER_STATE: RefCell<Option<RenderState>> = RefCell::new(None);
+}
+
+/// A helper that does its work asynchronously, outside of rendering.
+///
+/// When the template is rendered, calls to these helpers are collected and executed concurrently,
+/// after which the template is rendered again with their results.
+#[async_trait]
+pub trait AsyncHelper: Send + Sync {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError>;
+
+    /// Helpers with side effects are only executed once the rest of the template has been resolved,
+    /// one at a time in the order they're used in.
+    fn has_side_effects(&self) -> bool {
+        false
+    }
+}
+
+/// The parameters of a helper call, as evaluated during rendering.
+#[derive(Debug, Clone)]
+pub struct HelperCall {
+    params: Vec<HelperParam>,
+    hash: BTreeMap<String, Json>,
+    context: Json,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HelperParam {
+    relative_path: Option<String>,
+    value: Json,
+}
+
+impl HelperCall {
+    fn new(h: &Helper, ctx: &Context) -> Self {
+        Self {
+            params: h
+                .params()
+                .iter()
+                .map(|param| HelperParam {
+                    relative_path: param.relative_path().cloned(),
+                    value: param.value().clone(),
+                })
+                .collect(),
+            hash: h
+                .hash()
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.value().clone()))
+                .collect(),
+            context: ctx.data().clone(),
+        }
+    }
+
+    pub fn param(&self, index: usize) -> Option<&HelperParam> {
+        self.params.get(index)
+    }
+
+    pub fn params(&self) -> &[HelperParam] {
+        &self.params
+    }
+
+    pub fn hash_get(&self, key: &str) -> Option<&Json> {
+        self.hash.get(key)
+    }
+
+    pub fn context(&self) -> InquiryContext {
+        serde_json::from_value(self.context.clone()).expect("Failed to get command context")
+    }
+
+    /// Identifies the call between render passes.
+    fn key(&self, name: &str) -> String {
+        let args = serde_json::to_string(&(&self.params, &self.hash)).unwrap_or_default();
+        format!("{name}{args}")
+    }
+}
+
+impl HelperParam {
+    pub fn value(&self) -> &Json {
+        &self.value
+    }
+
+    pub fn relative_path(&self) -> Option<&String> {
+        self.relative_path.as_ref()
+    }
+
+    pub fn render(&self) -> String {
+        self.value.render()
+    }
+
+    /// The path as written if the parameter is a path, the rendered value otherwise.
+    pub fn path_or_render(&self) -> String {
+        match &self.relative_path {
+            Some(path) => path.to_owned(),
+            None => self.render(),
+        }
+    }
+}
+
+/// Registers an [`AsyncHelper`] as a normal helper, which looks up the result resolved for the call.
+pub struct Deferred(Arc<dyn AsyncHelper>);
+
+impl Deferred {
+    pub fn new(helper: impl AsyncHelper + 'static) -> Self {
+        Self(Arc::new(helper))
+    }
+}
+
+impl HelperDef for Deferred {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let call = HelperCall::new(h, ctx);
+        let key = call.key(h.name());
+
+        RENDER_STATE.with(|state| match state.borrow_mut().as_mut() {
+            Some(state) => state.resolve(key, &self.0, call).map(ScopedJson::Derived),
+            None => Err(RenderError::new(format!(
+                "{} can only be used in commands",
+                h.name()
+            ))),
+        })
+    }
+
+    // Written directly instead of the default implementation, to not escape the output
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let value = self.call_inner(h, r, ctx, rc)?;
+        out.write(&value.as_json().render())?;
+
+        Ok(())
+    }
+}
+
+struct PendingCall {
+    key: String,
+    helper: Arc<dyn AsyncHelper>,
+    call: HelperCall,
+}
+
+#[derive(Default)]
+struct RenderState {
+    results: HashMap<String, Result<Json, String>>,
+    /// How many times each call has been made in the current pass,
+    /// so repeated calls with the same parameters are executed separately
+    occurrences: HashMap<String, usize>,
+    pending: Vec<PendingCall>,
+    /// Side effects are skipped instead of executed
+    dry_run: bool,
+}
+
+impl RenderState {
+    fn resolve(
+        &mut self,
+        key: String,
+        helper: &Arc<dyn AsyncHelper>,
+        call: HelperCall,
+    ) -> Result<Json, RenderError> {
+        if key.contains(PLACEHOLDER) {
+            return Ok(PLACEHOLDER.into());
+        }
+
+        let occurrence = self.occurrences.entry(key.clone()).or_default();
+        *occurrence += 1;
+        let key = format!("{key}#{occurrence}");
+
+        match self.results.get(&key) {
+            Some(Ok(value)) => Ok(value.clone()),
+            Some(Err(desc)) => Err(RenderError::new(desc)),
+            None => {
+                self.pending.push(PendingCall {
+                    key,
+                    helper: helper.clone(),
+                    call,
+                });
+                Ok(PLACEHOLDER.into())
+            }
+        }
+    }
+
+    fn render_pass(
+        &mut self,
+        render: impl FnOnce() -> Result<String, RenderError>,
+    ) -> Result<String, RenderError> {
+        self.occurrences.clear();
+        RENDER_STATE.with(|state| *state.borrow_mut() = Some(mem::take(self)));
+
+        let result = render();
+
+        *self = RENDER_STATE
+            .with(|state| state.borrow_mut().take())
+            .unwrap_or_default();
+        result
+    }
+
+    async fn execute_pending(&mut self) {
+        let (effects, calls): (Vec<_>, Vec<_>) = mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|pending| pending.helper.has_side_effects());
+
+        // Side effects might depend on the results of the other calls, for example in a condition
+        if !calls.is_empty() {
+            let results = join_all(
+                calls
+                    .iter()
+                    .map(|pending| pending.helper.call(&pending.call)),
+            )
+            .await;
+
+            for (pending, result) in calls.into_iter().zip(results) {
+                self.results
+                    .insert(pending.key, result.map_err(|err| err.desc));
+            }
+        } else if self.dry_run {
+            for pending in effects {
+                self.results.insert(pending.key, Ok(Json::Null));
+            }
+        } else {
+            for pending in effects {
+                let result = pending.helper.call(&pending.call).await;
+                let failed = result.is_err();
+
+                self.results
+                    .insert(pending.key, result.map_err(|err| err.desc));
+
+                // Rendering stops at the error, so the effects after it shouldn't happen
+                if failed {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Renders the template, resolving the calls to [`AsyncHelper`]s between render passes.
+pub async fn render_template<T: Serialize>(
+    registry: &Handlebars<'_>,
+    template: &str,
+    data: &T,
+) -> Result<String, RenderError> {
+    render(registry, template, data, false).await
+}
+
+/// Renders the template without executing helpers that have side effects, their output is empty.
+pub async fn render_template_dry<T: Serialize>(
+    registry: &Handlebars<'_>,
+    template: &str,
+    data: &T,
+) -> Result<String, RenderError> {
+    render(registry, template, data, true).await
+}
+
+async fn render<T: Serialize>(
+    registry: &Handlebars<'_>,
+    template: &str,
+    data: &T,
+    dry_run: bool,
+) -> Result<String, RenderError> {
+    let mut state = RenderState {
+        dry_run,
+        ..Default::default()
+    };
+
+    for _ in 0..MAX_RENDER_PASSES {
+        let result = state.render_pass(|| registry.render_template(template, data));
+
+        // Errors can also be caused by placeholders, so they only count when everything is resolved
+        if state.pending.is_empty() {
+            return result;
+        }
+
+        state.execute_pending().await;
+    }
+
+    Err(RenderError::new("too many nested helpers"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_template, render_template_dry, AsyncHelper, Deferred, HelperCall};
+    use async_trait::async_trait;
+    use handlebars::{Handlebars, RenderError};
+    use pretty_assertions::assert_eq;
+    use serde_json::{json, Value as Json};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    };
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Echo {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl AsyncHelper for Echo {
+        async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            Ok(call.param(0).map(|param| param.render()).into())
+        }
+    }
+
+    struct Log(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl AsyncHelper for Log {
+        async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+            let text = call
+                .param(0)
+                .map(|param| param.render())
+                .unwrap_or_default();
+            self.0.lock().unwrap().push(text);
+            Ok(Json::Null)
+        }
+
+        fn has_side_effects(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_concurrently() {
+        let echo = Echo::default();
+        let max_in_flight = echo.max_in_flight.clone();
+
+        let mut registry = Handlebars::new();
+        registry.set_strict_mode(true);
+        registry.register_helper("echo", Box::new(Deferred::new(echo)));
+
+        let output = render_template(
+            &registry,
+            "{{echo \"a\"}} {{echo (echo \"b&c\")}}",
+            &json!({}),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(output, "a b&c");
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn side_effects_run_once_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let mut registry = Handlebars::new();
+        registry.register_helper("echo", Box::new(Deferred::new(Echo::default())));
+        registry.register_helper("log", Box::new(Deferred::new(Log(log.clone()))));
+
+        let template =
+            "{{log \"first\"}}{{#if (echo \"\")}}{{log \"skipped\"}}{{/if}}{{log (echo \"second\")}}{{log \"first\"}}";
+        render_template(&registry, template, &json!({}))
+            .await
+            .unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["first", "second", "first"]);
+    }
+
+    #[tokio::test]
+    async fn dry_run_skips_side_effects() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let mut registry = Handlebars::new();
+        registry.register_helper("echo", Box::new(Deferred::new(Echo::default())));
+        registry.register_helper("log", Box::new(Deferred::new(Log(log.clone()))));
+
+        let output = render_template_dry(&registry, "{{log \"a\"}}{{echo \"b\"}}", &json!({}))
+            .await
+            .unwrap();
+
+        assert_eq!(output, "b");
+        assert!(log.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+
+use handlebars::template::{Parameter, Template, TemplateElement};
+use handlebars::Handlebars;
+use serde::Serialize;
+
+/// A problem in a template that would make it fail when the command is executed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LintError {
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for LintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => {
+                write!(f, "line {line}, column {column}: {}", self.message)
+            }
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Checks the syntax of the template, and that the helpers it calls are registered.
+/// Variables are not checked, as they can come from decorators and blocks.
+pub fn lint_template(registry: &Handlebars, source: &str) -> Vec<LintError> {
+    match Template::compile(source) {
+        Ok(template) => {
+            let mut errors = Vec::new();
+            check_template(registry, &template, None, &mut errors);
+            errors
+        }
+        Err(err) => vec![LintError {
+            line: err.pos().map(|(line, _)| line),
+            column: err.pos().map(|(_, column)| column),
+            message: err.reason().to_string(),
+        }],
+    }
+}
+
+fn check_template(
+    registry: &Handlebars,
+    template: &Template,
+    parent_position: Option<(usize, usize)>,
+    errors: &mut Vec<LintError>,
+) {
+    for (i, element) in template.elements.iter().enumerate() {
+        let position = template
+            .mapping
+            .get(i)
+            .map(|mapping| (mapping.0, mapping.1))
+            .or(parent_position);
+
+        check_element(registry, element, position, false, errors);
+    }
+}
+
+fn check_element(
+    registry: &Handlebars,
+    element: &TemplateElement,
+    position: Option<(usize, usize)>,
+    is_subexpression: bool,
+    errors: &mut Vec<LintError>,
+) {
+    match element {
+        TemplateElement::Expression(helper)
+        | TemplateElement::HtmlExpression(helper)
+        | TemplateElement::HelperBlock(helper) => {
+            // `{{name}}` without parameters can also be a variable
+            let is_helper_call = is_subexpression
+                || helper.block
+                || !helper.params.is_empty()
+                || !helper.hash.is_empty();
+
+            if let Parameter::Name(name) = &helper.name {
+                if is_helper_call && !registry.has_helper(name) {
+                    errors.push(LintError {
+                        line: position.map(|(line, _)| line),
+                        column: position.map(|(_, column)| column),
+                        message: format!("helper {name} does not exist"),
+                    });
+                }
+            }
+
+            for param in helper.params.iter().chain(helper.hash.values()) {
+                if let Parameter::Subexpression(subexpression) = param {
+                    check_element(registry, &subexpression.element, position, true, errors);
+                }
+            }
+
+            for template in helper.template.iter().chain(helper.inverse.iter()) {
+                check_template(registry, template, position, errors);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lint_template;
+    use handlebars::Handlebars;
+    use pretty_assertions::assert_eq;
+
+    fn registry() -> Handlebars<'static> {
+        let mut registry = Handlebars::new();
+        registry.register_helper(
+            "args",
+            Box::new(crate::command_handler::inquiry_helper::args_helper),
+        );
+        registry
+    }
+
+    #[test]
+    fn valid_template() {
+        assert_eq!(
+            lint_template(
+                &registry(),
+                "hi {{username}} {{#if (args 0)}}{{args}}{{/if}}"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn unknown_helpers() {
+        let errors = lint_template(
+            &registry(),
+            "first line\n{{#if (args 0)}}{{wether (args)}}{{/if}} {{translate (args)}}",
+        );
+
+        let messages = errors
+            .iter()
+            .map(|error| error.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "helper wether does not exist",
+                "helper translate does not exist"
+            ]
+        );
+        assert!(errors.iter().all(|error| error.line == Some(2)));
+    }
+
+    #[test]
+    fn syntax_error() {
+        let errors = lint_template(&registry(), "{{#if (args 0)}} unclosed");
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].line.is_some());
+    }
+}
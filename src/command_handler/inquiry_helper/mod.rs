@@ -1,36 +1,41 @@
-mod forsencode;
+mod data;
+mod deferred;
+mod lint;
+mod twitch_moderation;
 mod twitch_timeout;
 
-use std::env;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
-use dashmap::DashMap;
+use async_trait::async_trait;
 use handlebars::{
     Context, Decorator, Handlebars, Helper, HelperDef, HelperResult, JsonRender, Output,
     RenderContext, RenderError, ScopedJson,
 };
 use rand::{thread_rng, Rng};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as Json;
-use tokio::runtime::Handle;
 use tokio::sync::RwLock;
 use tokio::time::sleep;
 use twitch_irc::login::{LoginCredentials, RefreshingLoginCredentials};
 
-use crate::database::{models::User, Database};
-use crate::platform::{ChannelIdentifier, UserIdentifier};
+use crate::database::{
+    models::{ProfileKey, User},
+    Database,
+};
+use crate::platform::ChannelIdentifier;
 
-use super::finnhub_api::FinnhubApi;
-use super::lastfm_api::LastFMApi;
-use super::lingva_api::LingvaApi;
+use super::egress::EgressPolicy;
 use super::platform_handler::PlatformHandler;
+use super::services::{forsencode, ServiceError, Services};
 use super::twitch_api::helix::HelixApi;
-use super::twitch_api::{get_client_id, get_client_secret, TwitchApi};
-use super::ukraine_alert::UkraineAlertClient;
-use super::{owm_api::OwmApi, spotify_api::SpotifyApi};
+use super::twitch_api::{get_client_id, get_client_secret};
 
+pub use data::{CounterGetHelper, CounterIncHelper, DataGetHelper, DataSetHelper};
+pub use deferred::{render_template, render_template_dry, AsyncHelper, Deferred, HelperCall};
+pub use lint::{lint_template, LintError};
+pub use twitch_moderation::TwitchModerationHelper;
 pub use twitch_timeout::TwitchTimeoutHelper;
 
 #[derive(Serialize, Deserialize)]
@@ -39,65 +44,32 @@ pub struct InquiryContext {
     pub arguments: Vec<String>,
     pub display_name: String,
     pub channel: ChannelIdentifier,
+    pub channel_id: Option<u64>,
 }
 
 pub struct TwitchUserHelper {
-    pub twitch_api: TwitchApi<RefreshingLoginCredentials<Database>>,
+    pub services: Services,
 }
 
-impl HelperDef for TwitchUserHelper {
-    fn call_inner<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'reg, 'rc>,
-        _: &'reg Handlebars<'reg>,
-        ctx: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-    ) -> Result<handlebars::ScopedJson<'reg, 'rc>, RenderError> {
-        tracing::info!("{:?}", h.param(0));
+#[async_trait]
+impl AsyncHelper for TwitchUserHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        tracing::info!("{:?}", call.param(0));
 
-        let param = h
+        let user = call
             .param(0)
-            .ok_or_else(|| RenderError::new("user not specified"))?;
-
-        let user = match param.relative_path() {
-            Some(path) => path.to_owned(),
-            None => param.render(),
-        };
-
-        let mut logins = Vec::new();
-        let mut ids = Vec::new();
-
-        match user.is_empty() {
-            false => logins.push(user),
-            true => {
-                let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-                    .expect("Failed to get command context");
-
-                match context.user.twitch_id {
-                    Some(twitch_id) => ids.push(twitch_id),
-                    None => return Err(RenderError::new("user not specified")),
-                }
-            }
-        }
-
-        let runtime = tokio::runtime::Handle::current();
-
-        let twitch_api = self.twitch_api.clone();
-
-        let users_response = runtime
-            .block_on(twitch_api.helix_api.get_users(
-                Some(&logins.iter().map(|u| u.as_str()).collect::<Vec<&str>>()),
-                Some(&ids.iter().map(|u| u.as_str()).collect::<Vec<&str>>()),
-            ))
-            .map_err(|e| RenderError::new(e.to_string()))?;
-
-        let user = users_response
-            .first()
-            .ok_or_else(|| RenderError::new("user not found"))?;
-
-        tracing::debug!("Twitch user: {:?}", user);
-
-        Ok(ScopedJson::Derived(serde_json::to_value(user).unwrap()))
+            .ok_or_else(|| RenderError::new("user not specified"))?
+            .path_or_render();
+
+        let context = call.context();
+
+        self.services
+            .twitch_user(
+                Some(user.as_str()).filter(|user| !user.is_empty()),
+                context.user.twitch_id.as_deref(),
+            )
+            .await
+            .map_err(service_error)
     }
 }
 
@@ -133,377 +105,222 @@ pub fn args_helper(
     Ok(())
 }
 
-pub fn song_helper(
-    h: &Helper,
-    hb: &Handlebars,
-    ctx: &Context,
-    _: &mut RenderContext,
-    out: &mut dyn Output,
-) -> HelperResult {
-    let mut params = h
-        .params()
-        .iter()
-        .map(|param| param.render())
-        .collect::<Vec<String>>()
-        .join(" ");
+pub struct SongHelper {
+    pub services: Services,
+}
 
-    tracing::info!("Params: {}", params);
+#[async_trait]
+impl AsyncHelper for SongHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let context = call.context();
 
-    if !params.is_empty() {
-        params = format!("\"{}\"", params);
-    }
+        let params = call
+            .params()
+            .iter()
+            .map(|param| param.render())
+            .collect::<Vec<String>>()
+            .join(" ");
 
-    // Curly braces are doubled for escaping
-    match hb.render_template_with_context(&format!("{{{{ lastfm {} }}}}", params), ctx) {
-        Ok(lastfm_result) => {
-            out.write(&lastfm_result)?;
-        }
-        Err(e) => {
-            tracing::info!("Last.FM Error: {}", e);
-
-            match hb.render_template_with_context(&format!("{{{{ spotify {} }}}}", params), ctx) {
-                Ok(spotify_result) => out.write(&spotify_result)?,
-                Err(e) => {
-                    tracing::info!("Spotify Error: {}", e);
-
-                    return Err(RenderError::new(format!(
-                        "No music source configured! Go to {}/profile to connect an account.",
-                        env::var("BASE_URL").expect("BASE_URL missing")
-                    )));
-                }
-            }
-        }
-    }
+        tracing::info!("Params: {}", params);
 
-    Ok(())
+        let user_id = resolve_user(
+            &self.services,
+            Some(params.as_str()).filter(|params| !params.is_empty()),
+            &context,
+        )
+        .await?;
+
+        self.services
+            .now_playing(user_id)
+            .await
+            .map(Json::String)
+            .map_err(service_error)
+    }
 }
 
 pub struct WeatherHelper {
-    pub db: Database,
-    pub api: OwmApi,
+    pub services: Services,
 }
 
-impl HelperDef for WeatherHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'reg, 'rc>,
-        _: &'reg Handlebars<'reg>,
-        ctx: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
-
-        let runtime = tokio::runtime::Handle::current();
-
-        let place = match h.params().len() {
-            0 => self
-                .db
-                .get_location(context.user.id)
-                .map_err(|e| RenderError::new(format!("DB Error: {}", e)))?
-                .ok_or_else(|| RenderError::new("location not set"))?,
-            _ => {
-                tracing::trace!("Helper params: {:?}", h.params());
+#[async_trait]
+impl AsyncHelper for WeatherHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let context = call.context();
 
-                h.params()
-                    .iter()
-                    .map(|item| item.render())
-                    .collect::<Vec<String>>()
-                    .join(" ")
+        let place = match call.params().len() {
+            0 => None,
+            _ => {
+                tracing::trace!("Helper params: {:?}", call.params());
+
+                Some(
+                    call.params()
+                        .iter()
+                        .map(|item| item.render())
+                        .collect::<Vec<String>>()
+                        .join(" "),
+                )
             }
         };
 
-        tracing::info!("Querying weather for {}", place);
-
-        let api = self.api.clone();
-
-        // All of this is needed to call async apis from a blocking function
-        let weather = runtime
-            .block_on(api.get_current(&place))
-            .map_err(|e| RenderError::new(e.to_string()))?;
-
-        out.write(&format!(
-            "{}, {}: {}°C",
-            weather.name,
-            weather.sys.country.unwrap_or_default(),
-            weather.main.temp
-        ))?;
-
-        Ok(())
+        self.services
+            .weather(context.user.id, place.as_deref())
+            .await
+            .map(Json::String)
+            .map_err(service_error)
     }
 }
 
-fn get_spotify_api(db: Database, ctx: &Context, h: &Helper) -> Result<SpotifyApi, RenderError> {
-    let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-        .expect("Failed to get command context");
-
-    let user_id = match h.param(0) {
-        Some(param) => {
-            tracing::info!("Spotify param: {:?}", param);
-            let user_identifier = UserIdentifier::from_string(&param.render())
-                .map_err(|_| RenderError::new("invalid user"))?;
-
-            db.get_user(&user_identifier)
-                .expect("DB Error")
-                .ok_or_else(|| RenderError::new("invalid user"))?
-                .id
-        }
-        None => context.user.id,
-    };
-
-    tracing::info!("Looking for spotify token for user ID {}", user_id);
-
-    let access_token = db
-        .get_spotify_access_token(user_id)
-        .map_err(|e| RenderError::new(format!("DB Error: {}", e)))?
-        .ok_or_else(|| {
-            RenderError::new(format!(
-                "Not configured for user! You can set up Spotify by going to {}/profile",
-                env::var("BASE_URL").unwrap()
-            ))
-        })?;
-
-    Ok(SpotifyApi::new(&access_token))
+/// `time format?`, the current time in the timezone of the user.
+pub struct TimeHelper {
+    pub services: Services,
 }
 
-#[derive(Clone)]
-pub struct SpotifyHelper {
-    pub db: Database,
-}
+#[async_trait]
+impl AsyncHelper for TimeHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let context = call.context();
+        let format = call.param(0).map(|param| param.render());
 
-impl HelperDef for SpotifyHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'reg, 'rc>,
-        _: &'reg Handlebars<'reg>,
-        ctx: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let spotify_api = get_spotify_api(self.db.clone(), ctx, h)?;
-
-        let runtime = tokio::runtime::Handle::current();
-
-        match runtime
-            .block_on(spotify_api.get_current_song())
-            .map_err(|e| RenderError::new(format!("Spotify API Error: {e:#}")))?
-        {
-            Some(playback) => {
-                let position = playback.progress_ms / 1000;
-                let position = format!("{}:{:02}", position / 60, position % 60);
-
-                let length = playback.item.duration_ms / 1000;
-                let length = format!("{}:{:02}", length / 60, length % 60);
-
-                let artist = playback
-                    .item
-                    .artists
-                    .iter()
-                    .map(|artist| artist.name.as_str())
-                    .collect::<Vec<&str>>()
-                    .join(" ");
-
-                out.write(&format!(
-                    "{} - {} [{}/{}]",
-                    artist, playback.item.name, position, length
-                ))
-                .expect("Failed to write");
-            }
-            None => out.write("No song is currently playing")?,
-        };
-
-        Ok(())
+        self.services
+            .current_time(context.user.id, format.as_deref())
+            .await
+            .map(Json::String)
+            .map_err(service_error)
     }
 }
 
-#[derive(Clone)]
-pub struct SpotifyPlaylistHelper {
-    pub db: Database,
+/// `profile key user?`, values that the user set as private are only shown to themselves.
+pub struct ProfileHelper {
+    pub services: Services,
 }
 
-impl HelperDef for SpotifyPlaylistHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'reg, 'rc>,
-        _: &'reg Handlebars<'reg>,
-        ctx: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let spotify_api = get_spotify_api(self.db.clone(), ctx, h)?;
+#[async_trait]
+impl AsyncHelper for ProfileHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let context = call.context();
 
-        let runtime = tokio::runtime::Handle::current();
+        let raw_key = call
+            .param(0)
+            .map(|param| param.render())
+            .ok_or_else(|| RenderError::new("key missing"))?;
+        let key = ProfileKey::from_str(&raw_key)
+            .map_err(|_| RenderError::new(format!("unknown key {raw_key}")))?;
 
-        match runtime
-            .block_on(spotify_api.get_current_song())
-            .map_err(|e| RenderError::new(format!("Spotify API Error: {e:#}")))?
-        {
-            Some(playback) => {
-                let response = match playback.context {
-                    Some(context) => context.external_urls.spotify,
-                    None => "not currently listening to a playlist".to_string(),
-                };
+        let param = call.param(1).map(|param| param.render());
+        let user_id = resolve_user(&self.services, param.as_deref(), &context).await?;
 
-                out.write(&response).unwrap();
-            }
-            None => out.write("No song is currently playing")?,
-        };
+        let value = self
+            .services
+            .profile_value(user_id, key, context.user.id)
+            .await
+            .map_err(service_error)?;
 
-        Ok(())
+        Ok(value.map(|value| value.value).unwrap_or_default().into())
     }
 }
 
-#[derive(Clone)]
-pub struct SpotifyLastHelper {
-    pub db: Database,
+/// The music helpers, which take an optional user as the parameter.
+#[derive(Clone, Copy)]
+pub enum MusicSource {
+    LastFM,
+    Spotify,
+    SpotifyPlaylist,
+    SpotifyLastSong,
 }
 
-impl HelperDef for SpotifyLastHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'reg, 'rc>,
-        _: &'reg Handlebars<'reg>,
-        ctx: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let spotify_api = get_spotify_api(self.db.clone(), ctx, h)?;
+pub struct MusicHelper {
+    pub services: Services,
+    pub source: MusicSource,
+}
 
-        let runtime = tokio::runtime::Handle::current();
+#[async_trait]
+impl AsyncHelper for MusicHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let context = call.context();
 
-        let last_played = runtime
-            .block_on(spotify_api.get_recently_played())
-            .map_err(|e| RenderError::new(format!("Spotify API Error: {}", e)))?;
+        let param = call.param(0).map(|param| param.render());
+        let user_id = resolve_user(&self.services, param.as_deref(), &context).await?;
 
-        out.write(&last_played)?;
+        let output = match self.source {
+            MusicSource::LastFM => self.services.lastfm_now_playing(user_id).await,
+            MusicSource::Spotify => self.services.spotify_now_playing(user_id).await,
+            MusicSource::SpotifyPlaylist => self.services.spotify_playlist(user_id).await,
+            MusicSource::SpotifyLastSong => self.services.spotify_last_played(user_id).await,
+        };
 
-        Ok(())
+        output.map(Json::String).map_err(service_error)
     }
 }
 
-#[derive(Clone)]
-pub struct LastFMHelper {
-    pub db: Database,
-    pub lastfm_api: LastFMApi,
+async fn resolve_user(
+    services: &Services,
+    identifier: Option<&str>,
+    context: &InquiryContext,
+) -> Result<u64, RenderError> {
+    services
+        .resolve_user(identifier, context.user.id)
+        .await
+        .map_err(service_error)
 }
 
-impl HelperDef for LastFMHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper<'reg, 'rc>,
-        _: &'reg Handlebars<'reg>,
-        ctx: &'rc Context,
-        _: &mut RenderContext<'reg, 'rc>,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
-
-        let runtime = tokio::runtime::Handle::current();
-
-        let user_id = match h.param(0) {
-            Some(param) => {
-                tracing::info!("Last.FM param: {:?}", param);
-                let user_identifier = UserIdentifier::from_string(&param.render())
-                    .map_err(|_| RenderError::new("invalid user"))?;
-
-                self.db
-                    .get_user(&user_identifier)
-                    .expect("DB Error")
-                    .ok_or_else(|| RenderError::new("invalid user"))?
-                    .id
-            }
-            None => context.user.id,
-        };
+fn service_error(e: ServiceError) -> RenderError {
+    RenderError::new(e.to_string())
+}
 
-        let username = self
-            .db
-            .get_lastfm_name(user_id)
-            .expect("DB Error")
-            .ok_or_else(|| RenderError::new("last.fm username not set!"))?;
+pub struct RandomHelper;
 
-        let lastfm_api = self.lastfm_api.clone();
+// Resolved once per call, so the choice stays the same between render passes
+#[async_trait]
+impl AsyncHelper for RandomHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let len = call.params().len();
 
-        let response = runtime
-            .block_on(lastfm_api.get_recent_tracks(&username))
-            .map_err(|e| RenderError::new(format!("Last.FM Error: {}", e)))?;
+        if len != 0 {
+            let mut rng = thread_rng();
 
-        out.write(&match response.recenttracks.track.iter().find(|track| {
-            if let Some(attr) = &track.attr {
-                attr.nowplaying == "true"
-            } else {
-                false
-            }
-        }) {
-            Some(current_track) => {
-                format!("{} - {}", current_track.artist.text, current_track.name)
-            }
-            None => "No song is currently playing".to_string(),
-        })?;
+            let param = call.params().get(rng.gen_range(0..len)).expect("RNG Error");
 
-        Ok(())
+            Ok(Json::String(param.render()))
+        } else {
+            Err(RenderError::new("missing items to choose from"))
+        }
     }
 }
 
-pub fn random_helper(
-    h: &Helper,
-    _: &Handlebars,
-    _: &Context,
-    _: &mut RenderContext,
-    out: &mut dyn Output,
-) -> HelperResult {
-    let len = h.params().len();
+pub struct SleepHelper;
 
-    if len != 0 {
-        let mut rng = thread_rng();
+#[async_trait]
+impl AsyncHelper for SleepHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        match call.param(0) {
+            Some(duration) => {
+                sleep(Duration::from_secs(
+                    duration.value().as_u64().expect("Invalid duration"),
+                ))
+                .await;
 
-        let param = h.params().get(rng.gen_range(0..len)).expect("RNG Error");
+                Ok(Json::Null)
+            }
+            None => Err(RenderError::new("sleep error: no duration specified")),
+        }
+    }
 
-        out.write(param.value().render().as_ref())?;
-        Ok(())
-    } else {
-        Err(RenderError::new("missing items to choose from"))
+    fn has_side_effects(&self) -> bool {
+        true
     }
 }
 
-pub fn sleep_helper(
-    h: &Helper,
-    _: &Handlebars,
-    _: &Context,
-    _: &mut RenderContext,
-    _: &mut dyn Output,
-) -> HelperResult {
-    match h.params().get(0) {
-        Some(duration) => {
-            let runtime = tokio::runtime::Handle::current();
-
-            runtime.block_on(sleep(Duration::from_secs(
-                duration.value().as_u64().expect("Invalid duration"),
-            )));
-
-            Ok(())
-        }
-        None => Err(RenderError::new("sleep error: no duration specified")),
-    }
+pub struct TranslateHelper {
+    pub services: Services,
 }
 
-impl HelperDef for LingvaApi {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        _: &Context,
-        _: &mut RenderContext,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let raw_params = h
+#[async_trait]
+impl AsyncHelper for TranslateHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let raw_params = call
             .params()
             .iter()
-            .map(|param| match param.relative_path() {
-                Some(path) => path.to_owned(),
-                None => param.render(),
-            })
+            .map(|param| param.path_or_render())
             .collect::<Vec<String>>()
             .join(" ");
 
@@ -531,127 +348,81 @@ impl HelperDef for LingvaApi {
 
         let text = params.join(" ");
 
-        tracing::info!("Translating text {}", text);
-
-        let rt = Handle::current();
-
-        match rt.block_on(self.translate(&source, &target, &text)) {
-            Ok(translation) => out.write(&translation)?,
-            Err(e) => out.write(&format!("error translating: {}", e))?,
-        }
-
-        Ok(())
+        Ok(Json::String(
+            match self.services.translate(&text, &source, &target).await {
+                Ok(translation) => translation,
+                Err(e) => format!("error translating: {}", e),
+            },
+        ))
     }
 }
 
-impl HelperDef for FinnhubApi {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        _: &Context,
-        _: &mut RenderContext,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let param = h
-            .param(0)
-            .ok_or_else(|| RenderError::new("symbol not specified!"))?;
-
-        let symbol = match param.relative_path() {
-            Some(path) => path.to_owned(),
-            None => param.render(),
-        };
-
-        let rt = Handle::current();
-
-        match rt.block_on(self.quote(&symbol)) {
-            Ok(quote) => {
-                out.write(&format!(
-                    "{} ({})",
-                    quote.current_price,
-                    quote.percent_change.unwrap_or(0.0)
-                ))?;
+pub struct StockHelper {
+    pub services: Services,
+}
 
-                Ok(())
-            }
-            Err(e) => Err(RenderError::new(e.to_string())),
-        }
+#[async_trait]
+impl AsyncHelper for StockHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let symbol = call
+            .param(0)
+            .ok_or_else(|| RenderError::new("symbol not specified!"))?
+            .path_or_render();
+
+        self.services
+            .stock_quote(&symbol)
+            .await
+            .map(Json::String)
+            .map_err(service_error)
     }
 }
 
-impl HelperDef for UkraineAlertClient {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        _: &Helper,
-        _: &Handlebars,
-        _: &Context,
-        _: &mut RenderContext,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let rt = Handle::current();
-
-        match rt.block_on(self.get_alerts()) {
-            Ok(regions) => {
-                let text = regions
-                    .into_iter()
-                    .map(|region| {
-                        let name = region.region_name;
-                        let alerts = region
-                            .active_alerts
-                            .iter()
-                            .map(|alert| alert.r#type.as_ref())
-                            .collect::<Vec<_>>()
-                            .join(",");
-
-                        format!("{name}: {alerts}")
-                    })
-                    .collect::<Vec<_>>()
-                    .join(";");
-
-                out.write(&text)?;
-                Ok(())
-            }
-            Err(e) => Err(RenderError::new(e.to_string())),
-        }
+pub struct UkraineAlertsHelper {
+    pub services: Services,
+}
+
+#[async_trait]
+impl AsyncHelper for UkraineAlertsHelper {
+    async fn call(&self, _: &HelperCall) -> Result<Json, RenderError> {
+        self.services
+            .ukraine_alerts()
+            .await
+            .map(Json::String)
+            .map_err(service_error)
     }
 }
 
 pub struct HttpHelper {
-    client: Client,
+    egress: EgressPolicy,
 }
 
 impl HttpHelper {
-    pub fn init() -> Self {
-        Self {
-            client: Client::new(),
-        }
+    pub fn init(egress: EgressPolicy) -> Self {
+        Self { egress }
     }
 }
 
-impl HelperDef for HttpHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        _: &Context,
-        _: &mut RenderContext,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let url = h
+#[async_trait]
+impl AsyncHelper for HttpHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let context = call.context();
+
+        let url = call
             .params()
             .iter()
-            .map(|param| match param.relative_path() {
-                Some(path) => path.to_owned(),
-                None => param.render(),
-            })
+            .map(|param| param.path_or_render())
             .collect::<Vec<String>>()
             .join("");
 
         tracing::info!("Making a request to: {}", url);
 
-        let rt = Handle::current();
+        let (url, client) = self
+            .egress
+            .check(context.channel_id, &url)
+            .await
+            .map_err(|e| RenderError::new(e.to_string()))?;
 
-        let response = rt.block_on(self.client.get(url).send());
+        let response = client.get(url).send().await;
 
         match response {
             Ok(response) => {
@@ -667,13 +438,12 @@ impl HelperDef for HttpHelper {
                                 .iter()
                                 .any(|t| content_type.starts_with(t))
                             {
-                                let text = rt
-                                    .block_on(response.text())
+                                let text = response
+                                    .text()
+                                    .await
                                     .unwrap_or_else(|_| "<empty text>".to_owned());
 
-                                out.write(&text)?;
-
-                                Ok(())
+                                Ok(Json::String(text))
                             } else {
                                 Err(RenderError::new("Disallowed content type!"))
                             }
@@ -681,9 +451,7 @@ impl HelperDef for HttpHelper {
                         None => Err(RenderError::new("server did not return content type!")),
                     }
                 } else {
-                    out.write(&format!("HTTP status: {}", response.status()))?;
-
-                    Ok(())
+                    Ok(Json::String(format!("HTTP status: {}", response.status())))
                 }
             }
             Err(e) => Err(RenderError::new(e.to_string())),
@@ -755,155 +523,36 @@ pub fn trim_matches_helper(
     Ok(())
 }
 
-pub struct SetTempData {
-    pub data: Arc<DashMap<String, String>>,
-}
-
-impl HelperDef for SetTempData {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        ctx: &Context,
-        _: &mut RenderContext,
-        _: &mut dyn Output,
-    ) -> HelperResult {
-        let raw_params = h
-            .params()
-            .iter()
-            .map(|param| param.value().render())
-            .collect::<Vec<String>>()
-            .join(" ");
-
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
-
-        let mut params = raw_params.split_whitespace();
-
-        let key = format!(
-            "{}-{}-{}",
-            context.channel.get_platform_name().unwrap_or_default(),
-            context
-                .channel
-                .get_channel()
-                .unwrap_or(&context.display_name),
-            params
-                .next()
-                .ok_or_else(|| RenderError::new("key missing"))?
-        );
-
-        let value = params
-            .next()
-            .ok_or_else(|| RenderError::new("value missing"))?;
-
-        tracing::info!("Set custom data {}: {}", key, value);
-
-        self.data.insert(key, value.to_string());
-
-        Ok(())
-    }
-}
-
-pub struct GetTempData {
-    pub data: Arc<DashMap<String, String>>,
-}
-
-impl HelperDef for GetTempData {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        ctx: &Context,
-        _: &mut RenderContext,
-        out: &mut dyn Output,
-    ) -> HelperResult {
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
-
-        let raw_params = h
-            .params()
-            .iter()
-            .map(|param| param.value().render())
-            .collect::<Vec<String>>()
-            .join(" ");
-
-        let mut params = raw_params.split_whitespace();
-
-        let response = match params.next() {
-            Some(key) => {
-                let key = format!(
-                    "{}-{}-{}",
-                    context.channel.get_platform_name().unwrap_or_default(),
-                    context
-                        .channel
-                        .get_channel()
-                        .unwrap_or(&context.display_name),
-                    key,
-                );
-
-                match self.data.get(&key) {
-                    Some(value) => value.value().to_string(),
-                    None => String::new(),
-                }
-            }
-            None => {
-                let keys = self
-                    .data
-                    .iter()
-                    .map(|e| e.key().as_str().to_string())
-                    .collect::<Vec<String>>();
-
-                if keys.is_empty() {
-                    return Err(RenderError::new("No data"));
-                }
-                keys.join(", ")
-            }
-        };
-
-        out.write(&response)?;
-
-        Ok(())
-    }
-}
-
 pub struct SayHelper {
     pub platform_handler: Arc<RwLock<PlatformHandler>>,
 }
 
-impl HelperDef for SayHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        ctx: &Context,
-        _: &mut RenderContext,
-        _: &mut dyn Output,
-    ) -> HelperResult {
-        let params = h
+#[async_trait]
+impl AsyncHelper for SayHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let params = call
             .params()
             .iter()
-            .map(|param| param.value().render())
+            .map(|param| param.render())
             .collect::<Vec<String>>()
             .join(" ");
 
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
+        let context = call.context();
 
-        let runtime = tokio::runtime::Handle::current();
+        let platform_handler_guard = self.platform_handler.read().await;
 
-        let platform_handler = self.platform_handler.clone();
-        runtime.spawn(async move {
-            let platform_handler_guard = platform_handler.read().await;
+        if let Err(e) = platform_handler_guard
+            .send_to_channel(context.channel, params)
+            .await
+        {
+            tracing::warn!("Failed sending message from inqury context: {}", e);
+        }
 
-            if let Err(e) = platform_handler_guard
-                .send_to_channel(context.channel, params)
-                .await
-            {
-                tracing::warn!("Failed sending message from inqury context: {}", e);
-            }
-        });
+        Ok(Json::Null)
+    }
 
-        Ok(())
+    fn has_side_effects(&self) -> bool {
+        true
     }
 }
 
@@ -936,26 +585,19 @@ pub struct CommercialHelper {
     pub db: Database,
 }
 
-impl HelperDef for CommercialHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        ctx: &Context,
-        _: &mut RenderContext,
-        _: &mut dyn Output,
-    ) -> HelperResult {
-        let duration = h
+#[async_trait]
+impl AsyncHelper for CommercialHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let duration = call
             .param(0)
-            .map(|v| v.value().render())
+            .map(|v| v.render())
             .ok_or_else(|| RenderError::new("commercial duration not specified"))?;
 
         let length: i32 = duration
             .parse()
             .map_err(|_| RenderError::new("duration is not an integer"))?;
 
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
+        let context = call.context();
 
         let broadcaster_id = match context.channel {
             ChannelIdentifier::TwitchChannel((id, _)) => id,
@@ -966,8 +608,6 @@ impl HelperDef for CommercialHelper {
             }
         };
 
-        let runtime = tokio::runtime::Handle::current();
-
         let credentials = self.db.make_twitch_credentials(broadcaster_id);
         let refreshing_credentials = RefreshingLoginCredentials::init(
             get_client_id().unwrap(),
@@ -975,20 +615,23 @@ impl HelperDef for CommercialHelper {
             credentials,
         );
 
-        runtime
-            .block_on(refreshing_credentials.get_credentials())
+        refreshing_credentials
+            .get_credentials()
+            .await
             .map_err(|_| RenderError::new("streamer is not authorized"))?;
 
-        let helix_api = runtime.block_on(HelixApi::with_credentials(refreshing_credentials));
+        let helix_api = HelixApi::with_credentials(refreshing_credentials).await;
 
-        runtime
-            .block_on(helix_api.start_commercial(length))
-            .map_err(|e| {
-                tracing::warn!("{:?}", e);
-                RenderError::new("Failed to run commercial")
-            })?;
+        helix_api.start_commercial(length).await.map_err(|e| {
+            tracing::warn!("{:?}", e);
+            RenderError::new("Failed to run commercial")
+        })?;
 
-        Ok(())
+        Ok(Json::Null)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
     }
 }
 
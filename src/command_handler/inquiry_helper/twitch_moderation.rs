@@ -0,0 +1,65 @@
+use crate::{
+    command_handler::twitch_api::{broadcaster_helix_api, moderation::ModerationAction},
+    database::Database,
+    platform::ChannelIdentifier,
+};
+use async_trait::async_trait;
+use handlebars::RenderError;
+use serde_json::Value as Json;
+use tracing::debug;
+
+use super::{twitch_timeout::collect_params, AsyncHelper, HelperCall};
+
+/// Helper for moderation actions (`twitch_ban`, `twitch_slow`, etc), the action being the part after `twitch_`.
+/// The actions are taken as the broadcaster.
+pub struct TwitchModerationHelper {
+    pub db: Database,
+    pub action: &'static str,
+}
+
+#[async_trait]
+impl AsyncHelper for TwitchModerationHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let params = collect_params(call);
+        debug!("Collected params {params:?}");
+
+        let mut action =
+            ModerationAction::from_params(self.action, params).map_err(RenderError::new)?;
+
+        if let ModerationAction::Announcement { color, .. } = &mut action {
+            *color = call
+                .hash_get("color")
+                .and_then(|value| value.as_str())
+                .map(str::to_owned);
+        }
+
+        let context = call.context();
+
+        let broadcaster_id = match context.channel {
+            ChannelIdentifier::TwitchChannel((id, _)) => id,
+            _ => {
+                return Err(RenderError::new(
+                    "moderation actions cannot be used outside of Twitch!",
+                ));
+            }
+        };
+
+        let helix_api = broadcaster_helix_api(&self.db, &broadcaster_id)
+            .await
+            .map_err(|e| RenderError::new(e.to_string()))?;
+
+        helix_api
+            .moderate(&broadcaster_id, &action)
+            .await
+            .map_err(|e| {
+                tracing::warn!("{:?}", e);
+                RenderError::new(format!("Failed to {}: {e}", self.action))
+            })?;
+
+        Ok(Json::Null)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
+    }
+}
@@ -1,25 +1,19 @@
 use crate::{command_handler::platform_handler::TwitchApi, platform::ChannelIdentifier};
-use handlebars::{
-    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
-};
+use async_trait::async_trait;
+use handlebars::RenderError;
+use serde_json::Value as Json;
 use tracing::debug;
 
-use super::InquiryContext;
+use super::{AsyncHelper, HelperCall};
 
 pub struct TwitchTimeoutHelper {
     pub twitch_api: TwitchApi,
 }
 
-impl HelperDef for TwitchTimeoutHelper {
-    fn call<'reg: 'rc, 'rc>(
-        &self,
-        h: &Helper,
-        _: &Handlebars,
-        ctx: &Context,
-        _: &mut RenderContext,
-        _: &mut dyn Output,
-    ) -> HelperResult {
-        let mut params = collect_params(h).into_iter();
+#[async_trait]
+impl AsyncHelper for TwitchTimeoutHelper {
+    async fn call(&self, call: &HelperCall) -> Result<Json, RenderError> {
+        let mut params = collect_params(call).into_iter();
         debug!("Collected params {params:?}");
 
         let name = params
@@ -36,8 +30,7 @@ impl HelperDef for TwitchTimeoutHelper {
 
         debug!("Timing out user {name} for duration {length}");
 
-        let context = serde_json::from_value::<InquiryContext>(ctx.data().clone())
-            .expect("Failed to get command context");
+        let context = call.context();
 
         let broadcaster_id = match context.channel {
             ChannelIdentifier::TwitchChannel((id, _)) => id,
@@ -48,34 +41,29 @@ impl HelperDef for TwitchTimeoutHelper {
             }
         };
 
-        let api = self.twitch_api.clone();
-
-        let runtime = tokio::runtime::Handle::current();
-
-        runtime
-            .block_on(async move {
-                api.helix_api
-                    .ban_user_by_name(&broadcaster_id, &name, Some(length))
-                    .await
-            })
+        self.twitch_api
+            .helix_api
+            .ban_user_by_name(&broadcaster_id, &name, Some(length))
+            .await
             .map_err(|e| {
                 tracing::warn!("{:?}", e);
                 RenderError::new("Failed to timeout user")
             })?;
 
-        Ok(())
+        Ok(Json::Null)
+    }
+
+    fn has_side_effects(&self) -> bool {
+        true
     }
 }
 
-fn collect_params(h: &Helper) -> Vec<String> {
-    h.params()
+pub(super) fn collect_params(call: &HelperCall) -> Vec<String> {
+    call.params()
         .iter()
         .flat_map(|param| {
-            let value = match param.relative_path() {
-                Some(path) => path.to_owned(),
-                None => param.render(),
-            };
-            value
+            param
+                .path_or_render()
                 .split_whitespace()
                 .map(str::to_owned)
                 .collect::<Vec<_>>()
@@ -3,6 +3,7 @@ use reqwest::Client;
 use serde_json::Value;
 use std::sync::Arc;
 
+#[derive(Clone)]
 pub struct LingvaApi {
     client: Client,
     url: Arc<String>,
@@ -1,7 +1,9 @@
+pub mod automod;
 mod commands;
 pub mod discord_api;
+pub mod egress;
 pub mod error;
-mod eval;
+pub mod eval;
 pub mod finnhub_api;
 pub mod geohub;
 pub mod inquiry_helper;
@@ -9,6 +11,7 @@ pub mod lastfm_api;
 pub mod lingva_api;
 pub mod owm_api;
 pub mod platform_handler;
+pub mod services;
 pub mod spotify_api;
 pub mod twitch_api;
 mod ukraine_alert;
@@ -17,13 +20,11 @@ use anyhow::{anyhow, Context};
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use discord_api::DiscordApi;
+use futures::future::BoxFuture;
 use handlebars::Handlebars;
 use hebi::prelude::NativeModule;
 use inquiry_helper::*;
-use lastfm_api::LastFMApi;
-use lingva_api::LingvaApi;
 use opentelemetry::trace::TraceContextExt;
-use owm_api::OwmApi;
 use reqwest::Client;
 use std::collections::HashMap;
 use std::env;
@@ -35,25 +36,34 @@ use tokio::sync::{Mutex, RwLock};
 use tokio::task;
 use tracing::{info, instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
-use twitch_api::TwitchApi;
+use twitch_api::{moderation::ModerationAction, TwitchApi};
 
+use self::automod::{AutoMod, InboundMessage};
 use self::commands::BuiltinCommand;
+use self::egress::EgressPolicy;
 use self::error::CommandError;
+use self::eval::cache::PreparedScript;
 use self::eval::context::HebiContext;
-use self::eval::storage::ModuleStorage;
-use self::eval::{create_native_modules, eval_hebi};
-use self::finnhub_api::FinnhubApi;
+use self::eval::storage::{compile_module, ModuleStorage};
+use self::eval::{
+    create_native_modules, eval_hebi, instrument::line_column, syntax_errors, ChatContext,
+};
 use self::platform_handler::PlatformHandler;
+use self::services::Services;
 use crate::command_handler::commands::{create_builtin_commands, ExecutableCommand};
 use crate::command_handler::eval::storage::create_module_storage_from_env;
-use crate::command_handler::ukraine_alert::UkraineAlertClient;
-use crate::database::models::{Command, CommandMode, Filter};
-use crate::database::{models::User, Database};
+use crate::database::models::{
+    Command, CommandMode, Filter, GlobalRole, ModerationRule, ModerationRuleAction,
+};
+use crate::database::{models::User, Database, DatabaseError};
 use crate::platform::connector::get_connector_permissions;
 use crate::platform::{minecraft, UserIdentifier};
-use crate::platform::{ChannelIdentifier, Permissions, PlatformContext, ServerPlatformContext};
+use crate::platform::{
+    ChannelIdentifier, MessageReference, Permissions, PlatformContext, ServerPlatformContext,
+};
 
 const DEFAULT_COOLDOWN: u64 = 5;
+const DEFAULT_MODERATION_TIMEOUT: u32 = 600;
 
 #[derive(Clone)]
 pub struct CommandHandler {
@@ -65,9 +75,11 @@ pub struct CommandHandler {
     cooldowns: Arc<RwLock<Vec<(u64, String)>>>, // User id and command
     command_triggers: Arc<DashMap<u64, Arc<DashMap<String, String>>>>, // Channel id, trigger phrase and command name
     mirror_connections: Arc<HashMap<String, ChannelIdentifier>>,       // from and to channel
-    pub blocked_users: Arc<Vec<UserIdentifier>>,
     hebi_native_modules: Arc<Vec<NativeModule>>,
     hebi_module_storage: ModuleStorage,
+    pub services: Services,
+    pub automod: AutoMod,
+    pub egress: EgressPolicy,
 }
 
 impl CommandHandler {
@@ -85,7 +97,15 @@ impl CommandHandler {
                     .await
                     .expect("Failed to get EventSub triggers");
 
-                for trigger in db.get_eventsub_triggers().expect("DB Error") {
+                let triggers = db
+                    .run(|db| db.get_eventsub_triggers())
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Could not load EventSub triggers: {e}");
+                        Vec::new()
+                    });
+
+                for trigger in triggers {
                     if !active_triggers
                         .iter()
                         .any(|active_trigger| active_trigger.id == trigger.id)
@@ -99,10 +119,15 @@ impl CommandHandler {
                             .await
                         {
                             Ok(response) => {
-                                let new_id = &response.data.first().unwrap().id;
-
-                                db.update_eventsub_trigger_id(&trigger.id, new_id)
-                                    .expect("DB error");
+                                let old_id = trigger.id;
+                                let new_id = response.data.first().unwrap().id.clone();
+
+                                if let Err(e) = db
+                                    .run(move |db| db.update_eventsub_trigger_id(&old_id, &new_id))
+                                    .await
+                                {
+                                    tracing::error!("Failed to update EventSub trigger! {}", e);
+                                }
                             }
                             Err(e) => tracing::error!("Failed to add EventSub subscription! {}", e),
                         }
@@ -129,13 +154,24 @@ impl CommandHandler {
 
         let mut filters: HashMap<ChannelIdentifier, Vec<Filter>> = HashMap::new();
 
-        for filter in db.get_all_filters().expect("DB Error") {
-            let channel = db
-                .get_channel_by_id(filter.channel_id)
-                .expect("DB error")
-                .unwrap(); // None is mpossible because channel_id is a foreign key
-            let channel_identifier = channel.get_identifier();
+        let all_filters = db
+            .run(|db| {
+                db.get_all_filters()?
+                    .into_iter()
+                    .map(|filter| {
+                        // None is mpossible because channel_id is a foreign key
+                        let channel = db.get_channel_by_id(filter.channel_id)?.unwrap();
+                        Ok((channel.get_identifier(), filter))
+                    })
+                    .collect::<Result<Vec<_>, DatabaseError>>()
+            })
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("Could not load filters: {e}");
+                Vec::new()
+            });
 
+        for (channel_identifier, filter) in all_filters {
             if let Some(channel_filters) = filters.get_mut(&channel_identifier) {
                 channel_filters.push(filter);
             } else {
@@ -147,9 +183,15 @@ impl CommandHandler {
 
         let minecraft = match minecraft::init() {
             Ok(mut minecraft) => {
-                db.get_or_create_channel(&ChannelIdentifier::Minecraft)
-                    .expect("DB error")
-                    .expect("Failed to initialize Minecraft channel in the DB!");
+                match db
+                    .run(|db| db.get_or_create_channel(&ChannelIdentifier::Minecraft))
+                    .await
+                {
+                    Ok(channel) => {
+                        channel.expect("Failed to initialize Minecraft channel in the DB!");
+                    }
+                    Err(e) => tracing::error!("Could not create the Minecraft channel: {e}"),
+                }
                 if env::var("PROFILE") == Ok("debug".to_string()) {
                     minecraft
                         .send_command("say Foobot2 connected".to_string())
@@ -174,25 +216,43 @@ impl CommandHandler {
         let hebi_module_storage =
             create_module_storage_from_env().expect("Could not create hebi module storage");
 
-        let lingva_api = LingvaApi::init(lingva_url);
-        let ukraine_alert_client = UkraineAlertClient::default();
+        let services = Services::new(db.clone(), lingva_url, platform_handler.twitch_api.clone());
+
+        let egress = EgressPolicy::new(db.clone());
 
         let mut template_registry = Handlebars::new();
 
-        template_registry.register_helper("translate", Box::new(lingva_api));
-        template_registry.register_helper("ukraine_alerts", Box::new(ukraine_alert_client));
-        template_registry.register_helper("args", Box::new(inquiry_helper::args_helper));
-        template_registry.register_helper("spotify", Box::new(SpotifyHelper { db: db.clone() }));
         template_registry.register_helper(
-            "spotify_last_song",
-            Box::new(SpotifyLastHelper { db: db.clone() }),
+            "translate",
+            Box::new(Deferred::new(TranslateHelper {
+                services: services.clone(),
+            })),
         );
         template_registry.register_helper(
-            "spotify_playlist",
-            Box::new(SpotifyPlaylistHelper { db: db.clone() }),
+            "ukraine_alerts",
+            Box::new(Deferred::new(UkraineAlertsHelper {
+                services: services.clone(),
+            })),
         );
-        template_registry.register_helper("choose", Box::new(random_helper));
-        template_registry.register_helper("sleep", Box::new(sleep_helper));
+        template_registry.register_helper("args", Box::new(inquiry_helper::args_helper));
+
+        for (name, source) in [
+            ("lastfm", MusicSource::LastFM),
+            ("spotify", MusicSource::Spotify),
+            ("spotify_last_song", MusicSource::SpotifyLastSong),
+            ("spotify_playlist", MusicSource::SpotifyPlaylist),
+        ] {
+            template_registry.register_helper(
+                name,
+                Box::new(Deferred::new(MusicHelper {
+                    services: services.clone(),
+                    source,
+                })),
+            );
+        }
+
+        template_registry.register_helper("choose", Box::new(Deferred::new(RandomHelper)));
+        template_registry.register_helper("sleep", Box::new(Deferred::new(SleepHelper)));
         template_registry.register_helper("username", Box::new(username_helper));
         template_registry.register_helper("concat", Box::new(concat_helper));
         template_registry.register_helper("trim_matches", Box::new(trim_matches_helper));
@@ -204,102 +264,152 @@ impl CommandHandler {
             "forsencode_decode",
             Box::new(inquiry_helper::forsencode_decode_helper),
         );
-
-        if let Ok(api_key) = env::var("FINNHUB_API_KEY") {
-            template_registry.register_helper("stock", Box::new(FinnhubApi::init(api_key)));
-        }
-
-        if let Ok(owm_api_key) = env::var("OWM_API_KEY") {
-            template_registry.register_helper(
-                "weather",
-                Box::new(WeatherHelper {
-                    db: db.clone(),
-                    api: OwmApi::init(owm_api_key),
-                }),
-            );
-        }
-
-        if let Ok(lastfm_api_key) = env::var("LASTFM_API_KEY") {
-            template_registry.register_helper(
-                "lastfm",
-                Box::new(LastFMHelper {
-                    db: db.clone(),
-                    lastfm_api: LastFMApi::init(lastfm_api_key),
-                }),
-            )
-        }
+        template_registry.register_helper(
+            "stock",
+            Box::new(Deferred::new(StockHelper {
+                services: services.clone(),
+            })),
+        );
+        template_registry.register_helper(
+            "weather",
+            Box::new(Deferred::new(WeatherHelper {
+                services: services.clone(),
+            })),
+        );
+        template_registry.register_helper(
+            "time",
+            Box::new(Deferred::new(TimeHelper {
+                services: services.clone(),
+            })),
+        );
+        template_registry.register_helper(
+            "profile",
+            Box::new(Deferred::new(ProfileHelper {
+                services: services.clone(),
+            })),
+        );
 
         if let Some(twitch_api) = &platform_handler.twitch_api {
             template_registry.register_helper(
                 "twitchuser",
-                Box::new(TwitchUserHelper {
-                    twitch_api: twitch_api.clone(),
-                }),
+                Box::new(Deferred::new(TwitchUserHelper {
+                    services: services.clone(),
+                })),
             );
             template_registry.register_helper(
                 "twitch_commercial",
-                Box::new(CommercialHelper { db: db.clone() }),
+                Box::new(Deferred::new(CommercialHelper { db: db.clone() })),
             );
             template_registry.register_helper(
                 "twitch_timeout",
-                Box::new(TwitchTimeoutHelper {
+                Box::new(Deferred::new(TwitchTimeoutHelper {
                     twitch_api: twitch_api.clone(),
-                }),
+                })),
             );
+
+            for action in [
+                "ban",
+                "unban",
+                "delete",
+                "clear",
+                "shield",
+                "slow",
+                "emoteonly",
+                "followersonly",
+                "subonly",
+                "announce",
+            ] {
+                template_registry.register_helper(
+                    &format!("twitch_{action}"),
+                    Box::new(Deferred::new(TwitchModerationHelper {
+                        db: db.clone(),
+                        action,
+                    })),
+                );
+            }
         }
 
-        template_registry.register_helper("get", Box::new(HttpHelper::init()));
+        template_registry.register_helper(
+            "get",
+            Box::new(Deferred::new(HttpHelper::init(egress.clone()))),
+        );
         template_registry.register_helper("json", Box::new(JsonHelper));
-        template_registry.register_helper("song", Box::new(inquiry_helper::song_helper));
-
-        let temp_data = Arc::new(DashMap::new());
+        template_registry.register_helper(
+            "song",
+            Box::new(Deferred::new(SongHelper {
+                services: services.clone(),
+            })),
+        );
 
         template_registry.register_helper(
             "data_get",
-            Box::new(GetTempData {
-                data: temp_data.clone(),
-            }),
+            Box::new(Deferred::new(DataGetHelper { db: db.clone() })),
+        );
+        template_registry.register_helper(
+            "data_set",
+            Box::new(Deferred::new(DataSetHelper { db: db.clone() })),
+        );
+        template_registry.register_helper(
+            "counter_get",
+            Box::new(Deferred::new(CounterGetHelper { db: db.clone() })),
+        );
+        template_registry.register_helper(
+            "counter_inc",
+            Box::new(Deferred::new(CounterIncHelper { db: db.clone() })),
         );
 
         let platform_handler = Arc::new(RwLock::new(platform_handler));
 
         template_registry.register_helper(
             "say",
-            Box::new(inquiry_helper::SayHelper {
+            Box::new(Deferred::new(inquiry_helper::SayHelper {
                 platform_handler: platform_handler.clone(),
-            }),
+            })),
         );
 
-        template_registry.register_helper("data_set", Box::new(SetTempData { data: temp_data }));
         template_registry.register_decorator("set", Box::new(set_decorator));
 
         template_registry.set_strict_mode(true);
 
         let template_registry = Arc::new(template_registry);
 
-        let hebi_native_modules = Arc::new(create_native_modules(db.clone()));
+        let hebi_native_modules = Arc::new(create_native_modules(egress.clone(), &services));
 
         let builtin_commands = create_builtin_commands(
             template_registry.clone(),
             hebi_native_modules.clone(),
             hebi_module_storage.clone(),
+            services.clone(),
         );
         info!("Loaded builtin commands: {builtin_commands:?}");
 
         let cooldowns = Arc::new(RwLock::new(Vec::new()));
 
-        let mut mirror_connections = HashMap::new();
-        for connection in db.get_mirror_connections().expect("DB error") {
-            let from_channel = db
-                .get_channel_by_id(connection.from_channel_id)
-                .unwrap()
-                .expect("Invalid channel connection");
-
-            let to_channel = db
-                .get_channel_by_id(connection.to_channel_id)
-                .unwrap()
-                .expect("Invalid channel connection");
+        let connected_channels = db
+            .run(|db| {
+                db.get_mirror_connections()?
+                    .into_iter()
+                    .map(|connection| {
+                        let from_channel = db
+                            .get_channel_by_id(connection.from_channel_id)?
+                            .expect("Invalid channel connection");
+
+                        let to_channel = db
+                            .get_channel_by_id(connection.to_channel_id)?
+                            .expect("Invalid channel connection");
+
+                        Ok((from_channel, to_channel))
+                    })
+                    .collect::<Result<Vec<_>, DatabaseError>>()
+            })
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("Could not load mirror connections: {e}");
+                Vec::new()
+            });
 
+        let mut mirror_connections = HashMap::new();
+        for (from_channel, to_channel) in connected_channels {
             if let Some(from_channel_str) = from_channel.get_identifier().get_channel() {
                 mirror_connections.insert(
                     format!("{}-{}", from_channel.platform, from_channel_str),
@@ -309,17 +419,19 @@ impl CommandHandler {
         }
         tracing::info!("Mirroring channels: {:?}", mirror_connections);
 
-        let blocked_users = env::var("BLOCKED_USERS")
-            .map(|blocked_users| {
-                blocked_users
-                    .split(',')
-                    .map(|raw_identifier| UserIdentifier::from_string(raw_identifier).unwrap())
-                    .collect()
-            })
-            .unwrap_or_default();
+        if let Ok(blocked_users) = env::var("BLOCKED_USERS") {
+            tracing::warn!("BLOCKED_USERS is deprecated, the users are now blocked in the database. Use the block command instead");
+
+            if let Err(e) = services.import_blocked_users(&blocked_users).await {
+                tracing::error!("Failed to import BLOCKED_USERS: {e}");
+            }
+        }
 
         start_supinic_heartbeat().await;
 
+        let automod = AutoMod::new(db.clone());
+        automod.start_history_sweep();
+
         Self {
             db,
             platform_handler,
@@ -329,9 +441,11 @@ impl CommandHandler {
             command_triggers: Arc::new(DashMap::new()),
             builtin_commands: Arc::new(builtin_commands),
             nats_client,
-            blocked_users: Arc::new(blocked_users),
             hebi_native_modules,
             hebi_module_storage,
+            services,
+            automod,
+            egress,
         }
     }
 
@@ -396,12 +510,43 @@ impl CommandHandler {
             }
         }
 
-        if let Some(channel) = self
+        let channel_identifier = platform_ctx.get_channel();
+        let channel = match self
             .db
-            .get_or_create_channel(&platform_ctx.get_channel())
-            .expect("DB error")
+            .run(move |db| db.get_or_create_channel(&channel_identifier))
+            .await
         {
-            let triggers = self.get_command_triggers(channel.id).expect("DB error");
+            Ok(channel) => channel,
+            Err(e) => {
+                tracing::error!("Failed to get channel: {e}");
+                return None;
+            }
+        };
+
+        if let Some(channel) = channel {
+            let inbound_message = InboundMessage {
+                channel_id: channel.id,
+                user: platform_ctx.get_user_identifier().to_string(),
+                text: message_text,
+                emote_count: platform_ctx.get_emote_count(),
+            };
+
+            let is_exempt =
+                async { platform_ctx.get_permissions_internal().await >= Permissions::ChannelMod };
+
+            match self.automod.check(&inbound_message, is_exempt).await {
+                Ok(Some(rule)) => return self.apply_moderation_rule(rule, &platform_ctx).await,
+                Ok(None) => (),
+                Err(e) => tracing::error!("Failed to check moderation rules: {e:?}"),
+            }
+
+            let triggers = match self.get_command_triggers(channel.id).await {
+                Ok(triggers) => triggers,
+                Err(e) => {
+                    tracing::error!("Failed to get command triggers: {e}");
+                    return None;
+                }
+            };
 
             for trigger in triggers.iter() {
                 if let Some(command_args) = message_text.strip_prefix(trigger.key()) {
@@ -423,6 +568,123 @@ impl CommandHandler {
         None
     }
 
+    /// Applies the action of a violated moderation rule, returning the message to reply with (if any).
+    async fn apply_moderation_rule<P: PlatformContext + Send + Sync>(
+        &self,
+        rule: ModerationRule,
+        platform_ctx: &P,
+    ) -> Option<String> {
+        info!(
+            "Message from {} in {} violated moderation rule {:?}",
+            platform_ctx.get_user_identifier(),
+            platform_ctx.get_channel(),
+            rule
+        );
+
+        let reason = rule
+            .reason
+            .clone()
+            .unwrap_or_else(|| format!("{} rule violation", rule.kind));
+
+        if rule.action == ModerationRuleAction::Warn {
+            return Some(format!("{}, {reason}", platform_ctx.get_display_name()));
+        }
+
+        if let Err(e) = self
+            .execute_moderation_action(&rule, &reason, platform_ctx)
+            .await
+        {
+            tracing::warn!("Failed to apply moderation rule: {e:?}");
+        }
+
+        None
+    }
+
+    async fn execute_moderation_action<P: PlatformContext + Send + Sync>(
+        &self,
+        rule: &ModerationRule,
+        reason: &str,
+        platform_ctx: &P,
+    ) -> anyhow::Result<()> {
+        let platform_handler = self.platform_handler.read().await;
+        let duration = rule.timeout_duration.unwrap_or(DEFAULT_MODERATION_TIMEOUT);
+
+        match (
+            rule.action,
+            platform_ctx.get_message_reference(),
+            platform_ctx.get_channel(),
+        ) {
+            (
+                action,
+                Some(MessageReference::Twitch { message_id }),
+                ChannelIdentifier::TwitchChannel((broadcaster_id, _)),
+            ) => {
+                let twitch_api = platform_handler
+                    .twitch_api
+                    .as_ref()
+                    .context("Twitch not configured")?;
+
+                match action {
+                    ModerationRuleAction::Timeout => {
+                        let UserIdentifier::TwitchID(user_id) = platform_ctx.get_user_identifier()
+                        else {
+                            return Err(anyhow!("Invalid Twitch user"));
+                        };
+
+                        twitch_api
+                            .helix_api
+                            .ban_user(
+                                &broadcaster_id,
+                                &user_id,
+                                Some(duration as i32),
+                                Some(reason),
+                            )
+                            .await
+                    }
+                    _ => {
+                        twitch_api
+                            .helix_api
+                            .moderate(
+                                &broadcaster_id,
+                                &ModerationAction::DeleteMessage { message_id },
+                            )
+                            .await
+                    }
+                }
+            }
+            (
+                action,
+                Some(MessageReference::Discord {
+                    channel_id,
+                    message_id,
+                }),
+                ChannelIdentifier::DiscordChannel(guild_id),
+            ) => {
+                let discord_api = platform_handler
+                    .discord_api
+                    .as_ref()
+                    .context("Discord not configured")?;
+
+                match action {
+                    ModerationRuleAction::Timeout => {
+                        let UserIdentifier::DiscordID(user_id) = platform_ctx.get_user_identifier()
+                        else {
+                            return Err(anyhow!("Invalid Discord user"));
+                        };
+
+                        discord_api
+                            .timeout_member(guild_id.parse()?, user_id.parse()?, duration as u64)
+                            .await
+                    }
+                    _ => discord_api.delete_message(channel_id, message_id).await,
+                }
+            }
+            _ => Err(anyhow!(
+                "Moderation actions are not supported on this platform"
+            )),
+        }
+    }
+
     /// This function expects a raw message that appears to be a command without the leading command prefix.
     #[instrument(skip(self))]
     async fn handle_command_message<C>(&self, message_text: &str, context: C) -> Option<String>
@@ -447,7 +709,23 @@ impl CommandHandler {
         }
     }
 
-    // #[async_recursion]
+    /// Runs a command on behalf of another command (see the Hebi `chat.run` function).
+    ///
+    /// The future is boxed, as otherwise it would contain itself through the Hebi evaluation.
+    pub fn run_nested_command(
+        &self,
+        command: String,
+        args: Vec<String>,
+        platform_ctx: ServerPlatformContext,
+    ) -> BoxFuture<'static, Result<Option<String>, CommandError>> {
+        let cmd = self.clone();
+
+        Box::pin(async move {
+            let args = args.iter().map(String::as_str).collect();
+            cmd.run_command(&command, args, platform_ctx).await
+        })
+    }
+
     #[instrument(skip(self, platform_ctx))]
     async fn run_command<P: PlatformContext + Send + Sync>(
         &self,
@@ -462,7 +740,10 @@ impl CommandHandler {
         let processing_timestamp = Utc::now();
 
         let user_identifier = platform_ctx.get_user_identifier();
-        let user = self.db.get_or_create_user(&user_identifier)?;
+        let user = self
+            .db
+            .run(move |db| db.get_or_create_user(&user_identifier))
+            .await?;
 
         if !self
             .cooldowns
@@ -471,7 +752,11 @@ impl CommandHandler {
             .contains(&(user.id, command.to_string()))
         {
             let platform_handler = self.platform_handler.read().await;
-            let channel = self.db.get_or_create_channel(&platform_ctx.get_channel())?;
+            let channel_identifier = platform_ctx.get_channel();
+            let channel = self
+                .db
+                .run(move |db| db.get_or_create_channel(&channel_identifier))
+                .await?;
             let mut execution_ctx = ExecutionContext {
                 db: &self.db,
                 channel_id: channel.map(|channel| channel.id),
@@ -479,7 +764,6 @@ impl CommandHandler {
                 platform_ctx,
                 user: &user,
                 processing_timestamp,
-                blocked_users: &self.blocked_users,
             };
 
             let (output, cooldown) = if let Some(builtin_command) = self
@@ -490,7 +774,14 @@ impl CommandHandler {
                 let command_permissions = builtin_command.get_permissions();
                 let user_permissions = execution_ctx.get_permissions().await?;
                 if command_permissions > user_permissions {
-                    return Err(CommandError::NoPermissions);
+                    let user_id = user.id;
+                    let trusted = builtin_command.allows_trusted()
+                        && self.db.run(move |db| db.get_global_role(user_id)).await?
+                            == Some(GlobalRole::Trusted);
+
+                    if !trusted {
+                        return Err(CommandError::NoPermissions);
+                    }
                 }
 
                 let cooldown = builtin_command.get_cooldown();
@@ -500,8 +791,8 @@ impl CommandHandler {
 
                 (output, cooldown)
             } else if let Some(command) = self
-                .db
-                .get_command(&execution_ctx.platform_ctx.get_channel(), command)?
+                .find_command(execution_ctx.platform_ctx.get_channel(), command)
+                .await?
             {
                 // TODO custom permissions
 
@@ -533,6 +824,18 @@ impl CommandHandler {
         }
     }
 
+    async fn find_command(
+        &self,
+        channel_identifier: ChannelIdentifier,
+        name: &str,
+    ) -> Result<Option<Command>, DatabaseError> {
+        let name = name.to_owned();
+
+        self.db
+            .run(move |db| db.get_command(&channel_identifier, &name))
+            .await
+    }
+
     #[instrument(skip(self))]
     pub async fn execute_command<P: PlatformContext>(
         &self,
@@ -546,21 +849,38 @@ impl CommandHandler {
                     .await
             }
             CommandMode::Hebi => {
-                let hebi_ctx = HebiContext::try_from(ctx)?;
+                let hebi_ctx = HebiContext::new(ctx).await?;
+                let script = self.db.get_hebi_script(&command);
 
                 eval_hebi(
-                    command.action,
+                    script,
                     &self.hebi_native_modules,
                     self.hebi_module_storage.clone(),
                     self.db.clone(),
                     &args,
                     hebi_ctx,
+                    self.services.clone(),
+                    Some(ChatContext::new(self.clone(), &ctx.platform_ctx)),
                 )
                 .await
             }
         }
     }
 
+    /// Checks the action of a command with the registered template helpers.
+    pub fn validate_command(&self, mode: &CommandMode, action: &str) -> Vec<LintError> {
+        validate_command_action(&self.template_registry, mode, action)
+    }
+
+    pub async fn dry_run_template<P: PlatformContext>(
+        &self,
+        action: &str,
+        ctx: &ExecutionContext<'_, P>,
+        args: Vec<String>,
+    ) -> Result<String, CommandError> {
+        dry_run_template_command(&self.template_registry, action, ctx, args).await
+    }
+
     async fn start_cooldown(&self, user_id: u64, command: String, cooldown: u64) {
         let cooldowns = self.cooldowns.clone();
         task::spawn(async move {
@@ -582,18 +902,57 @@ impl CommandHandler {
         user: User,
         channel: &ChannelIdentifier,
     ) -> anyhow::Result<Permissions> {
-        if let Ok(Some(admin_user)) = self.db.get_admin_user() {
-            if user.id == admin_user.id {
-                return Ok(Permissions::Admin);
-            }
+        let user_id = user.id;
+        let channel_identifier = channel.clone();
+
+        let (is_admin, granted_role) = self
+            .db
+            .run(move |db| {
+                if db.is_admin(user_id)? {
+                    return Ok((true, None));
+                }
+
+                let granted_role = match db.get_channel(&channel_identifier)? {
+                    Some(channel) => db.get_channel_role(channel.id, user_id)?,
+                    None => None,
+                };
+
+                Ok((false, granted_role))
+            })
+            .await?;
+
+        if is_admin {
+            return Ok(Permissions::Admin);
         }
 
+        let platform_permissions = self.get_platform_permissions(user, channel).await;
+
+        // Roles given in foobot also work for users who aren't on the channel's platform
+        match granted_role {
+            Some(role) => Ok(
+                platform_permissions.map_or(role.permissions(), |permissions| {
+                    permissions.max(role.permissions())
+                }),
+            ),
+            None => platform_permissions,
+        }
+    }
+
+    async fn get_platform_permissions(
+        &self,
+        user: User,
+        channel: &ChannelIdentifier,
+    ) -> anyhow::Result<Permissions> {
         match channel {
             ChannelIdentifier::TwitchChannel((channel_id, _)) => {
                 let twitch_id = user
                     .twitch_id
                     .ok_or_else(|| anyhow!("Not registered on this platform"))?;
 
+                if twitch_id == *channel_id {
+                    return Ok(Permissions::ChannelOwner);
+                }
+
                 let platform_handler = self.platform_handler.read().await;
 
                 let twitch_api = platform_handler
@@ -601,25 +960,10 @@ impl CommandHandler {
                     .as_ref()
                     .ok_or_else(|| anyhow!("Twitch not configured"))?;
 
-                let users_response = twitch_api
-                    .helix_api
-                    .get_users(None, Some(&[channel_id]))
-                    .await?;
-
-                let channel_login = &users_response.first().expect("User not found").login;
-
-                match twitch_api.get_channel_mods(channel_login).await?.contains(
-                    &twitch_api
-                        .helix_api
-                        .get_users(None, Some(&[&twitch_id]))
-                        .await?
-                        .first()
-                        .unwrap()
-                        .display_name,
-                ) {
-                    true => Ok(Permissions::ChannelMod),
-                    false => Ok(Permissions::Default),
-                }
+                Ok(twitch_api
+                    .get_channel_roles(&self.db, channel_id)
+                    .await?
+                    .get_permissions(&twitch_id))
             }
             ChannelIdentifier::DiscordChannel(guild_id) => {
                 let user_id = user
@@ -663,12 +1007,18 @@ impl CommandHandler {
         user_id: u64,
         channel_id: u64,
     ) -> anyhow::Result<Permissions> {
-        let user = self
+        let (user, channel) = self
             .db
-            .get_user_by_id(user_id)?
-            .ok_or_else(|| anyhow!("Invalid user id"))?;
+            .run(move |db| {
+                Ok((
+                    db.get_user_by_id(user_id)?,
+                    db.get_channel_by_id(channel_id)?,
+                ))
+            })
+            .await?;
+        let user = user.ok_or_else(|| anyhow!("Invalid user id"))?;
 
-        match self.db.get_channel_by_id(channel_id)? {
+        match channel {
             Some(channel) => {
                 let channel_identifier =
                     ChannelIdentifier::new(&channel.platform, channel.channel)?;
@@ -690,7 +1040,11 @@ impl CommandHandler {
         channel_id: Option<u64>,
     ) -> anyhow::Result<()> {
         let processing_timestamp = Utc::now();
-        let user = self.db.get_or_create_user(&platform_ctx.executing_user)?;
+        let user_identifier = platform_ctx.executing_user.clone();
+        let user = self
+            .db
+            .run(move |db| db.get_or_create_user(&user_identifier))
+            .await?;
 
         let platform_handler = self.platform_handler.read().await;
         let execution_ctx = ExecutionContext {
@@ -700,7 +1054,6 @@ impl CommandHandler {
             platform_ctx,
             user: &user,
             processing_timestamp,
-            blocked_users: &self.blocked_users,
         };
 
         let response = match mode {
@@ -714,14 +1067,16 @@ impl CommandHandler {
                 .await?
             }
             CommandMode::Hebi => {
-                let hebi_ctx = HebiContext::try_from(&execution_ctx)?;
+                let hebi_ctx = HebiContext::new(&execution_ctx).await?;
                 eval_hebi(
-                    action,
+                    PreparedScript::new(&action),
                     &self.hebi_native_modules,
                     self.hebi_module_storage.clone(),
                     self.db.clone(),
                     &arguments,
                     hebi_ctx,
+                    self.services.clone(),
+                    Some(ChatContext::new(self.clone(), &execution_ctx.platform_ctx)),
                 )
                 .await?
             }
@@ -778,21 +1133,22 @@ impl CommandHandler {
         }
     }*/
 
-    fn get_command_triggers(
+    async fn get_command_triggers(
         &self,
         channel_id: u64,
     ) -> Result<Arc<DashMap<String, String>>, CommandError> {
-        match self.command_triggers.get(&channel_id) {
-            Some(triggers) => Ok(triggers.clone()),
-            None => {
-                self.refresh_command_triggers(channel_id)?;
-                self.get_command_triggers(channel_id)
-            }
+        if let Some(triggers) = self.command_triggers.get(&channel_id) {
+            return Ok(triggers.clone());
         }
+
+        self.refresh_command_triggers(channel_id).await
     }
 
-    fn refresh_command_triggers(&self, channel_id: u64) -> Result<(), CommandError> {
-        let commands = self.db.get_commands(channel_id)?;
+    async fn refresh_command_triggers(
+        &self,
+        channel_id: u64,
+    ) -> Result<Arc<DashMap<String, String>>, CommandError> {
+        let commands = self.db.run(move |db| db.get_commands(channel_id)).await?;
 
         let triggers = DashMap::new();
 
@@ -804,15 +1160,17 @@ impl CommandHandler {
             }
         }
 
+        let triggers = Arc::new(triggers);
+
         if self
             .command_triggers
-            .insert(channel_id, Arc::new(triggers))
+            .insert(channel_id, triggers.clone())
             .is_some()
         {
             tracing::info!("Reloaded command triggers in channel {}", channel_id);
         }
 
-        Ok(())
+        Ok(triggers)
     }
 }
 
@@ -823,7 +1181,6 @@ pub struct ExecutionContext<'a, P: PlatformContext> {
     pub channel_id: Option<u64>,
     pub user: &'a User,
     pub processing_timestamp: DateTime<Utc>,
-    pub blocked_users: &'a [UserIdentifier],
 }
 
 impl<P: PlatformContext> Debug for ExecutionContext<'_, P> {
@@ -832,7 +1189,6 @@ impl<P: PlatformContext> Debug for ExecutionContext<'_, P> {
             .field("platform_ctx", &self.platform_ctx)
             .field("user", &self.user)
             .field("processing_timestamp", &self.processing_timestamp)
-            .field("blocked_users", &self.blocked_users)
             .finish()
     }
 }
@@ -840,16 +1196,19 @@ impl<P: PlatformContext> Debug for ExecutionContext<'_, P> {
 impl<P: PlatformContext> ExecutionContext<'_, P> {
     #[instrument]
     async fn get_permissions(&self) -> Result<Permissions, CommandError> {
-        if let Ok(Some(admin_user)) = self.db.get_admin_user() {
-            if admin_user.id == self.user.id {
-                return Ok(Permissions::Admin);
-            }
+        let user_id = self.user.id;
+        let (is_admin, global_role) = self
+            .db
+            .run(move |db| Ok((db.is_admin(user_id)?, db.get_global_role(user_id)?)))
+            .await?;
+
+        if is_admin {
+            return Ok(Permissions::Admin);
         }
 
-        let identifier = self.platform_ctx.get_user_identifier();
-        if self.blocked_users.contains(&identifier) {
+        if global_role == Some(GlobalRole::Blocked) {
             return Err(CommandError::NoPermissions);
-        };
+        }
 
         Ok(self.platform_ctx.get_permissions_internal().await)
     }
@@ -894,24 +1253,9 @@ async fn execute_template_command<P: PlatformContext>(
 ) -> Result<Option<String>, CommandError> {
     tracing::debug!("Parsing action {}", action);
 
-    let display_name = ctx.platform_ctx.get_display_name().to_string();
-    let channel = ctx.platform_ctx.get_channel();
-    let user = ctx.user.clone();
-
-    let response = match task::spawn_blocking(move || {
-        template_registry.render_template(
-            &action,
-            &(InquiryContext {
-                user,
-                arguments: args,
-                display_name,
-                channel,
-            }),
-        )
-    })
-    .await
-    .expect("Failed to join")
-    {
+    let context = create_inquiry_context(ctx, args);
+
+    let response = match render_template(&template_registry, &action, &context).await {
         Ok(result) => result,
         Err(err) => err.desc,
     };
@@ -923,6 +1267,68 @@ async fn execute_template_command<P: PlatformContext>(
     }
 }
 
+/// Renders the template like [`execute_template_command`], without executing helpers that have side effects.
+pub async fn dry_run_template_command<P: PlatformContext>(
+    template_registry: &Handlebars<'static>,
+    action: &str,
+    ctx: &ExecutionContext<'_, P>,
+    args: Vec<String>,
+) -> Result<String, CommandError> {
+    let context = create_inquiry_context(ctx, args);
+
+    Ok(render_template_dry(template_registry, action, &context).await?)
+}
+
+fn create_inquiry_context<P: PlatformContext>(
+    ctx: &ExecutionContext<'_, P>,
+    args: Vec<String>,
+) -> InquiryContext {
+    InquiryContext {
+        user: ctx.user.clone(),
+        arguments: args,
+        display_name: ctx.platform_ctx.get_display_name().to_string(),
+        channel: ctx.platform_ctx.get_channel(),
+        channel_id: ctx.channel_id,
+    }
+}
+
+/// Checks that the action of a command can be executed, without running it.
+pub fn validate_command_action(
+    template_registry: &Handlebars,
+    mode: &CommandMode,
+    action: &str,
+) -> Vec<LintError> {
+    match mode {
+        CommandMode::Template => lint_template(template_registry, action),
+        CommandMode::Hebi => match compile_module(action) {
+            Ok(()) => Vec::new(),
+            Err(err) => {
+                let errors = syntax_errors(&err);
+
+                if errors.is_empty() {
+                    return vec![LintError {
+                        line: None,
+                        column: None,
+                        message: err.to_string(),
+                    }];
+                }
+
+                errors
+                    .into_iter()
+                    .map(|(offset, message)| {
+                        let (line, column) = line_column(action, offset);
+                        LintError {
+                            line: Some(line),
+                            column: Some(column),
+                            message,
+                        }
+                    })
+                    .collect()
+            }
+        },
+    }
+}
+
 pub fn get_admin_channel() -> Option<ChannelIdentifier> {
     if let Ok(admin_str) = env::var("ADMIN_USER") {
         match ChannelIdentifier::from_str(&admin_str) {
@@ -939,6 +1345,10 @@ pub fn get_admin_channel() -> Option<ChannelIdentifier> {
 
 fn unping(s: &mut String) {
     let magic_char = char::from_u32(0x000E0000).unwrap();
-    let second = s.split_off(s.len() / 2);
+    let middle = s
+        .char_indices()
+        .nth(s.chars().count() / 2)
+        .map_or(s.len(), |(i, _)| i);
+    let second = s.split_off(middle);
     *s = format!("{s}{magic_char}{second}")
 }
@@ -0,0 +1,90 @@
+use super::{ServiceError, Services};
+use crate::database::models::{GlobalRole, User};
+use chrono::{Duration, Utc};
+use rand::{thread_rng, Rng};
+
+const LINK_CODE_LENGTH: usize = 8;
+/// Without characters that are easy to confuse, the codes are case insensitive
+const LINK_CODE_CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+pub const LINK_CODE_LIFETIME_MINUTES: i64 = 10;
+
+impl Services {
+    /// Creates a one-time code that links the account that redeems it with this user.
+    pub async fn create_link_code(&self, user_id: u64) -> Result<String, ServiceError> {
+        let code = {
+            let mut rng = thread_rng();
+            (0..LINK_CODE_LENGTH)
+                .map(|_| LINK_CODE_CHARSET[rng.gen_range(0..LINK_CODE_CHARSET.len())] as char)
+                .collect::<String>()
+        };
+
+        let expires_at = (Utc::now() + Duration::minutes(LINK_CODE_LIFETIME_MINUTES)).timestamp();
+        self.db
+            .run({
+                let code = code.clone();
+                move |db| db.create_link_code(user_id, &code, expires_at as u64)
+            })
+            .await?;
+
+        Ok(code)
+    }
+
+    /// Links the user with the user who created the code.
+    pub async fn redeem_link_code(&self, code: &str, user: User) -> Result<User, ServiceError> {
+        let code = code.trim().to_uppercase();
+
+        let other_user = self
+            .db
+            .run(move |db| match db.redeem_link_code(&code)? {
+                Some(other_user_id) => db.get_user_by_id(other_user_id),
+                None => Ok(None),
+            })
+            .await?
+            .ok_or_else(|| ServiceError::InvalidInput("invalid or expired code".to_owned()))?;
+
+        self.link_users(user, other_user).await
+    }
+
+    /// Merges the users and their data. The older account is kept,
+    /// so its data wins when both users have a value.
+    pub async fn link_users(&self, user: User, other: User) -> Result<User, ServiceError> {
+        if user.id == other.id {
+            return Err(ServiceError::InvalidInput(
+                "the accounts are already linked".to_owned(),
+            ));
+        }
+        if !user.can_merge(&other) {
+            return Err(ServiceError::InvalidInput(
+                "the accounts have different users on the same platform".to_owned(),
+            ));
+        }
+
+        // The merged user would keep only one of the roles
+        let user_ids = [user.id, other.id];
+        let roles = self
+            .db
+            .run(move |db| {
+                user_ids
+                    .into_iter()
+                    .map(|user_id| db.get_global_role(user_id))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .await?;
+
+        if roles.contains(&Some(GlobalRole::Blocked)) {
+            return Err(ServiceError::InvalidInput(
+                "blocked accounts can't be linked".to_owned(),
+            ));
+        }
+
+        let (user, other) = if user.id < other.id {
+            (user, other)
+        } else {
+            (other, user)
+        };
+
+        tracing::info!("Linking user {} with {}", user.id, other.id);
+
+        Ok(self.db.run(move |db| db.merge_users(user, other)).await?)
+    }
+}
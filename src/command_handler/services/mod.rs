@@ -0,0 +1,343 @@
+pub mod forsencode;
+mod link;
+mod profile;
+mod roles;
+
+pub use link::LINK_CODE_LIFETIME_MINUTES;
+
+use super::{
+    finnhub_api::FinnhubApi, lastfm_api::LastFMApi, lingva_api::LingvaApi, owm_api::OwmApi,
+    platform_handler::TwitchApi, spotify_api::SpotifyApi, ukraine_alert::UkraineAlertClient,
+};
+use crate::database::{Database, DatabaseError};
+use crate::platform::UserIdentifier;
+use anyhow::anyhow;
+use std::env;
+
+/// Integrations with external services, shared by the template helpers and the Hebi modules.
+/// The methods return the text that should be shown to the user.
+#[derive(Clone)]
+pub struct Services {
+    db: Database,
+    owm_api: Option<OwmApi>,
+    lastfm_api: Option<LastFMApi>,
+    finnhub_api: Option<FinnhubApi>,
+    lingva_api: LingvaApi,
+    ukraine_alert_client: UkraineAlertClient,
+    pub twitch_api: Option<TwitchApi>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ServiceError {
+    #[error("{0} is not configured")]
+    NotConfigured(&'static str),
+    #[error("{0}")]
+    InvalidInput(String),
+    #[error("{0} error: {1:#}")]
+    Api(&'static str, anyhow::Error),
+    #[error(transparent)]
+    Database(#[from] DatabaseError),
+}
+
+impl From<diesel::result::Error> for ServiceError {
+    fn from(e: diesel::result::Error) -> Self {
+        Self::Database(DatabaseError::DieselError(e))
+    }
+}
+
+impl Services {
+    pub fn new(db: Database, lingva_url: String, twitch_api: Option<TwitchApi>) -> Self {
+        Self {
+            db,
+            owm_api: env::var("OWM_API_KEY").ok().map(OwmApi::init),
+            lastfm_api: env::var("LASTFM_API_KEY").ok().map(LastFMApi::init),
+            finnhub_api: env::var("FINNHUB_API_KEY").ok().map(FinnhubApi::init),
+            lingva_api: LingvaApi::init(lingva_url),
+            ukraine_alert_client: UkraineAlertClient::default(),
+            twitch_api,
+        }
+    }
+
+    /// Finds the user by a raw user identifier, or uses the fallback user if none is given.
+    pub async fn resolve_user(
+        &self,
+        identifier: Option<&str>,
+        fallback_user_id: u64,
+    ) -> Result<u64, ServiceError> {
+        match identifier {
+            Some(identifier) => {
+                let user_identifier = UserIdentifier::from_string(identifier)
+                    .map_err(|_| ServiceError::InvalidInput("invalid user".to_owned()))?;
+
+                Ok(self
+                    .db
+                    .run(move |db| db.get_user(&user_identifier))
+                    .await?
+                    .ok_or_else(|| ServiceError::InvalidInput("invalid user".to_owned()))?
+                    .id)
+            }
+            None => Ok(fallback_user_id),
+        }
+    }
+
+    /// Like `resolve_user`, but users that haven't been seen yet are created,
+    /// so that they can be given roles in advance.
+    pub async fn resolve_or_create_user(&self, identifier: &str) -> Result<u64, ServiceError> {
+        let user_identifier = UserIdentifier::from_string(identifier)
+            .map_err(|_| ServiceError::InvalidInput(format!("invalid user {identifier}")))?;
+
+        let user = self
+            .db
+            .run(move |db| db.get_or_create_user(&user_identifier))
+            .await?;
+
+        Ok(user.id)
+    }
+
+    /// Current weather in the place, or in the location of the user if no place is given.
+    pub async fn weather(&self, user_id: u64, place: Option<&str>) -> Result<String, ServiceError> {
+        let api = self
+            .owm_api
+            .as_ref()
+            .ok_or(ServiceError::NotConfigured("weather"))?;
+
+        let place = match place {
+            Some(place) => place.to_owned(),
+            None => self
+                .db
+                .run(move |db| db.get_location(user_id))
+                .await?
+                .ok_or_else(|| ServiceError::InvalidInput("location not set".to_owned()))?,
+        };
+
+        tracing::info!("Querying weather for {}", place);
+
+        let weather = api
+            .get_current(&place)
+            .await
+            .map_err(|e| ServiceError::InvalidInput(e.to_string()))?;
+
+        Ok(format!(
+            "{}, {}: {}°C",
+            weather.name,
+            weather.sys.country.unwrap_or_default(),
+            weather.main.temp
+        ))
+    }
+
+    /// Currently playing song from Last.FM, falling back to Spotify.
+    pub async fn now_playing(&self, user_id: u64) -> Result<String, ServiceError> {
+        match self.lastfm_now_playing(user_id).await {
+            Ok(song) => Ok(song),
+            Err(e) => {
+                tracing::info!("Last.FM Error: {}", e);
+
+                self.spotify_now_playing(user_id).await.map_err(|e| {
+                    tracing::info!("Spotify Error: {}", e);
+
+                    ServiceError::InvalidInput(format!(
+                        "No music source configured! Go to {}/profile to connect an account.",
+                        env::var("BASE_URL").expect("BASE_URL missing")
+                    ))
+                })
+            }
+        }
+    }
+
+    pub async fn lastfm_now_playing(&self, user_id: u64) -> Result<String, ServiceError> {
+        let lastfm_api = self
+            .lastfm_api
+            .as_ref()
+            .ok_or(ServiceError::NotConfigured("Last.FM"))?;
+
+        let username = self
+            .db
+            .run(move |db| db.get_lastfm_name(user_id))
+            .await?
+            .ok_or_else(|| ServiceError::InvalidInput("last.fm username not set!".to_owned()))?;
+
+        let response = lastfm_api
+            .get_recent_tracks(&username)
+            .await
+            .map_err(|e| ServiceError::Api("Last.FM", e))?;
+
+        Ok(
+            match response.recenttracks.track.iter().find(|track| {
+                if let Some(attr) = &track.attr {
+                    attr.nowplaying == "true"
+                } else {
+                    false
+                }
+            }) {
+                Some(current_track) => {
+                    format!("{} - {}", current_track.artist.text, current_track.name)
+                }
+                None => "No song is currently playing".to_string(),
+            },
+        )
+    }
+
+    pub async fn spotify_now_playing(&self, user_id: u64) -> Result<String, ServiceError> {
+        let spotify_api = self.get_spotify_api(user_id).await?;
+
+        match spotify_api
+            .get_current_song()
+            .await
+            .map_err(|e| ServiceError::Api("Spotify API", e))?
+        {
+            Some(playback) => {
+                let position = playback.progress_ms / 1000;
+                let position = format!("{}:{:02}", position / 60, position % 60);
+
+                let length = playback.item.duration_ms / 1000;
+                let length = format!("{}:{:02}", length / 60, length % 60);
+
+                let artist = playback
+                    .item
+                    .artists
+                    .iter()
+                    .map(|artist| artist.name.as_str())
+                    .collect::<Vec<&str>>()
+                    .join(" ");
+
+                Ok(format!(
+                    "{} - {} [{}/{}]",
+                    artist, playback.item.name, position, length
+                ))
+            }
+            None => Ok("No song is currently playing".to_owned()),
+        }
+    }
+
+    pub async fn spotify_playlist(&self, user_id: u64) -> Result<String, ServiceError> {
+        let spotify_api = self.get_spotify_api(user_id).await?;
+
+        match spotify_api
+            .get_current_song()
+            .await
+            .map_err(|e| ServiceError::Api("Spotify API", e))?
+        {
+            Some(playback) => Ok(match playback.context {
+                Some(context) => context.external_urls.spotify,
+                None => "not currently listening to a playlist".to_string(),
+            }),
+            None => Ok("No song is currently playing".to_owned()),
+        }
+    }
+
+    pub async fn spotify_last_played(&self, user_id: u64) -> Result<String, ServiceError> {
+        let spotify_api = self.get_spotify_api(user_id).await?;
+
+        spotify_api
+            .get_recently_played()
+            .await
+            .map_err(|e| ServiceError::Api("Spotify API", e.into()))
+    }
+
+    async fn get_spotify_api(&self, user_id: u64) -> Result<SpotifyApi, ServiceError> {
+        tracing::info!("Looking for spotify token for user ID {}", user_id);
+
+        let access_token = self
+            .db
+            .run(move |db| db.get_spotify_access_token(user_id))
+            .await?
+            .ok_or_else(|| {
+                ServiceError::InvalidInput(format!(
+                    "Not configured for user! You can set up Spotify by going to {}/profile",
+                    env::var("BASE_URL").unwrap()
+                ))
+            })?;
+
+        Ok(SpotifyApi::new(&access_token))
+    }
+
+    pub async fn translate(
+        &self,
+        text: &str,
+        source: &str,
+        target: &str,
+    ) -> Result<String, ServiceError> {
+        tracing::info!("Translating text {}", text);
+
+        self.lingva_api
+            .translate(source, target, text)
+            .await
+            .map_err(|e| ServiceError::Api("Translation", e))
+    }
+
+    pub async fn stock_quote(&self, symbol: &str) -> Result<String, ServiceError> {
+        let finnhub_api = self
+            .finnhub_api
+            .as_ref()
+            .ok_or(ServiceError::NotConfigured("stock"))?;
+
+        let quote = finnhub_api
+            .quote(symbol)
+            .await
+            .map_err(|e| ServiceError::Api("Finnhub", e))?;
+
+        Ok(format!(
+            "{} ({})",
+            quote.current_price,
+            quote.percent_change.unwrap_or(0.0)
+        ))
+    }
+
+    pub async fn ukraine_alerts(&self) -> Result<String, ServiceError> {
+        let regions = self
+            .ukraine_alert_client
+            .get_alerts()
+            .await
+            .map_err(|e| ServiceError::Api("Ukraine alerts", e.into()))?;
+
+        Ok(regions
+            .into_iter()
+            .map(|region| {
+                let name = region.region_name;
+                let alerts = region
+                    .active_alerts
+                    .iter()
+                    .map(|alert| alert.r#type.as_ref())
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                format!("{name}: {alerts}")
+            })
+            .collect::<Vec<_>>()
+            .join(";"))
+    }
+
+    /// Looks up a Twitch user by login, or the user with the given Twitch ID if there is no login.
+    pub async fn twitch_user(
+        &self,
+        login: Option<&str>,
+        twitch_id: Option<&str>,
+    ) -> Result<serde_json::Value, ServiceError> {
+        let twitch_api = self
+            .twitch_api
+            .as_ref()
+            .ok_or(ServiceError::NotConfigured("Twitch"))?;
+
+        let (logins, ids) = match (login, twitch_id) {
+            (Some(login), _) => (vec![login], vec![]),
+            (None, Some(twitch_id)) => (vec![], vec![twitch_id]),
+            (None, None) => {
+                return Err(ServiceError::InvalidInput("user not specified".to_owned()))
+            }
+        };
+
+        let users = twitch_api
+            .helix_api
+            .get_users(Some(&logins), Some(&ids))
+            .await
+            .map_err(|e| ServiceError::Api("Twitch API", e))?;
+
+        let user = users
+            .first()
+            .ok_or_else(|| ServiceError::InvalidInput("user not found".to_owned()))?;
+
+        tracing::debug!("Twitch user: {:?}", user);
+
+        serde_json::to_value(user).map_err(|e| ServiceError::Api("Twitch API", anyhow!(e)))
+    }
+}
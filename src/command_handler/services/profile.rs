@@ -0,0 +1,151 @@
+use super::{ServiceError, Services};
+use crate::command_handler::eval::time::{format_timestamp, Timezone, DEFAULT_FORMAT};
+use crate::database::models::{ProfileKey, ProfileValue};
+use chrono::{NaiveDate, Utc};
+
+/// Limit of the `user_data.value` column
+const MAX_VALUE_LENGTH: usize = 255;
+const MAX_PRONOUNS_LENGTH: usize = 32;
+
+impl Services {
+    /// Validates and saves a value of the user's profile.
+    pub async fn set_profile_value(
+        &self,
+        user_id: u64,
+        key: ProfileKey,
+        value: &str,
+        public: bool,
+    ) -> Result<(), ServiceError> {
+        let value = normalize_profile_value(key, value).map_err(ServiceError::InvalidInput)?;
+
+        Ok(self
+            .db
+            .run(move |db| db.set_profile_value(user_id, key, &value, public))
+            .await?)
+    }
+
+    pub async fn unset_profile_value(
+        &self,
+        user_id: u64,
+        key: ProfileKey,
+    ) -> Result<(), ServiceError> {
+        Ok(self
+            .db
+            .run(move |db| db.remove_user_data(user_id, key.data_name()))
+            .await?)
+    }
+
+    /// Private values are only shown to the user they belong to.
+    pub async fn profile_value(
+        &self,
+        user_id: u64,
+        key: ProfileKey,
+        requesting_user_id: u64,
+    ) -> Result<Option<ProfileValue>, ServiceError> {
+        Ok(self
+            .db
+            .run(move |db| db.get_profile_value(user_id, key))
+            .await?
+            .filter(|value| value.public || user_id == requesting_user_id))
+    }
+
+    /// Current time in the timezone of the user, or in UTC if it's not set.
+    pub async fn current_time(
+        &self,
+        user_id: u64,
+        format: Option<&str>,
+    ) -> Result<String, ServiceError> {
+        let timezone = self.db.run(move |db| db.get_timezone(user_id)).await?;
+
+        format_timestamp(
+            Utc::now().timestamp_millis(),
+            format.unwrap_or(DEFAULT_FORMAT),
+            timezone.as_deref().unwrap_or("UTC"),
+        )
+        .map_err(ServiceError::InvalidInput)
+    }
+}
+
+fn normalize_profile_value(key: ProfileKey, value: &str) -> Result<String, String> {
+    let value = value.trim();
+
+    if value.is_empty() {
+        return Err(format!("{key} can't be empty"));
+    }
+    if value.len() > MAX_VALUE_LENGTH {
+        return Err(format!("{key} is too long"));
+    }
+
+    match key {
+        ProfileKey::Location => Ok(value.to_owned()),
+        ProfileKey::LastFM => match value.contains(char::is_whitespace) {
+            true => Err("invalid last.fm username".to_owned()),
+            false => Ok(value.to_owned()),
+        },
+        // Named timezones are stored by their name, so that the time follows daylight saving changes
+        ProfileKey::Timezone => {
+            let timezone = Timezone::parse(value.trim_start_matches("UTC").trim())
+                .or_else(|_| Timezone::parse(value))?;
+            Ok(timezone.to_string())
+        }
+        ProfileKey::Pronouns => match value.len() > MAX_PRONOUNS_LENGTH {
+            true => Err("pronouns are too long".to_owned()),
+            false => Ok(value.to_owned()),
+        },
+        ProfileKey::Birthday => parse_birthday(value)
+            .ok_or_else(|| "invalid birthday, expected YYYY-MM-DD or MM-DD".to_owned()),
+    }
+}
+
+/// The year is optional
+fn parse_birthday(value: &str) -> Option<String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some(date.format("%Y-%m-%d").to_string());
+    }
+
+    // Parsed with a leap year, so February 29th is accepted
+    NaiveDate::parse_from_str(&format!("2000-{value}"), "%Y-%m-%d")
+        .ok()
+        .map(|date| date.format("%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_profile_value;
+    use crate::database::models::ProfileKey;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn timezones() {
+        assert_eq!(
+            normalize_profile_value(ProfileKey::Timezone, "UTC+2").as_deref(),
+            Ok("+02:00")
+        );
+        assert_eq!(
+            normalize_profile_value(ProfileKey::Timezone, "-05:30").as_deref(),
+            Ok("-05:30")
+        );
+        assert_eq!(
+            normalize_profile_value(ProfileKey::Timezone, "UTC").as_deref(),
+            Ok("+00:00")
+        );
+        assert_eq!(
+            normalize_profile_value(ProfileKey::Timezone, "Europe/Riga").as_deref(),
+            Ok("Europe/Riga")
+        );
+        assert!(normalize_profile_value(ProfileKey::Timezone, "Riga").is_err());
+    }
+
+    #[test]
+    fn birthdays() {
+        assert_eq!(
+            normalize_profile_value(ProfileKey::Birthday, "2000-1-5").as_deref(),
+            Ok("2000-01-05")
+        );
+        assert_eq!(
+            normalize_profile_value(ProfileKey::Birthday, "02-29").as_deref(),
+            Ok("02-29")
+        );
+        assert!(normalize_profile_value(ProfileKey::Birthday, "13-01").is_err());
+    }
+}
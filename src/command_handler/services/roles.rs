@@ -0,0 +1,111 @@
+use super::{ServiceError, Services};
+use crate::database::{models::GlobalRole, DatabaseError};
+use crate::platform::UserIdentifier;
+
+/// Set once the users from the deprecated `BLOCKED_USERS` variable have been blocked
+const BLOCKED_USERS_IMPORTED: &str = "blocked_users_imported";
+
+impl Services {
+    /// Gives the user a role that applies in every channel.
+    pub async fn set_global_role(
+        &self,
+        user_id: u64,
+        role: GlobalRole,
+    ) -> Result<(), ServiceError> {
+        let is_admin = self.db.run(move |db| db.is_admin(user_id)).await?;
+
+        // Otherwise admins could lock each other out
+        if role == GlobalRole::Blocked && is_admin {
+            return Err(ServiceError::InvalidInput(
+                "admins can't be blocked".to_owned(),
+            ));
+        }
+
+        Ok(self
+            .db
+            .run(move |db| db.set_global_role(user_id, role))
+            .await?)
+    }
+
+    pub async fn remove_global_role(&self, user_id: u64) -> Result<(), ServiceError> {
+        self.db
+            .run(move |db| db.remove_global_role(user_id))
+            .await
+            .map_err(|e| match e {
+                DatabaseError::InvalidValue => {
+                    ServiceError::InvalidInput("the user has no role".to_owned())
+                }
+                e => e.into(),
+            })
+    }
+
+    pub async fn block_user(&self, identifier: &str) -> Result<(), ServiceError> {
+        let user_id = self.resolve_or_create_user(identifier).await?;
+
+        self.set_global_role(user_id, GlobalRole::Blocked).await
+    }
+
+    pub async fn unblock_user(&self, identifier: &str) -> Result<(), ServiceError> {
+        let user_identifier = UserIdentifier::from_string(identifier)
+            .map_err(|_| ServiceError::InvalidInput(format!("invalid user {identifier}")))?;
+
+        let role = self
+            .db
+            .run(move |db| match db.get_user(&user_identifier)? {
+                Some(user) => Ok(Some((user.id, db.get_global_role(user.id)?))),
+                None => Ok(None),
+            })
+            .await?;
+
+        match role {
+            Some((user_id, Some(GlobalRole::Blocked))) => self.remove_global_role(user_id).await,
+            _ => Err(ServiceError::InvalidInput(
+                "the user is not blocked".to_owned(),
+            )),
+        }
+    }
+
+    /// Blocks the users from the deprecated `BLOCKED_USERS` variable, only the first time it is seen.
+    /// Afterwards they are managed with the block commands, so unblocked users stay unblocked.
+    /// Users that already have a global role keep it.
+    pub async fn import_blocked_users(&self, blocked_users: &str) -> Result<(), ServiceError> {
+        if self
+            .db
+            .run(|db| db.get_auth(BLOCKED_USERS_IMPORTED))
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        for identifier in blocked_users.split(',') {
+            let user_id = match self.resolve_or_create_user(identifier).await {
+                Ok(user_id) => user_id,
+                Err(ServiceError::InvalidInput(msg)) => {
+                    tracing::warn!("Not blocking {identifier}: {msg}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if let Some(role) = self.db.run(move |db| db.get_global_role(user_id)).await? {
+                tracing::info!("Not blocking {identifier}, they already have the role {role}");
+                continue;
+            }
+
+            match self.set_global_role(user_id, GlobalRole::Blocked).await {
+                Ok(()) => tracing::info!("Blocked {identifier}"),
+                Err(ServiceError::InvalidInput(msg)) => {
+                    tracing::warn!("Not blocking {identifier}: {msg}")
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.db
+            .run(|db| db.set_auth(BLOCKED_USERS_IMPORTED, "1"))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use std::{
 
 use anyhow::{anyhow, Context};
 use http::{HeaderMap, Method};
-use reqwest::{Client, RequestBuilder};
+use reqwest::{Client, RequestBuilder, StatusCode};
 use serde_json::{json, Value};
 use tokio::task;
 use tracing::debug;
@@ -17,15 +17,20 @@ use super::{
     eventsub::{EventSubSubscription, EventSubSubscriptionResponse, EventSubSubscriptionType},
     get_client_id,
     model::*,
+    moderation::ModerationAction,
 };
 
 pub const HELIX_URL: &str = "https://api.twitch.tv/helix";
+/// Twitch truncates whispers to users who have never whispered the bot to this length,
+/// and there is no way to tell whether a user has.
+pub const WHISPER_LENGTH_LIMIT: usize = 500;
 
 #[derive(Clone, Debug)]
 pub struct HelixApi<C: LoginCredentials> {
     client: Client,
     pub credentials: C,
     users_cache: Arc<RwLock<Vec<User>>>,
+    scopes_cache: Arc<RwLock<Option<(String, Vec<String>)>>>, // Token and its scopes
     headers: HeaderMap,
 }
 
@@ -42,6 +47,7 @@ impl<C: LoginCredentials> HelixApi<C> {
             client: Client::new(),
             credentials,
             users_cache: Arc::new(RwLock::new(Vec::new())),
+            scopes_cache: Arc::new(RwLock::new(None)),
             headers,
         };
 
@@ -92,6 +98,14 @@ impl<C: LoginCredentials> HelixApi<C> {
         self.request(Method::DELETE, path).await
     }
 
+    async fn put(&self, path: &str) -> anyhow::Result<RequestBuilder> {
+        self.request(Method::PUT, path).await
+    }
+
+    async fn patch(&self, path: &str) -> anyhow::Result<RequestBuilder> {
+        self.request(Method::PATCH, path).await
+    }
+
     pub async fn get_users(
         &self,
         logins: Option<&[&str]>,
@@ -156,15 +170,64 @@ impl<C: LoginCredentials> HelixApi<C> {
             .ok_or_else(|| anyhow!("User not found"))
     }
 
-    /*async fn get_token(&self) -> anyhow::Result<String> {
-        Ok(self
-            .credentials
+    async fn get_token(&self) -> anyhow::Result<String> {
+        self.credentials
             .get_credentials()
             .await
             .map_err(|e| anyhow!("Unable to get credentials: {:?}", e))?
             .token
-            .ok_or_else(|| anyhow!("Token missing"))?)
-    }*/
+            .ok_or_else(|| anyhow!("Token missing"))
+    }
+
+    pub async fn validate_token(&self) -> anyhow::Result<ValidationResponse> {
+        let token = self.get_token().await?;
+
+        let response = self
+            .client
+            .get("https://id.twitch.tv/oauth2/validate")
+            .header("Authorization", format!("OAuth {token}"))
+            .send()
+            .await?;
+
+        response_ok(&response)?;
+
+        Ok(response.json().await?)
+    }
+
+    /// Checks that the current token has been granted the given scope.
+    /// The scopes are cached until the token changes (e.g. gets refreshed).
+    pub async fn ensure_scope(&self, scope: &str) -> anyhow::Result<()> {
+        let token = self.get_token().await?;
+
+        let cached_scopes = {
+            let scopes_cache = self.scopes_cache.read().unwrap();
+
+            scopes_cache
+                .as_ref()
+                .filter(|(cached_token, _)| *cached_token == token)
+                .map(|(_, scopes)| scopes.clone())
+        };
+
+        let scopes = match cached_scopes {
+            Some(scopes) => scopes,
+            None => {
+                let scopes = self.validate_token().await?.scopes;
+
+                let mut scopes_cache = self.scopes_cache.write().unwrap();
+                *scopes_cache = Some((token, scopes.clone()));
+
+                scopes
+            }
+        };
+
+        if scopes.iter().any(|granted| granted == scope) {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "the bot is missing the {scope} scope, it needs to be reauthenticated"
+            ))
+        }
+    }
 
     pub async fn get_self_user(&self) -> anyhow::Result<User> {
         Ok(self
@@ -283,16 +346,22 @@ impl<C: LoginCredentials> HelixApi<C> {
         broadcaster_id: &str,
         user_id: &str,
         duration: Option<i32>,
+        reason: Option<&str>,
     ) -> anyhow::Result<()> {
         let self_id = self.get_self_user().await?.id;
         debug!("Self id: {self_id}");
 
-        let payload = json!({
+        let mut payload = json!({
             "data": {
                 "user_id": user_id,
-                "duration": duration,
             }
         });
+        if let Some(duration) = duration {
+            payload["data"]["duration"] = json!(duration);
+        }
+        if let Some(reason) = reason {
+            payload["data"]["reason"] = json!(reason);
+        }
         debug!("Timeout payload: {payload}");
 
         let response = self
@@ -320,8 +389,205 @@ impl<C: LoginCredentials> HelixApi<C> {
         let users = self.get_users(Some(&[user_name]), None).await?;
         debug!("Fetched user info");
         let user = users.first().context("Empty users response")?;
-        self.ban_user(broadcaster_id, &user.id, duration).await
+        self.ban_user(broadcaster_id, &user.id, duration, None)
+            .await
+    }
+
+    pub async fn unban_user(&self, broadcaster_id: &str, user_id: &str) -> anyhow::Result<()> {
+        let self_id = self.get_self_user().await?.id;
+
+        let response = self
+            .delete("/moderation/bans")
+            .await?
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &self_id),
+                ("user_id", user_id),
+            ])
+            .send()
+            .await?;
+
+        response_ok(&response)
+    }
+
+    /// Deletes a single message, or all messages in the chat if `message_id` is not specified.
+    pub async fn delete_chat_messages(
+        &self,
+        broadcaster_id: &str,
+        message_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let self_id = self.get_self_user().await?.id;
+
+        let mut request = self.delete("/moderation/chat").await?.query(&[
+            ("broadcaster_id", broadcaster_id),
+            ("moderator_id", &self_id),
+        ]);
+
+        if let Some(message_id) = message_id {
+            request = request.query(&[("message_id", message_id)]);
+        }
+
+        response_ok(&request.send().await?)
     }
+
+    pub async fn update_shield_mode_status(
+        &self,
+        broadcaster_id: &str,
+        is_active: bool,
+    ) -> anyhow::Result<ShieldModeStatus> {
+        let self_id = self.get_self_user().await?.id;
+
+        let response = self
+            .put("/moderation/shield_mode")
+            .await?
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &self_id),
+            ])
+            .json(&json!({ "is_active": is_active }))
+            .send()
+            .await?;
+
+        response_ok(&response)?;
+
+        let mut data = response
+            .json::<GenericHelixResponse<ShieldModeStatus>>()
+            .await?;
+
+        data.data.pop().context("Empty shield mode response")
+    }
+
+    pub async fn update_chat_settings(
+        &self,
+        broadcaster_id: &str,
+        settings: &ChatSettingsUpdate,
+    ) -> anyhow::Result<()> {
+        let self_id = self.get_self_user().await?.id;
+        debug!("Updating chat settings in {broadcaster_id}: {settings:?}");
+
+        let response = self
+            .patch("/chat/settings")
+            .await?
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &self_id),
+            ])
+            .json(settings)
+            .send()
+            .await?;
+
+        response_ok(&response)
+    }
+
+    pub async fn send_chat_announcement(
+        &self,
+        broadcaster_id: &str,
+        message: &str,
+        color: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let self_id = self.get_self_user().await?.id;
+
+        let response = self
+            .post("/chat/announcements")
+            .await?
+            .query(&[
+                ("broadcaster_id", broadcaster_id),
+                ("moderator_id", &self_id),
+            ])
+            .json(&json!({
+                "message": message,
+                "color": color.unwrap_or("primary"),
+            }))
+            .send()
+            .await?;
+
+        response_ok(&response)
+    }
+
+    /// Performs a moderation action in the given channel, acting as the user the credentials belong to.
+    ///
+    /// Use the credentials of the broadcaster (see [`super::broadcaster_helix_api`]), so that the
+    /// actions are limited to the scopes they have granted.
+    pub async fn moderate(
+        &self,
+        broadcaster_id: &str,
+        action: &ModerationAction,
+    ) -> anyhow::Result<()> {
+        self.ensure_scope(action.required_scope()).await?;
+
+        match action {
+            ModerationAction::Ban { user, reason } => {
+                let users = self.get_users(Some(&[user.as_str()]), None).await?;
+                let user = users.first().context("User not found")?;
+
+                self.ban_user(broadcaster_id, &user.id, None, reason.as_deref())
+                    .await
+            }
+            ModerationAction::Unban { user } => {
+                let users = self.get_users(Some(&[user.as_str()]), None).await?;
+                let user = users.first().context("User not found")?;
+
+                self.unban_user(broadcaster_id, &user.id).await
+            }
+            ModerationAction::DeleteMessage { message_id } => {
+                self.delete_chat_messages(broadcaster_id, Some(message_id))
+                    .await
+            }
+            ModerationAction::ClearChat => self.delete_chat_messages(broadcaster_id, None).await,
+            ModerationAction::ShieldMode(is_active) => self
+                .update_shield_mode_status(broadcaster_id, *is_active)
+                .await
+                .map(|_| ()),
+            ModerationAction::ChatSettings(settings) => {
+                self.update_chat_settings(broadcaster_id, settings).await
+            }
+            ModerationAction::Announcement { message, color } => {
+                self.send_chat_announcement(broadcaster_id, message, color.as_deref())
+                    .await
+            }
+        }
+    }
+
+    /// Sends a whisper from the user the credentials belong to.
+    pub async fn send_whisper(&self, to_user_id: &str, message: &str) -> Result<(), WhisperError> {
+        self.ensure_scope("user:manage:whispers").await?;
+
+        let self_id = self.get_self_user().await?.id;
+
+        let message: String = message.chars().take(WHISPER_LENGTH_LIMIT).collect();
+
+        let response = self
+            .post("/whispers")
+            .await?
+            .query(&[
+                ("from_user_id", self_id.as_str()),
+                ("to_user_id", to_user_id),
+            ])
+            .json(&json!({ "message": message }))
+            .send()
+            .await
+            .map_err(anyhow::Error::from)?;
+
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let text = response.text().await.unwrap_or_default();
+
+            if text.contains("verified phone number") {
+                return Err(WhisperError::PhoneNotVerified);
+            }
+
+            return Err(anyhow!("Failed to send whisper: {text}").into());
+        }
+
+        Ok(response_ok(&response)?)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WhisperError {
+    #[error("the bot account needs a verified phone number to send whispers")]
+    PhoneNotVerified,
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
 }
 
 impl HelixApi<StaticLoginCredentials> {
@@ -1,13 +1,15 @@
 pub mod eventsub;
 pub mod helix;
 pub mod model;
+pub mod moderation;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::sync::Mutex;
 
+use anyhow::{anyhow, Context};
 use reqwest::Client;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::task;
@@ -15,10 +17,11 @@ use tokio::task;
 use model::*;
 use twitch_irc::login::{LoginCredentials, RefreshingLoginCredentials, StaticLoginCredentials};
 
-use crate::database::Database;
-use crate::platform::twitch;
+use crate::api::response_ok;
+use crate::database::{credentials::Credentials, Database};
+use crate::platform::{twitch, Permissions};
 
-use self::helix::HelixApi;
+use self::helix::{HelixApi, HELIX_URL};
 
 const APP_SCOPES: &[&str] = &["moderation:read", "channel:moderate", "chat:edit"];
 
@@ -27,7 +30,8 @@ pub struct TwitchApi<C: LoginCredentials + Clone> {
     pub helix_api: HelixApi<C>,
     pub helix_api_app: HelixApi<StaticLoginCredentials>,
     pub chat_sender: Arc<Mutex<Option<UnboundedSender<twitch::SenderMessage>>>>,
-    moderators_cache: Arc<RwLock<HashMap<String, Vec<String>>>>,
+    pub queue_metrics: Arc<twitch::QueueMetrics>,
+    roles_cache: Arc<RwLock<HashMap<String, ChannelRoles>>>, // Broadcaster id and roles
     client: Client,
 }
 
@@ -61,7 +65,8 @@ impl<C: LoginCredentials + Clone> TwitchApi<C> {
             .await,
             client: Client::new(),
             chat_sender: Arc::new(Mutex::new(None)),
-            moderators_cache: Arc::new(RwLock::new(HashMap::new())),
+            queue_metrics: Arc::new(twitch::QueueMetrics::default()),
+            roles_cache: Arc::new(RwLock::new(HashMap::new())),
         };
 
         twitch_api.start_cron().await;
@@ -97,17 +102,17 @@ impl<C: LoginCredentials + Clone> TwitchApi<C> {
     }
 
     pub async fn start_cron(&self) {
-        let moderators_cache = self.moderators_cache.clone();
+        let roles_cache = self.roles_cache.clone();
 
         task::spawn(async move {
             loop {
                 tokio::time::sleep(Duration::from_secs(600)).await;
 
-                tracing::info!("Clearing moderators cache");
+                tracing::info!("Clearing channel roles cache");
 
-                let mut moderators_cache = moderators_cache.write().expect("Failed to lock");
+                let mut roles_cache = roles_cache.write().expect("Failed to lock");
 
-                moderators_cache.clear();
+                roles_cache.clear();
             }
         });
     }
@@ -127,19 +132,127 @@ impl<C: LoginCredentials + Clone> TwitchApi<C> {
         self.headers.get("Client-Id").unwrap().to_str().unwrap()
     }*/
 
-    pub async fn get_channel_mods(
+    /// Returns the moderators and VIPs of a channel.
+    /// Uses the broadcaster's token if they have authorized the bot, otherwise falls back to ivr.fi.
+    pub async fn get_channel_roles(
         &self,
-        channel_login: &str,
-    ) -> Result<Vec<String>, reqwest::Error> {
-        // This is not very idiomatic but i couldnt figure out how to make it work otherwise
+        db: &Database,
+        broadcaster_id: &str,
+    ) -> anyhow::Result<ChannelRoles> {
         {
-            let moderators_cache = self.moderators_cache.read().unwrap();
+            let roles_cache = self.roles_cache.read().unwrap();
+
+            if let Some(roles) = roles_cache.get(broadcaster_id) {
+                return Ok(roles.clone());
+            }
+        }
+
+        let roles = match self.get_channel_roles_helix(db, broadcaster_id).await {
+            Ok(roles) => roles,
+            Err(e) => {
+                tracing::debug!("Could not get roles in {broadcaster_id} from Helix: {e}");
+                self.get_channel_roles_ivr(broadcaster_id).await?
+            }
+        };
+
+        tracing::debug!("Roles in {broadcaster_id}: {roles:?}");
+
+        let mut roles_cache = self.roles_cache.write().unwrap();
+        roles_cache.insert(broadcaster_id.to_owned(), roles.clone());
+
+        Ok(roles)
+    }
+
+    /// Drops the cached roles of a channel if they don't match what was observed in chat.
+    pub fn sync_channel_role(&self, broadcaster_id: &str, user_id: &str, permissions: Permissions) {
+        let mut roles_cache = self.roles_cache.write().unwrap();
+
+        if let Some(roles) = roles_cache.get(broadcaster_id) {
+            let cached_permissions = roles.get_permissions(user_id);
+
+            if permissions < Permissions::ChannelOwner && cached_permissions != permissions {
+                tracing::info!("Roles in {broadcaster_id} are outdated, invalidating cache");
+                roles_cache.remove(broadcaster_id);
+            }
+        }
+    }
+
+    pub fn invalidate_channel_roles(&self, broadcaster_id: &str) {
+        self.roles_cache.write().unwrap().remove(broadcaster_id);
+    }
+
+    async fn get_channel_roles_helix(
+        &self,
+        db: &Database,
+        broadcaster_id: &str,
+    ) -> anyhow::Result<ChannelRoles> {
+        let credentials = RefreshingLoginCredentials::init(
+            get_client_id().context("Client ID missing")?,
+            get_client_secret().context("Client secret missing")?,
+            db.make_twitch_credentials(broadcaster_id.to_owned()),
+        );
+
+        let token = credentials
+            .get_credentials()
+            .await
+            .map_err(|_| anyhow!("broadcaster has not authorized the bot"))?
+            .token
+            .context("Token missing")?;
+
+        Ok(ChannelRoles {
+            moderators: self
+                .get_channel_members(&token, "/moderation/moderators", broadcaster_id)
+                .await?,
+            vips: self
+                .get_channel_members(&token, "/channels/vips", broadcaster_id)
+                .await?,
+        })
+    }
 
-            if let Some(mods) = moderators_cache.get(channel_login) {
-                return Ok(mods.clone());
+    async fn get_channel_members(
+        &self,
+        token: &str,
+        path: &str,
+        broadcaster_id: &str,
+    ) -> anyhow::Result<HashSet<String>> {
+        let client_id = get_client_id().context("Client ID missing")?;
+        let mut members = HashSet::new();
+        let mut cursor = None;
+
+        loop {
+            let mut request = self
+                .client
+                .get(format!("{HELIX_URL}{path}"))
+                .header("Client-Id", &client_id)
+                .bearer_auth(token)
+                .query(&[("broadcaster_id", broadcaster_id), ("first", "100")]);
+
+            if let Some(cursor) = &cursor {
+                request = request.query(&[("after", cursor)]);
+            }
+
+            let response = request.send().await?;
+
+            tracing::info!("GET {}: {}", response.url(), response.status());
+
+            response_ok(&response)?;
+
+            let page: PaginatedHelixResponse<ChannelMember> = response.json().await?;
+
+            members.extend(page.data.into_iter().map(|member| member.user_id));
+
+            match page.pagination.cursor {
+                Some(next_cursor) => cursor = Some(next_cursor),
+                None => break,
             }
         }
 
+        Ok(members)
+    }
+
+    async fn get_channel_roles_ivr(&self, broadcaster_id: &str) -> anyhow::Result<ChannelRoles> {
+        let channel_login = self.helix_api.get_user_by_id(broadcaster_id).await?.login;
+
         let response = self
             .client
             .get(format!(
@@ -151,24 +264,20 @@ impl<C: LoginCredentials + Clone> TwitchApi<C> {
 
         tracing::info!("GET {}: {}", response.url(), response.status());
 
-        let lookup = response.json::<IvrModInfo>().await?;
-
-        let mut mods = vec![channel_login.to_owned()];
-
-        for moderator in lookup.mods {
-            mods.push(moderator.login);
-        }
-
-        tracing::debug!("{:?}", mods);
-
-        // Err(_) => self.get_channel_mods_from_irc(channel_login).await?,
+        response_ok(&response)?;
 
-        let mut moderators_cache = self.moderators_cache.write().unwrap();
-
-        moderators_cache.insert(channel_login.to_string(), mods.clone());
+        let lookup = response.json::<IvrModInfo>().await?;
 
-        Ok(mods)
+        Ok(ChannelRoles {
+            moderators: lookup
+                .mods
+                .into_iter()
+                .map(|moderator| moderator.id)
+                .collect(),
+            vips: lookup.vips.into_iter().map(|vip| vip.id).collect(),
+        })
     }
+
     // This terrible abomination has to exist because twitch doesn't provide an endpoint for this that doesn't require channel auth
     // /// Returns the list of logins of channel moderators. Don't expect this to be efficient
     /*async fn get_channel_mods_from_irc(
@@ -219,6 +328,25 @@ impl<C: LoginCredentials + Clone> TwitchApi<C> {
     }*/
 }
 
+/// Helix API acting as the broadcaster, with the token they authorized the bot with.
+pub async fn broadcaster_helix_api(
+    db: &Database,
+    broadcaster_id: &str,
+) -> anyhow::Result<HelixApi<RefreshingLoginCredentials<Credentials>>> {
+    let credentials = RefreshingLoginCredentials::init(
+        get_client_id().context("Client ID missing")?,
+        get_client_secret().context("Client secret missing")?,
+        db.make_twitch_credentials(broadcaster_id.to_owned()),
+    );
+
+    credentials
+        .get_credentials()
+        .await
+        .map_err(|_| anyhow!("broadcaster has not authorized the bot"))?;
+
+    Ok(HelixApi::with_credentials(credentials).await)
+}
+
 pub fn get_client_id() -> Option<String> {
     env::var("TWITCH_CLIENT_ID").ok()
 }
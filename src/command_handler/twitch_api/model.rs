@@ -1,3 +1,6 @@
+use std::collections::HashSet;
+
+use crate::platform::Permissions;
 use serde::{Deserialize, Serialize};
 
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -136,3 +139,67 @@ pub struct UserBanInfo {
     pub created_at: String,
     pub end_time: String,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatSettingsUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub emote_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follower_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub follower_mode_duration: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_mode: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_mode_wait_time: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscriber_mode: Option<bool>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShieldModeStatus {
+    pub is_active: bool,
+    pub moderator_id: String,
+    pub moderator_login: String,
+    pub moderator_name: String,
+    pub last_activated_at: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PaginatedHelixResponse<T> {
+    pub data: Vec<T>,
+    #[serde(default)]
+    pub pagination: Pagination,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Pagination {
+    pub cursor: Option<String>,
+}
+
+/// An entry in the moderators or VIPs list of a channel
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelMember {
+    pub user_id: String,
+    pub user_login: String,
+    pub user_name: String,
+}
+
+/// User ids of the moderators and VIPs in a channel
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ChannelRoles {
+    pub moderators: HashSet<String>,
+    pub vips: HashSet<String>,
+}
+
+impl ChannelRoles {
+    pub fn get_permissions(&self, user_id: &str) -> Permissions {
+        if self.moderators.contains(user_id) {
+            Permissions::ChannelMod
+        } else if self.vips.contains(user_id) {
+            Permissions::Vip
+        } else {
+            Permissions::Default
+        }
+    }
+}
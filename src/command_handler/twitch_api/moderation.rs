@@ -0,0 +1,178 @@
+use super::model::ChatSettingsUpdate;
+
+/// A moderation action that can be performed in a Twitch channel through Helix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModerationAction {
+    Ban {
+        user: String,
+        reason: Option<String>,
+    },
+    Unban {
+        user: String,
+    },
+    DeleteMessage {
+        message_id: String,
+    },
+    ClearChat,
+    ShieldMode(bool),
+    ChatSettings(ChatSettingsUpdate),
+    Announcement {
+        message: String,
+        color: Option<String>,
+    },
+}
+
+impl ModerationAction {
+    /// Builds an action from its name and space-separated arguments, as used by the template helpers and Hebi.
+    pub fn from_params(name: &str, params: Vec<String>) -> Result<Self, String> {
+        let mut params = params.into_iter();
+
+        match name {
+            "ban" => {
+                let user = params.next().ok_or("user name not specified")?;
+                let reason = params.collect::<Vec<_>>().join(" ");
+
+                Ok(Self::Ban {
+                    user,
+                    reason: (!reason.is_empty()).then_some(reason),
+                })
+            }
+            "unban" => Ok(Self::Unban {
+                user: params.next().ok_or("user name not specified")?,
+            }),
+            "delete" => Ok(Self::DeleteMessage {
+                message_id: params.next().ok_or("message id not specified")?,
+            }),
+            "clear" => Ok(Self::ClearChat),
+            "shield" => Ok(Self::ShieldMode(parse_toggle(params.next())?)),
+            "slow" => {
+                let wait_time = params.next().ok_or("slow mode wait time not specified")?;
+                let wait_time = parse_duration(Some(wait_time), 3..=120)?;
+
+                Ok(Self::ChatSettings(ChatSettingsUpdate {
+                    slow_mode: Some(wait_time.is_some()),
+                    slow_mode_wait_time: wait_time,
+                    ..Default::default()
+                }))
+            }
+            "emoteonly" => Ok(Self::ChatSettings(ChatSettingsUpdate {
+                emote_mode: Some(parse_toggle(params.next())?),
+                ..Default::default()
+            })),
+            "followersonly" => {
+                let duration = parse_duration(params.next(), 0..=129600)?;
+
+                Ok(Self::ChatSettings(ChatSettingsUpdate {
+                    follower_mode: Some(duration.is_some()),
+                    follower_mode_duration: duration,
+                    ..Default::default()
+                }))
+            }
+            "subonly" => Ok(Self::ChatSettings(ChatSettingsUpdate {
+                subscriber_mode: Some(parse_toggle(params.next())?),
+                ..Default::default()
+            })),
+            "announce" => {
+                let message = params.collect::<Vec<_>>().join(" ");
+
+                if message.is_empty() {
+                    return Err("announcement message not specified".to_owned());
+                }
+
+                Ok(Self::Announcement {
+                    message,
+                    color: None,
+                })
+            }
+            other => Err(format!("unknown moderation action {other}")),
+        }
+    }
+
+    /// The OAuth scope the moderator token needs for this action.
+    pub fn required_scope(&self) -> &'static str {
+        match self {
+            Self::Ban { .. } | Self::Unban { .. } => "moderator:manage:banned_users",
+            Self::DeleteMessage { .. } | Self::ClearChat => "moderator:manage:chat_messages",
+            Self::ShieldMode(_) => "moderator:manage:shield_mode",
+            Self::ChatSettings(_) => "moderator:manage:chat_settings",
+            Self::Announcement { .. } => "moderator:manage:announcements",
+        }
+    }
+}
+
+fn parse_toggle(value: Option<String>) -> Result<bool, String> {
+    match value.as_deref() {
+        None | Some("on") | Some("true") | Some("enable") => Ok(true),
+        Some("off") | Some("false") | Some("disable") => Ok(false),
+        Some(other) => Err(format!("expected on or off, got {other}")),
+    }
+}
+
+/// Parses a duration in seconds/minutes, where `off` disables the mode.
+fn parse_duration(
+    value: Option<String>,
+    range: std::ops::RangeInclusive<u32>,
+) -> Result<Option<u32>, String> {
+    match value.as_deref() {
+        None => Ok(Some(*range.start())),
+        Some("off") | Some("false") | Some("disable") => Ok(None),
+        Some(raw) => {
+            let duration: u32 = raw
+                .parse()
+                .map_err(|_| "duration is not an integer".to_owned())?;
+
+            if range.contains(&duration) {
+                Ok(Some(duration))
+            } else {
+                Err(format!(
+                    "duration must be between {} and {}",
+                    range.start(),
+                    range.end()
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModerationAction;
+    use crate::command_handler::twitch_api::model::ChatSettingsUpdate;
+    use pretty_assertions::assert_eq;
+
+    fn params(input: &str) -> Vec<String> {
+        input.split_whitespace().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn parse_ban_with_reason() {
+        assert_eq!(
+            ModerationAction::from_params("ban", params("forsen being too based")).unwrap(),
+            ModerationAction::Ban {
+                user: "forsen".to_owned(),
+                reason: Some("being too based".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn parse_slow_mode() {
+        assert_eq!(
+            ModerationAction::from_params("slow", params("30")).unwrap(),
+            ModerationAction::ChatSettings(ChatSettingsUpdate {
+                slow_mode: Some(true),
+                slow_mode_wait_time: Some(30),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            ModerationAction::from_params("slow", params("off")).unwrap(),
+            ModerationAction::ChatSettings(ChatSettingsUpdate {
+                slow_mode: Some(false),
+                ..Default::default()
+            })
+        );
+        assert!(ModerationAction::from_params("slow", params("1000")).is_err());
+        assert!(ModerationAction::from_params("slow", params("")).is_err());
+    }
+}
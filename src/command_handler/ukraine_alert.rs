@@ -5,7 +5,7 @@ use strum::AsRefStr;
 
 const BASE_URL: &str = "https://siren.pp.ua";
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct UkraineAlertClient {
     client: Client,
 }
@@ -0,0 +1,260 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::{
+    borrow::Borrow,
+    fmt::Display,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Share of the capacity evicted at once when the cache is full, so that finding
+/// the oldest entries doesn't have to scan the whole cache on every insert
+const EVICTION_BATCH_DIVISOR: usize = 10;
+
+/// Query results kept in memory, shared between the clones of the cache.
+///
+/// Entries expire after the time to live, and the oldest entries are evicted when the cache is full.
+/// Writes to the cached data have to invalidate the affected entries themselves.
+pub struct Cache<K: Eq + Hash, V> {
+    inner: Arc<CacheInner<K, V>>,
+}
+
+struct CacheInner<K: Eq + Hash, V> {
+    name: &'static str,
+    entries: DashMap<K, CacheEntry<V>>,
+    ttl: Duration,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    insertions: AtomicU64,
+}
+
+struct CacheEntry<V> {
+    value: V,
+    inserted_at: Instant,
+    /// Orders the entries for eviction, as instants can be equal
+    sequence: u64,
+}
+
+impl<V> CacheEntry<V> {
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.inserted_at.elapsed() >= ttl
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Cache<K, V> {
+    pub fn new(name: &'static str, ttl: Duration, capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(CacheInner {
+                name,
+                entries: DashMap::new(),
+                ttl,
+                capacity,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                insertions: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let ttl = self.inner.ttl;
+        let value = self
+            .inner
+            .entries
+            .get(key)
+            .filter(|entry| !entry.is_expired(ttl))
+            .map(|entry| entry.value.clone());
+
+        match value {
+            Some(value) => {
+                self.inner.hits.fetch_add(1, Ordering::Relaxed);
+                Some(value)
+            }
+            None => {
+                self.inner
+                    .entries
+                    .remove_if(key, |_, entry| entry.is_expired(ttl));
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        let entries = &self.inner.entries;
+
+        if entries.len() >= self.inner.capacity && !entries.contains_key(&key) {
+            self.evict();
+        }
+
+        entries.insert(
+            key,
+            CacheEntry {
+                value,
+                inserted_at: Instant::now(),
+                sequence: self.inner.insertions.fetch_add(1, Ordering::Relaxed),
+            },
+        );
+    }
+
+    /// Removes the expired entries, along with the oldest ones if that doesn't free up a batch.
+    fn evict(&self) {
+        let entries = &self.inner.entries;
+        let ttl = self.inner.ttl;
+        let batch = (self.inner.capacity / EVICTION_BATCH_DIVISOR).max(1);
+
+        let mut sequences: Vec<u64> = entries
+            .iter()
+            .filter(|entry| !entry.is_expired(ttl))
+            .map(|entry| entry.sequence)
+            .collect();
+
+        let cutoff = if sequences.len() + batch > self.inner.capacity {
+            let index =
+                (sequences.len() + batch - self.inner.capacity - 1).min(sequences.len() - 1);
+            Some(*sequences.select_nth_unstable(index).1)
+        } else {
+            None
+        };
+
+        entries.retain(|_, entry| {
+            !entry.is_expired(ttl) && cutoff.map_or(true, |cutoff| entry.sequence > cutoff)
+        });
+    }
+
+    pub fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.inner.entries.remove(key);
+    }
+
+    /// Invalidates the entries for which the predicate returns false
+    pub fn retain(&self, mut f: impl FnMut(&K, &V) -> bool) {
+        self.inner.entries.retain(|key, entry| f(key, &entry.value));
+    }
+
+    pub fn clear(&self) {
+        self.inner.entries.clear();
+    }
+
+    pub fn purge_expired(&self) {
+        let ttl = self.inner.ttl;
+        self.inner.entries.retain(|_, entry| !entry.is_expired(ttl));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            name: self.inner.name,
+            entries: self.inner.entries.len(),
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Clone for Cache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> std::fmt::Debug for Cache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("name", &self.inner.name)
+            .field("entries", &self.inner.entries.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub name: &'static str,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} entries, {} hits, {} misses",
+            self.name, self.entries, self.hits, self.misses
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use std::time::Duration;
+
+    #[test]
+    fn hits_and_misses() {
+        let cache = Cache::new("test", Duration::from_secs(60), 10);
+
+        assert_eq!(cache.get(&1), None);
+        cache.insert(1, "one");
+        assert_eq!(cache.get(&1), Some("one"));
+
+        cache.remove(&1);
+        assert_eq!(cache.get(&1), None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+    }
+
+    #[test]
+    fn expired_entries() {
+        let cache = Cache::new("test", Duration::ZERO, 10);
+
+        cache.insert(1, "one");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn evicts_oldest_when_full() {
+        let cache = Cache::new("test", Duration::from_secs(60), 2);
+
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        cache.insert(3, "three");
+
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some("two"));
+        assert_eq!(cache.get(&3), Some("three"));
+    }
+
+    #[test]
+    fn evicts_in_batches() {
+        let cache = Cache::new("test", Duration::from_secs(60), 20);
+
+        for i in 0..21 {
+            cache.insert(i, i);
+        }
+
+        assert_eq!(cache.stats().entries, 19);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(2));
+
+        // The freed up space is used before evicting again
+        cache.insert(21, 21);
+        assert_eq!(cache.stats().entries, 20);
+        assert_eq!(cache.get(&2), Some(2));
+    }
+}
@@ -22,70 +22,74 @@ impl TokenStorage for Credentials {
     type UpdateError = anyhow::Error;
 
     async fn load_token(&mut self) -> Result<UserAccessToken, Self::LoadError> {
-        let access_token = self
-            .db
-            .get_auth(&self.make_entry_name("twitch_access_token"))?
-            .unwrap_or_default();
-        let refresh_token = self
-            .db
-            .get_auth(&self.make_entry_name("twitch_refresh_token"))?
-            .unwrap_or_default();
-
-        let created_at = DateTime::from_utc(
-            DateTime::parse_from_rfc3339(
-                &self
-                    .db
-                    .get_auth(&self.make_entry_name("twitch_created_at"))?
-                    .unwrap_or_default(),
-            )?
-            .naive_utc(),
-            Utc,
-        );
-
-        let expires_at = match self
-            .db
-            .get_auth(&self.make_entry_name("twitch_expires_at"))?
-        {
-            Some(date) => Some(DateTime::from_utc(
-                DateTime::parse_from_rfc3339(&date)?.naive_utc(),
-                Utc,
-            )),
-            None => None,
-        };
-
-        Ok(UserAccessToken {
-            access_token,
-            refresh_token,
-            created_at,
-            expires_at,
-        })
+        load_token(&self.db, self.make_entry_name("")).await
     }
 
     async fn update_token(&mut self, token: &UserAccessToken) -> Result<(), Self::UpdateError> {
         tracing::info!("Refreshed Twitch token for {}!", self.user_id);
 
-        self.db.set_auth(
-            &self.make_entry_name("twitch_access_token"),
-            &token.access_token,
-        )?;
+        update_token(&self.db, self.make_entry_name(""), token).await
+    }
+}
+
+/// Loads the token stored under the entries starting with `prefix`
+pub(super) async fn load_token(db: &Database, prefix: String) -> anyhow::Result<UserAccessToken> {
+    let (access_token, refresh_token, created_at, expires_at) = db
+        .run(move |db| {
+            let get = |entry: &str| db.get_auth(&format!("{prefix}{entry}"));
+
+            Ok((
+                get("twitch_access_token")?,
+                get("twitch_refresh_token")?,
+                get("twitch_created_at")?,
+                get("twitch_expires_at")?,
+            ))
+        })
+        .await?;
 
-        self.db.set_auth(
-            &self.make_entry_name("twitch_refresh_token"),
-            &token.refresh_token,
-        )?;
+    let created_at = DateTime::from_utc(
+        DateTime::parse_from_rfc3339(&created_at.unwrap_or_default())?.naive_utc(),
+        Utc,
+    );
 
-        self.db.set_auth(
-            &self.make_entry_name("twitch_created_at"),
-            &token.created_at.to_rfc3339(),
-        )?;
+    let expires_at = match expires_at {
+        Some(date) => Some(DateTime::from_utc(
+            DateTime::parse_from_rfc3339(&date)?.naive_utc(),
+            Utc,
+        )),
+        None => None,
+    };
+
+    Ok(UserAccessToken {
+        access_token: access_token.unwrap_or_default(),
+        refresh_token: refresh_token.unwrap_or_default(),
+        created_at,
+        expires_at,
+    })
+}
+
+/// Stores the token under the entries starting with `prefix`
+pub(super) async fn update_token(
+    db: &Database,
+    prefix: String,
+    token: &UserAccessToken,
+) -> anyhow::Result<()> {
+    let token = token.clone();
+
+    db.run(move |db| {
+        let set = |entry: &str, value: &str| db.set_auth(&format!("{prefix}{entry}"), value);
+
+        set("twitch_access_token", &token.access_token)?;
+        set("twitch_refresh_token", &token.refresh_token)?;
+        set("twitch_created_at", &token.created_at.to_rfc3339())?;
 
         if let Some(expires_at) = token.expires_at {
-            self.db.set_auth(
-                &self.make_entry_name("twitch_expires_at"),
-                &expires_at.to_rfc3339(),
-            )?;
+            set("twitch_expires_at", &expires_at.to_rfc3339())?;
         }
 
         Ok(())
-    }
+    })
+    .await?;
+
+    Ok(())
 }
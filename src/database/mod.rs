@@ -1,69 +1,103 @@
 use std::env;
 use std::fmt::Display;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
-use dashmap::DashMap;
+use chrono::Utc;
 use diesel::mysql::MysqlConnection;
-use diesel::r2d2::{self, ConnectionManager, Pool};
-use diesel::sql_types::{BigInt, Unsigned};
+use diesel::r2d2::{self, ConnectionManager, Pool, PoolError, PooledConnection};
+use diesel::sql_types::{BigInt, Text, Unsigned};
 use diesel::{sql_query, EqAll, QueryDsl};
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, RunQueryDsl, TextExpressionMethods,
+};
 use diesel::{ConnectionError, OptionalExtension};
-use diesel::{ExpressionMethods, RunQueryDsl};
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use passwords::PasswordGenerator;
 use reqwest::Client;
-use tokio::time;
+use tokio::{task, time};
 use tracing::{error, instrument};
 use twitch_irc::login::{TokenStorage, UserAccessToken};
 
+use crate::command_handler::eval::cache::{PreparedScript, ScriptCache};
 use crate::command_handler::spotify_api::SpotifyApi;
 use crate::database::schema::*;
 use crate::platform::{ChannelIdentifier, UserIdentifier, UserIdentifierError};
 
+use self::cache::{Cache, CacheStats};
 use self::credentials::Credentials;
 use self::models::*;
 
+pub mod cache;
 pub mod credentials;
 pub mod models;
 mod schema;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+const DEFAULT_POOL_SIZE: u32 = 10;
+const DEFAULT_CONNECTION_TIMEOUT_SECS: u64 = 30;
+
+const CACHE_TTL: Duration = Duration::from_secs(3600);
+/// Every entry holds all the commands of a channel, so fewer of them are kept
+const COMMANDS_CACHE_CAPACITY: usize = 1_000;
+const CACHE_CAPACITY: usize = 10_000;
+
 const BUILTIN_COMMANDS: &[&str] = &[
     "ping", "commands", "cmd", "command", "addcmd", "debug", "delcmd", "merge", "showcmd",
-    "checkcmd",
+    "checkcmd", "link", "set", "unset", "block", "unblock",
 ];
 
 #[derive(Clone, Debug)]
 pub struct Database {
     conn_pool: Pool<ConnectionManager<MysqlConnection>>,
-    web_sessions_cache: Arc<DashMap<String, WebSession>>,
-    users_cache: Arc<DashMap<u64, User>>,
-    user_identifiers_cache: Arc<DashMap<UserIdentifier, u64>>, // Caches the user IDs
-    prefixes_cache: Arc<DashMap<u64, Option<String>>>,
+    web_sessions_cache: Cache<String, WebSession>,
+    users_cache: Cache<u64, User>,
+    user_identifiers_cache: Cache<UserIdentifier, u64>, // Caches the user IDs
+    prefixes_cache: Cache<u64, Option<String>>,
+    channel_roles_cache: Cache<u64, Arc<Vec<ChannelRole>>>,
+    global_roles_cache: Cache<u64, Option<GlobalRole>>,
     // TODO: look into only caching channel IDs, not entire channels
-    channels_cache: Arc<DashMap<String, Channel>>,
+    channels_cache: Cache<String, Channel>,
+    commands_cache: Cache<u64, Arc<Vec<Command>>>, // Channel id and its commands
+    hebi_modules_cache: Cache<u64, Arc<Vec<HebiModule>>>, // Channel id and its modules
+    hebi_scripts_cache: ScriptCache,
 }
 
 impl Database {
     pub fn connect(database_url: String) -> Result<Self, ConnectionError> {
+        let pool_size = env_config("DATABASE_POOL_SIZE", DEFAULT_POOL_SIZE)?;
+        let connection_timeout = env_config(
+            "DATABASE_CONNECTION_TIMEOUT_SECS",
+            DEFAULT_CONNECTION_TIMEOUT_SECS,
+        )?;
+
         let manager = ConnectionManager::<MysqlConnection>::new(database_url);
-        let conn_pool = r2d2::Pool::new(manager).expect("Failed to set up DB connection pool");
+        let conn_pool = r2d2::Pool::builder()
+            .max_size(pool_size)
+            .connection_timeout(Duration::from_secs(connection_timeout))
+            .build(manager)
+            .expect("Failed to set up DB connection pool");
 
         conn_pool
             .get()
-            .unwrap()
+            .expect("Failed to connect to the database")
             .run_pending_migrations(MIGRATIONS)
             .expect("Failed to run migrations");
 
-        let web_sessions_cache = Arc::new(DashMap::new());
-        let users_cache = Arc::new(DashMap::new());
-        let user_identifiers_cache = Arc::new(DashMap::new());
-        let prefixes_cache = Arc::new(DashMap::new());
-        let channels_cache = Arc::new(DashMap::new());
+        let web_sessions_cache = Cache::new("web sessions", CACHE_TTL, CACHE_CAPACITY);
+        let users_cache = Cache::new("users", CACHE_TTL, CACHE_CAPACITY);
+        let user_identifiers_cache = Cache::new("user identifiers", CACHE_TTL, CACHE_CAPACITY);
+        let prefixes_cache = Cache::new("prefixes", CACHE_TTL, CACHE_CAPACITY);
+        let channel_roles_cache = Cache::new("channel roles", CACHE_TTL, CACHE_CAPACITY);
+        let global_roles_cache = Cache::new("global roles", CACHE_TTL, CACHE_CAPACITY);
+        let channels_cache = Cache::new("channels", CACHE_TTL, CACHE_CAPACITY);
+        let commands_cache = Cache::new("commands", CACHE_TTL, COMMANDS_CACHE_CAPACITY);
+        let hebi_modules_cache = Cache::new("hebi modules", CACHE_TTL, CACHE_CAPACITY);
+        let hebi_scripts_cache = ScriptCache::new();
 
         Ok(Self {
             conn_pool,
@@ -71,41 +105,95 @@ impl Database {
             users_cache,
             user_identifiers_cache,
             prefixes_cache,
+            channel_roles_cache,
+            global_roles_cache,
             channels_cache,
+            commands_cache,
+            hebi_modules_cache,
+            hebi_scripts_cache,
         })
     }
 
+    fn conn(&self) -> Result<PooledConnection<ConnectionManager<MysqlConnection>>, DatabaseError> {
+        Ok(self.conn_pool.get()?)
+    }
+
+    /// Runs the queries on a blocking thread, so that waiting for the database
+    /// doesn't hold up other tasks on the async executor.
+    pub async fn run<T, F>(&self, f: F) -> Result<T, DatabaseError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> Result<T, DatabaseError> + Send + 'static,
+    {
+        let db = self.clone();
+
+        task::spawn_blocking(move || f(&db))
+            .await
+            .expect("Database task panicked")
+    }
+
+    pub fn cache_stats(&self) -> Vec<CacheStats> {
+        vec![
+            self.web_sessions_cache.stats(),
+            self.users_cache.stats(),
+            self.user_identifiers_cache.stats(),
+            self.prefixes_cache.stats(),
+            self.channel_roles_cache.stats(),
+            self.global_roles_cache.stats(),
+            self.channels_cache.stats(),
+            self.commands_cache.stats(),
+            self.hebi_modules_cache.stats(),
+            self.hebi_scripts_cache.stats(),
+        ]
+    }
+
+    fn purge_expired_cache_entries(&self) {
+        self.web_sessions_cache.purge_expired();
+        self.users_cache.purge_expired();
+        self.user_identifiers_cache.purge_expired();
+        self.prefixes_cache.purge_expired();
+        self.channel_roles_cache.purge_expired();
+        self.global_roles_cache.purge_expired();
+        self.channels_cache.purge_expired();
+        self.commands_cache.purge_expired();
+        self.hebi_modules_cache.purge_expired();
+        self.hebi_scripts_cache.purge_expired();
+    }
+
     pub fn start_cron(&self) {
-        let web_sessions_cache = self.web_sessions_cache.clone();
-        let users_cache = self.users_cache.clone();
-        let user_identifiers_cache = self.user_identifiers_cache.clone();
+        let db = self.clone();
 
         tokio::spawn(async move {
             loop {
                 time::sleep(Duration::from_secs(3600)).await;
 
-                tracing::info!("Clearing caches");
+                db.purge_expired_cache_entries();
 
-                web_sessions_cache.clear();
-                users_cache.clear();
-                user_identifiers_cache.clear();
+                for stats in db.cache_stats() {
+                    tracing::info!("Cache {stats}");
+                }
             }
         });
 
         {
-            let conn_pool = self.conn_pool.clone();
+            let db = self.clone();
 
             if let Ok(client_id) = env::var("SPOTIFY_CLIENT_ID") {
                 if let Ok(client_secret) = env::var("SPOTIFY_CLIENT_SECRET") {
                     tokio::spawn(async move {
                         loop {
-                            let mut conn = conn_pool.get().unwrap();
-
-                            let refresh_tokens = user_data::table
-                                .select((user_data::user_id, user_data::value))
-                                .filter(user_data::name.eq_all("spotify_refresh_token"))
-                                .load::<(u64, String)>(&mut conn)
-                                .expect("DB Error");
+                            let refresh_tokens = db
+                                .run(|db| {
+                                    Ok(user_data::table
+                                        .select((user_data::user_id, user_data::value))
+                                        .filter(user_data::name.eq_all("spotify_refresh_token"))
+                                        .load::<(u64, String)>(&mut db.conn()?)?)
+                                })
+                                .await
+                                .unwrap_or_else(|e| {
+                                    tracing::error!("Failed to load Spotify tokens: {e}");
+                                    Vec::new()
+                                });
 
                             if refresh_tokens.is_empty() {
                                 tracing::info!("Updating Spotify tokens...");
@@ -130,16 +218,27 @@ impl Database {
                                             user_id
                                         );
 
-                                        diesel::update(
-                                            user_data::table
-                                                .filter(
-                                                    user_data::name.eq_all("spotify_access_token"),
+                                        let result = db
+                                            .run(move |db| {
+                                                diesel::update(
+                                                    user_data::table
+                                                        .filter(
+                                                            user_data::name
+                                                                .eq_all("spotify_access_token"),
+                                                        )
+                                                        .filter(user_data::user_id.eq_all(user_id)),
                                                 )
-                                                .filter(user_data::user_id.eq_all(user_id)),
-                                        )
-                                        .set(user_data::value.eq_all(access_token))
-                                        .execute(&mut conn)
-                                        .expect("DB Error");
+                                                .set(user_data::value.eq_all(access_token))
+                                                .execute(&mut db.conn()?)?;
+
+                                                Ok(())
+                                            })
+                                            .await;
+                                        if let Err(e) = result {
+                                            tracing::error!(
+                                                "Failed to save Spotify token for user {user_id}: {e}"
+                                            );
+                                        }
 
                                         if refresh_in.is_none() {
                                             refresh_in = Some(expiration_time);
@@ -162,22 +261,22 @@ impl Database {
         }
     }
 
-    pub fn get_channels(&self) -> Result<Vec<Channel>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    pub fn get_channels(&self) -> Result<Vec<Channel>, DatabaseError> {
+        let mut conn = self.conn()?;
 
-        channels::table.order(channels::id).load(&mut conn)
+        Ok(channels::table.order(channels::id).load(&mut conn)?)
     }
 
     pub fn get_channel(
         &self,
         channel_identifier: &ChannelIdentifier,
-    ) -> Result<Option<Channel>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
-
+    ) -> Result<Option<Channel>, DatabaseError> {
         if let Some(channel) = channel_identifier.get_channel() {
             if let Some(channel) = self.channels_cache.get(&channel_identifier.to_string()) {
-                Ok(Some(channel.value().clone()))
+                Ok(Some(channel))
             } else {
+                let mut conn = self.conn()?;
+
                 let channel = channels::table
                     .filter(
                         channels::platform.eq_all(channel_identifier.get_platform_name().unwrap()),
@@ -201,30 +300,34 @@ impl Database {
     pub fn get_or_create_channel(
         &self,
         channel_identifier: &ChannelIdentifier,
-    ) -> Result<Option<Channel>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    ) -> Result<Option<Channel>, DatabaseError> {
+        if let Some(platform) = channel_identifier.get_platform_name() {
+            let cache_key = channel_identifier.to_string();
+            if let Some(channel) = self.channels_cache.get(&cache_key) {
+                return Ok(Some(channel));
+            }
 
-        let query = channels::table.into_boxed();
+            let mut conn = self.conn()?;
 
-        if let Some(platform) = channel_identifier.get_platform_name() {
             let channel = channel_identifier.get_channel().unwrap_or_default();
-            match query
+            match channels::table
                 .filter(channels::platform.eq_all(platform))
                 .filter(channels::channel.eq_all(channel))
-                .first(&mut conn)
+                .first::<Channel>(&mut conn)
                 .optional()?
             {
-                Some(channel) => Ok(Some(channel)),
+                Some(channel) => {
+                    self.channels_cache.insert(cache_key, channel.clone());
+
+                    Ok(Some(channel))
+                }
                 None => {
-                    let new_channel = NewChannel {
-                        platform: channel_identifier.get_platform_name().unwrap(),
-                        channel,
-                    };
+                    let new_channel = NewChannel { platform, channel };
 
                     diesel::insert_into(channels::table)
                         .values(new_channel)
-                        .execute(&mut conn)
-                        .expect("Failed to create channel");
+                        .execute(&mut conn)?;
+                    drop(conn);
 
                     self.get_or_create_channel(channel_identifier)
                 }
@@ -245,22 +348,83 @@ impl Database {
         }
     }
 
-    pub fn get_channel_by_id(
-        &self,
-        channel_id: u64,
-    ) -> Result<Option<Channel>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    /// Admins are the user from `ADMIN_USER` and the users with the admin role.
+    pub fn is_admin(&self, user_id: u64) -> Result<bool, DatabaseError> {
+        if let Some(admin_user) = self.get_admin_user()? {
+            if admin_user.id == user_id {
+                return Ok(true);
+            }
+        }
+
+        Ok(self.get_global_role(user_id)? == Some(GlobalRole::Admin))
+    }
+
+    pub fn get_global_role(&self, user_id: u64) -> Result<Option<GlobalRole>, DatabaseError> {
+        if let Some(role) = self.global_roles_cache.get(&user_id) {
+            return Ok(role);
+        }
+
+        let mut conn = self.conn()?;
+
+        let role = global_roles::table
+            .filter(global_roles::user_id.eq(user_id))
+            .first::<GlobalRoleAssignment>(&mut conn)
+            .optional()?
+            .map(|assignment| assignment.role);
+        self.global_roles_cache.insert(user_id, role);
+
+        Ok(role)
+    }
+
+    pub fn get_global_roles(&self) -> Result<Vec<GlobalRoleAssignment>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        Ok(global_roles::table.load(&mut conn)?)
+    }
+
+    pub fn set_global_role(&self, user_id: u64, role: GlobalRole) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        diesel::replace_into(global_roles::table)
+            .values((
+                global_roles::user_id.eq(user_id),
+                global_roles::role.eq(role.to_string()),
+            ))
+            .execute(&mut conn)?;
+
+        self.global_roles_cache.remove(&user_id);
+
+        Ok(())
+    }
 
-        channels::table
+    pub fn remove_global_role(&self, user_id: u64) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let deleted = diesel::delete(global_roles::table.filter(global_roles::user_id.eq(user_id)))
+            .execute(&mut conn)?;
+
+        self.global_roles_cache.remove(&user_id);
+
+        if deleted == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn get_channel_by_id(&self, channel_id: u64) -> Result<Option<Channel>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        Ok(channels::table
             .filter(channels::id.eq_all(channel_id))
             .first(&mut conn)
-            .optional()
+            .optional()?)
     }
 
-    pub fn get_channels_amount(&self) -> Result<i64, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    pub fn get_channels_amount(&self) -> Result<i64, DatabaseError> {
+        let mut conn = self.conn()?;
 
-        channels::table.count().get_result(&mut conn)
+        Ok(channels::table.count().get_result(&mut conn)?)
     }
 
     #[instrument]
@@ -269,34 +433,41 @@ impl Database {
         channel_identifier: &ChannelIdentifier,
         command: &str,
     ) -> Result<Option<Command>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
-
-        match channel_identifier.get_channel() {
-            Some(channel) => Ok(commands::table
-                .filter(
-                    commands::channel_id.eq_any(
-                        channels::table
-                            .filter(
-                                channels::platform
-                                    .eq_all(channel_identifier.get_platform_name().unwrap()),
-                            )
-                            .filter(channels::channel.eq_all(channel))
-                            .select(channels::id),
-                    ),
-                )
-                .filter(commands::name.eq_all(command))
-                .first(&mut conn)
-                .optional()?),
+        match self.get_channel(channel_identifier)? {
+            // Names are compared case-insensitively, like the collation of the column does
+            Some(channel) => Ok(self
+                .get_cached_commands(channel.id)?
+                .iter()
+                .find(|cmd| cmd.name.to_lowercase() == command.to_lowercase())
+                .cloned()),
             None => Ok(None),
         }
     }
 
-    pub fn get_commands(&self, channel_id: u64) -> Result<Vec<Command>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    /// The prepared script of a Hebi command, which is kept until the command is changed
+    pub fn get_hebi_script(&self, command: &Command) -> PreparedScript {
+        self.hebi_scripts_cache.get(command)
+    }
 
-        commands::table
-            .filter(commands::channel_id.eq_all(channel_id))
-            .load::<Command>(&mut conn)
+    pub fn get_commands(&self, channel_id: u64) -> Result<Vec<Command>, DatabaseError> {
+        Ok(self.get_cached_commands(channel_id)?.as_ref().clone())
+    }
+
+    fn get_cached_commands(&self, channel_id: u64) -> Result<Arc<Vec<Command>>, DatabaseError> {
+        if let Some(commands) = self.commands_cache.get(&channel_id) {
+            return Ok(commands);
+        }
+
+        let mut conn = self.conn()?;
+
+        let commands = Arc::new(
+            commands::table
+                .filter(commands::channel_id.eq_all(channel_id))
+                .load::<Command>(&mut conn)?,
+        );
+        self.commands_cache.insert(channel_id, commands.clone());
+
+        Ok(commands)
     }
 
     pub fn add_command_to_channel(
@@ -319,12 +490,16 @@ impl Database {
     fn add_command(&self, command: NewCommand) -> Result<(), DatabaseError> {
         match BUILTIN_COMMANDS.contains(&command.name) {
             false => {
-                let mut conn = self.conn_pool.get().unwrap();
+                let mut conn = self.conn()?;
 
                 diesel::insert_into(commands::table)
                     .values(&command)
                     .execute(&mut conn)?;
 
+                self.commands_cache.remove(&command.channel_id);
+                self.hebi_scripts_cache
+                    .invalidate(command.channel_id, command.name);
+
                 Ok(())
             }
             true => Err(DatabaseError::InvalidValue),
@@ -337,7 +512,7 @@ impl Database {
         command_name: &str,
         new_action: &str,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
         let channel = self
             .get_channel(channel_identifier)?
             .ok_or(DatabaseError::InvalidValue)?;
@@ -350,6 +525,9 @@ impl Database {
         .set(commands::action.eq_all(new_action))
         .execute(&mut conn)?;
 
+        self.commands_cache.remove(&channel.id);
+        self.hebi_scripts_cache.invalidate(channel.id, command_name);
+
         Ok(())
     }
 
@@ -359,7 +537,7 @@ impl Database {
         command_name: &str,
         mode: CommandMode,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
         let channel = self
             .get_channel(channel_identifier)?
             .ok_or(DatabaseError::InvalidValue)?;
@@ -372,6 +550,8 @@ impl Database {
         .set(commands::mode.eq_all(mode.to_string()))
         .execute(&mut conn)?;
 
+        self.commands_cache.remove(&channel.id);
+
         Ok(())
     }
 
@@ -386,7 +566,7 @@ impl Database {
     }
 
     pub fn delete_command(&self, channel_id: u64, command_name: &str) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         let affected = diesel::delete(
             commands::table
@@ -395,6 +575,9 @@ impl Database {
         )
         .execute(&mut conn)?;
 
+        self.commands_cache.remove(&channel_id);
+        self.hebi_scripts_cache.invalidate(channel_id, command_name);
+
         match affected {
             0 => Err(DatabaseError::InvalidValue),
             _ => Ok(()),
@@ -404,11 +587,11 @@ impl Database {
     pub fn get_user(
         &self,
         user_identifier: &UserIdentifier,
-    ) -> Result<Option<User>, diesel::result::Error> {
+    ) -> Result<Option<User>, DatabaseError> {
         match self.user_identifiers_cache.get(user_identifier) {
-            Some(id) => self.get_user_by_id(*id),
+            Some(id) => self.get_user_by_id(id),
             None => {
-                let mut conn = self.conn_pool.get().unwrap();
+                let mut conn = self.conn()?;
 
                 let query = users::table.into_boxed();
 
@@ -439,11 +622,11 @@ impl Database {
         }
     }
 
-    pub fn get_user_by_id(&self, user_id: u64) -> Result<Option<User>, diesel::result::Error> {
+    pub fn get_user_by_id(&self, user_id: u64) -> Result<Option<User>, DatabaseError> {
         match self.users_cache.get(&user_id) {
-            Some(user) => Ok(Some(user.clone())),
+            Some(user) => Ok(Some(user)),
             None => {
-                let mut conn = self.conn_pool.get().unwrap();
+                let mut conn = self.conn()?;
 
                 match users::table
                     .filter(users::id.eq_all(user_id))
@@ -466,9 +649,7 @@ impl Database {
     pub fn get_or_create_user(
         &self,
         user_identifier: &UserIdentifier,
-    ) -> Result<User, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
-
+    ) -> Result<User, DatabaseError> {
         match self.get_user(user_identifier)? {
             Some(user) => Ok(user),
             None => {
@@ -501,41 +682,145 @@ impl Database {
 
                 diesel::insert_into(users::table)
                     .values(new_user)
-                    .execute(&mut conn)
-                    .expect("Failed to save new user");
+                    .execute(&mut self.conn()?)?;
 
                 Ok(self.get_user(user_identifier)?.unwrap())
             }
         }
     }
 
-    pub fn merge_users(&self, mut user: User, other: User) -> User {
-        let mut conn = self.conn_pool.get().unwrap();
+    /// Merges the other user into the user, moving their data and identifiers.
+    /// Data that both users have is kept from the user.
+    pub fn merge_users(&self, mut user: User, other: User) -> Result<User, DatabaseError> {
+        let mut conn = self.conn()?;
+        let other_id = other.id;
 
-        self.users_cache.remove(&other.id);
+        user.merge(other);
 
-        sql_query("REPLACE INTO user_data(user_id, name, value) SELECT ?, name, value FROM user_data WHERE user_id = ?").bind::<Unsigned<BigInt>, _>(user.id).bind::<Unsigned<BigInt>, _>(other.id).execute(&mut conn).expect("Failed to run replace query");
+        conn.transaction(|conn| {
+            sql_query("INSERT IGNORE INTO user_data(user_id, name, value, public) SELECT ?, name, value, public FROM user_data WHERE user_id = ?")
+                .bind::<Unsigned<BigInt>, _>(user.id)
+                .bind::<Unsigned<BigInt>, _>(other_id)
+                .execute(conn)?;
+
+            sql_query("UPDATE IGNORE hebi_data SET user_id = ? WHERE user_id = ?")
+                .bind::<Unsigned<BigInt>, _>(user.id)
+                .bind::<Unsigned<BigInt>, _>(other_id)
+                .execute(conn)?;
+            diesel::delete(hebi_data::table.filter(hebi_data::user_id.eq(other_id))).execute(conn)?;
+
+            sql_query("UPDATE IGNORE geohub_link SET user_id = ? WHERE user_id = ?")
+                .bind::<Unsigned<BigInt>, _>(user.id)
+                .bind::<Unsigned<BigInt>, _>(other_id)
+                .execute(conn)?;
+            diesel::delete(geohub_link::table.filter(geohub_link::user_id.eq(other_id)))
+                .execute(conn)?;
+
+            let role = global_roles::table
+                .filter(global_roles::user_id.eq_any([user.id, other_id]))
+                .load::<GlobalRoleAssignment>(conn)?
+                .into_iter()
+                .map(|assignment| assignment.role)
+                .reduce(GlobalRole::merge);
+            if let Some(role) = role {
+                diesel::delete(global_roles::table.filter(global_roles::user_id.eq(other_id)))
+                    .execute(conn)?;
+                diesel::replace_into(global_roles::table)
+                    .values((
+                        global_roles::user_id.eq(user.id),
+                        global_roles::role.eq(role.to_string()),
+                    ))
+                    .execute(conn)?;
+            }
 
-        diesel::delete(&other)
-            .execute(&mut conn)
-            .expect("Failed to delete");
+            sql_query("UPDATE IGNORE channel_roles SET user_id = ? WHERE user_id = ?")
+                .bind::<Unsigned<BigInt>, _>(user.id)
+                .bind::<Unsigned<BigInt>, _>(other_id)
+                .execute(conn)?;
 
-        user.merge(other);
+            diesel::update(web_sessions::table.filter(web_sessions::user_id.eq(other_id)))
+                .set(web_sessions::user_id.eq(user.id))
+                .execute(conn)?;
+
+            // The remaining user data, roles and link codes are deleted with the user
+            diesel::delete(users::table.filter(users::id.eq(other_id))).execute(conn)?;
+
+            diesel::update(users::table.filter(users::id.eq(user.id)))
+                .set(&user)
+                .execute(conn)?;
 
-        diesel::update(users::table.filter(users::id.eq_all(user.id)))
-            .set(&user)
-            .execute(&mut conn)
-            .expect("Failed to update");
+            Ok::<_, DatabaseError>(())
+        })?;
 
+        self.users_cache.remove(&other_id);
         self.users_cache.remove(&user.id);
+        self.user_identifiers_cache.retain(|_, id| *id != other_id);
+        self.web_sessions_cache
+            .retain(|_, session| session.user_id != other_id);
+        // The roles are cached per channel
+        self.channel_roles_cache.clear();
+        self.global_roles_cache.remove(&other_id);
+        self.global_roles_cache.remove(&user.id);
+
+        Ok(user)
+    }
+
+    /// Saves a one-time code for linking the user with another account, replacing their previous code.
+    pub fn create_link_code(
+        &self,
+        user_id: u64,
+        code: &str,
+        expires_at: u64,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+        let now = Utc::now().timestamp() as u64;
 
-        self.user_identifiers_cache.clear();
+        diesel::delete(
+            link_codes::table.filter(
+                link_codes::user_id
+                    .eq(user_id)
+                    .or(link_codes::expires_at.le(now)),
+            ),
+        )
+        .execute(&mut conn)?;
+
+        diesel::insert_into(link_codes::table)
+            .values(&LinkCode {
+                code: code.to_owned(),
+                user_id,
+                expires_at,
+            })
+            .execute(&mut conn)?;
 
-        user
+        Ok(())
+    }
+
+    /// Consumes the code, returning the user it was created for if it hasn't expired.
+    pub fn redeem_link_code(&self, code: &str) -> Result<Option<u64>, DatabaseError> {
+        let mut conn = self.conn()?;
+        let now = Utc::now().timestamp() as u64;
+
+        conn.transaction(|conn| {
+            let link_code = link_codes::table
+                .filter(link_codes::code.eq(code))
+                .for_update()
+                .first::<LinkCode>(conn)
+                .optional()?;
+
+            match link_code {
+                Some(link_code) => {
+                    diesel::delete(link_codes::table.filter(link_codes::code.eq(code)))
+                        .execute(conn)?;
+
+                    Ok((link_code.expires_at > now).then_some(link_code.user_id))
+                }
+                None => Ok(None),
+            }
+        })
     }
 
     pub fn get_auth(&self, key: &str) -> Result<Option<String>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         Ok(auth::table
             .filter(auth::name.eq_all(key))
@@ -546,7 +831,7 @@ impl Database {
     }
 
     pub fn set_auth(&self, key: &str, value: &str) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         tracing::debug!("Setting auth: {} - {}", key, value);
 
@@ -561,31 +846,28 @@ impl Database {
         &self,
         user_id: u64,
         key: &str,
-    ) -> Result<Option<String>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    ) -> Result<Option<String>, DatabaseError> {
+        let mut conn = self.conn()?;
 
-        user_data::table
+        Ok(user_data::table
             .filter(user_data::user_id.eq_all(user_id))
             .filter(user_data::name.eq_all(key))
             .select(user_data::value)
             .first(&mut conn)
-            .optional()
+            .optional()?)
     }
 
-    pub fn get_eventsub_redeem(
-        &self,
-        id: &str,
-    ) -> Result<Option<EventSubTrigger>, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    pub fn get_eventsub_redeem(&self, id: &str) -> Result<Option<EventSubTrigger>, DatabaseError> {
+        let mut conn = self.conn()?;
 
-        eventsub_triggers::table
+        Ok(eventsub_triggers::table
             .filter(eventsub_triggers::id.eq_all(id))
             .first(&mut conn)
-            .optional()
+            .optional()?)
     }
 
     pub fn get_eventsub_triggers(&self) -> Result<Vec<EventSubTrigger>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         Ok(eventsub_triggers::table.load(&mut conn)?)
     }
@@ -594,7 +876,7 @@ impl Database {
         &self,
         broadcaster_id: &str,
     ) -> Result<Vec<EventSubTrigger>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         Ok(eventsub_triggers::table
             .filter(eventsub_triggers::broadcaster_id.eq(broadcaster_id))
@@ -604,8 +886,8 @@ impl Database {
         &self,
         user_data: &UserData,
         overwrite: bool,
-    ) -> Result<(), diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
 
         match overwrite {
             true => diesel::replace_into(user_data::table)
@@ -619,8 +901,8 @@ impl Database {
         Ok(())
     }
 
-    pub fn remove_user_data(&self, user_id: u64, data: &str) -> Result<(), diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    pub fn remove_user_data(&self, user_id: u64, data: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
 
         diesel::delete(
             user_data::table
@@ -632,41 +914,90 @@ impl Database {
         Ok(())
     }
 
-    pub fn get_spotify_access_token(
-        &self,
-        user_id: u64,
-    ) -> Result<Option<String>, diesel::result::Error> {
+    pub fn get_spotify_access_token(&self, user_id: u64) -> Result<Option<String>, DatabaseError> {
         self.get_user_data_value(user_id, "spotify_access_token")
     }
 
-    pub fn get_location(&self, user_id: u64) -> Result<Option<String>, diesel::result::Error> {
-        self.get_user_data_value(user_id, "location")
+    pub fn get_location(&self, user_id: u64) -> Result<Option<String>, DatabaseError> {
+        self.get_user_data_value(user_id, ProfileKey::Location.data_name())
     }
 
-    pub fn get_lastfm_name(&self, user_id: u64) -> Result<Option<String>, DatabaseError> {
-        Ok(self.get_user_data_value(user_id, "lastfm_name")?)
+    pub fn get_timezone(&self, user_id: u64) -> Result<Option<String>, DatabaseError> {
+        Ok(self.get_user_data_value(user_id, ProfileKey::Timezone.data_name())?)
     }
 
-    pub fn set_lastfm_name(&self, user_id: u64, name: &str) -> Result<(), DatabaseError> {
+    pub fn get_profile_value(
+        &self,
+        user_id: u64,
+        key: ProfileKey,
+    ) -> Result<Option<ProfileValue>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let data = user_data::table
+            .filter(user_data::user_id.eq_all(user_id))
+            .filter(user_data::name.eq_all(key.data_name()))
+            .first::<UserData>(&mut conn)
+            .optional()?;
+
+        Ok(data.map(|data| ProfileValue {
+            key,
+            value: data.value,
+            public: data.public,
+        }))
+    }
+
+    pub fn get_profile(&self, user_id: u64) -> Result<Vec<ProfileValue>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let names = ProfileKey::ALL.map(|key| key.data_name());
+        let data = user_data::table
+            .filter(user_data::user_id.eq_all(user_id))
+            .filter(user_data::name.eq_any(names))
+            .load::<UserData>(&mut conn)?;
+
+        Ok(data
+            .into_iter()
+            .filter_map(|data| {
+                Some(ProfileValue {
+                    key: ProfileKey::from_data_name(&data.name)?,
+                    value: data.value,
+                    public: data.public,
+                })
+            })
+            .collect())
+    }
+
+    pub fn set_profile_value(
+        &self,
+        user_id: u64,
+        key: ProfileKey,
+        value: &str,
+        public: bool,
+    ) -> Result<(), DatabaseError> {
         Ok(self.set_user_data(
             &UserData {
-                name: "lastfm_name".to_string(),
-                value: name.to_string(),
-                public: true,
+                name: key.data_name().to_owned(),
+                value: value.to_owned(),
+                public,
                 user_id,
             },
             true,
         )?)
     }
 
-    pub fn get_web_session(
-        &self,
-        session_id: &str,
-    ) -> Result<Option<WebSession>, diesel::result::Error> {
+    pub fn get_lastfm_name(&self, user_id: u64) -> Result<Option<String>, DatabaseError> {
+        Ok(self.get_user_data_value(user_id, ProfileKey::LastFM.data_name())?)
+    }
+
+    pub fn set_lastfm_name(&self, user_id: u64, name: &str) -> Result<(), DatabaseError> {
+        self.set_profile_value(user_id, ProfileKey::LastFM, name, true)
+    }
+
+    pub fn get_web_session(&self, session_id: &str) -> Result<Option<WebSession>, DatabaseError> {
         match self.web_sessions_cache.get(session_id) {
-            Some(session) => Ok(Some(session.clone())),
+            Some(session) => Ok(Some(session)),
             None => {
-                let mut conn = self.conn_pool.get().unwrap();
+                let mut conn = self.conn()?;
 
                 match web_sessions::table
                     .filter(web_sessions::session_id.eq_all(session_id))
@@ -692,8 +1023,8 @@ impl Database {
         &self,
         user_id: u64,
         username: String,
-    ) -> Result<String, diesel::result::Error> {
-        let mut conn = self.conn_pool.get().unwrap();
+    ) -> Result<String, DatabaseError> {
+        let mut conn = self.conn()?;
 
         let session = WebSession {
             session_id: PasswordGenerator {
@@ -720,7 +1051,7 @@ impl Database {
     }
 
     pub fn remove_web_session(&self, session_id: &str) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         diesel::delete(web_sessions::table)
             .filter(web_sessions::session_id.eq(session_id))
@@ -731,7 +1062,7 @@ impl Database {
     }
 
     pub fn add_eventsub_trigger(&self, trigger: NewEventSubTrigger) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         diesel::insert_into(eventsub_triggers::table)
             .values(trigger)
@@ -741,7 +1072,7 @@ impl Database {
     }
 
     pub fn delete_eventsub_trigger(&self, id: &str) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         diesel::delete(eventsub_triggers::table)
             .filter(eventsub_triggers::id.eq(id))
@@ -755,7 +1086,7 @@ impl Database {
         old_id: &str,
         new_id: &str,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         diesel::update(eventsub_triggers::table)
             .filter(eventsub_triggers::id.eq(old_id))
@@ -772,12 +1103,13 @@ impl Database {
         }
     }
 
+    /// Prefixes are only changed directly in the database, so changes apply once the entry expires.
     pub fn get_prefix(&self, channel_id: u64) -> Result<Option<String>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
-
         match self.prefixes_cache.get(&channel_id) {
-            Some(prefix_entry) => Ok(prefix_entry.value().clone()),
+            Some(prefix) => Ok(prefix),
             None => {
+                let mut conn = self.conn()?;
+
                 let prefix = prefixes::table
                     .filter(prefixes::channel_id.eq_all(channel_id))
                     .first::<Prefix>(&mut conn)
@@ -802,8 +1134,80 @@ impl Database {
         }
     }
 
+    pub fn get_channel_roles(
+        &self,
+        channel_id: u64,
+    ) -> Result<Arc<Vec<ChannelRole>>, DatabaseError> {
+        if let Some(roles) = self.channel_roles_cache.get(&channel_id) {
+            return Ok(roles);
+        }
+
+        let mut conn = self.conn()?;
+
+        let roles = Arc::new(
+            channel_roles::table
+                .filter(channel_roles::channel_id.eq(channel_id))
+                .load::<ChannelRole>(&mut conn)?,
+        );
+        self.channel_roles_cache.insert(channel_id, roles.clone());
+
+        Ok(roles)
+    }
+
+    pub fn get_channel_role(
+        &self,
+        channel_id: u64,
+        user_id: u64,
+    ) -> Result<Option<Role>, DatabaseError> {
+        Ok(self
+            .get_channel_roles(channel_id)?
+            .iter()
+            .find(|channel_role| channel_role.user_id == user_id)
+            .map(|channel_role| channel_role.role))
+    }
+
+    pub fn set_channel_role(
+        &self,
+        channel_id: u64,
+        user_id: u64,
+        role: Role,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        diesel::replace_into(channel_roles::table)
+            .values((
+                channel_roles::channel_id.eq(channel_id),
+                channel_roles::user_id.eq(user_id),
+                channel_roles::role.eq(role.to_string()),
+            ))
+            .execute(&mut conn)?;
+
+        self.channel_roles_cache.remove(&channel_id);
+
+        Ok(())
+    }
+
+    pub fn remove_channel_role(&self, channel_id: u64, user_id: u64) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let deleted = diesel::delete(
+            channel_roles::table
+                .filter(channel_roles::channel_id.eq(channel_id))
+                .filter(channel_roles::user_id.eq(user_id)),
+        )
+        .execute(&mut conn)?;
+
+        self.channel_roles_cache.remove(&channel_id);
+
+        if deleted == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn get_mirror_connections(&self) -> Result<Vec<MirrorConnection>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         Ok(mirror_connections::table.load(&mut conn)?)
     }
@@ -812,7 +1216,7 @@ impl Database {
         &self,
         connection: MirrorConnection,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         diesel::insert_into(mirror_connections::table)
             .values(&connection)
@@ -826,15 +1230,17 @@ impl Database {
         command_name: &str,
         triggers: &str,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
-        if diesel::update(commands::table)
+        let updated = diesel::update(commands::table)
             .filter(commands::channel_id.eq(channel_id))
             .filter(commands::name.eq(command_name))
             .set(commands::triggers.eq(triggers))
-            .execute(&mut conn)?
-            > 0
-        {
+            .execute(&mut conn)?;
+
+        self.commands_cache.remove(&channel_id);
+
+        if updated > 0 {
             Ok(())
         } else {
             Err(DatabaseError::InvalidValue)
@@ -842,30 +1248,103 @@ impl Database {
     }
 
     pub fn get_all_filters(&self) -> Result<Vec<Filter>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         Ok(filters::table.load(&mut conn)?)
     }
 
     pub fn get_filters_in_channel_id(&self, channel_id: u64) -> Result<Vec<Filter>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
 
         Ok(filters::table
             .filter(filters::channel_id.eq(channel_id))
             .load(&mut conn)?)
     }
 
+    pub fn get_moderation_rules(
+        &self,
+        channel_id: u64,
+    ) -> Result<Vec<ModerationRule>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        Ok(moderation_rules::table
+            .filter(moderation_rules::channel_id.eq(channel_id))
+            .order(moderation_rules::id.asc())
+            .load(&mut conn)?)
+    }
+
+    pub fn add_moderation_rule(&self, rule: NewModerationRule) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        diesel::insert_into(moderation_rules::table)
+            .values(rule)
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn update_moderation_rule(
+        &self,
+        rule_id: u64,
+        rule: NewModerationRule,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let updated = diesel::update(
+            moderation_rules::table
+                .filter(moderation_rules::channel_id.eq(rule.channel_id))
+                .filter(moderation_rules::id.eq(rule_id)),
+        )
+        .set(&rule)
+        .execute(&mut conn)?;
+
+        if updated == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn delete_moderation_rule(
+        &self,
+        channel_id: u64,
+        rule_id: u64,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let deleted = diesel::delete(
+            moderation_rules::table
+                .filter(moderation_rules::channel_id.eq(channel_id))
+                .filter(moderation_rules::id.eq(rule_id)),
+        )
+        .execute(&mut conn)?;
+
+        if deleted == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn get_hebi_data(
         &self,
         channel_id: u64,
+        scope: HebiDataScope,
         key: &str,
     ) -> Result<Option<String>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
+        let now = Utc::now().timestamp() as u64;
 
         let value = hebi_data::table
             .select(hebi_data::value)
             .filter(hebi_data::channel_id.eq(channel_id))
+            .filter(hebi_data::user_id.eq(scope.user_id()))
             .filter(hebi_data::name.eq(key))
+            .filter(
+                hebi_data::expires_at
+                    .is_null()
+                    .or(hebi_data::expires_at.gt(now)),
+            )
             .first::<Option<String>>(&mut conn)
             .optional()?
             .flatten();
@@ -873,31 +1352,117 @@ impl Database {
         Ok(value)
     }
 
+    /// The value should be JSON encoded. Expired data of the channel is cleaned up when setting new values.
     pub fn set_hebi_data(
         &self,
         channel_id: u64,
+        scope: HebiDataScope,
         key: &str,
         value: &str,
+        expires_at: Option<u64>,
     ) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
+        let now = Utc::now().timestamp() as u64;
+
+        diesel::delete(
+            hebi_data::table
+                .filter(hebi_data::channel_id.eq(channel_id))
+                .filter(hebi_data::expires_at.le(now)),
+        )
+        .execute(&mut conn)?;
 
         diesel::replace_into(hebi_data::table)
             .values((
                 hebi_data::channel_id.eq(channel_id),
+                hebi_data::user_id.eq(scope.user_id()),
                 hebi_data::name.eq(key),
                 hebi_data::value.eq(value),
+                hebi_data::expires_at.eq(expires_at),
             ))
             .execute(&mut conn)?;
 
         Ok(())
     }
 
-    pub fn remove_hebi_data(&self, channel_id: u64, key: &str) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+    /// Atomically adds to a numeric value, missing and expired values count as 0.
+    /// The expiry of an existing value is kept.
+    ///
+    /// Fails with `InvalidValue` if the value is not an integer, and with `OutOfRange`
+    /// if the result would not be within the bounds, in which case nothing is written.
+    pub fn increment_hebi_data(
+        &self,
+        channel_id: u64,
+        scope: HebiDataScope,
+        key: &str,
+        amount: i64,
+        bounds: RangeInclusive<i64>,
+    ) -> Result<i64, DatabaseError> {
+        let mut conn = self.conn()?;
+        let now = Utc::now().timestamp() as u64;
+
+        conn.transaction(|conn| {
+            // Creating a missing row up front means that it is locked by its key. Locking a row that
+            // doesn't exist yet with SELECT ... FOR UPDATE takes gap locks, which deadlock when the
+            // first increments of a counter happen at the same time.
+            sql_query("INSERT INTO hebi_data (channel_id, user_id, name) VALUES (?, ?, ?) ON DUPLICATE KEY UPDATE name = name")
+                .bind::<Unsigned<BigInt>, _>(channel_id)
+                .bind::<Unsigned<BigInt>, _>(scope.user_id())
+                .bind::<Text, _>(key)
+                .execute(conn)?;
+
+            let current: Option<(Option<String>, Option<u64>)> = hebi_data::table
+                .select((hebi_data::value, hebi_data::expires_at))
+                .filter(hebi_data::channel_id.eq(channel_id))
+                .filter(hebi_data::user_id.eq(scope.user_id()))
+                .filter(hebi_data::name.eq(key))
+                .for_update()
+                .first(conn)
+                .optional()?;
+
+            let (current_value, expires_at) = match current {
+                Some((_, Some(expires_at))) if expires_at <= now => (0, None),
+                Some((Some(value), expires_at)) => (
+                    serde_json::from_str::<i64>(&value).map_err(|_| DatabaseError::InvalidValue)?,
+                    expires_at,
+                ),
+                Some((None, expires_at)) => (0, expires_at),
+                None => (0, None),
+            };
+
+            // Returning an error rolls back the row that was created above
+            let new_value = current_value
+                .checked_add(amount)
+                .filter(|value| bounds.contains(value))
+                .ok_or(DatabaseError::OutOfRange)?;
+
+            diesel::update(
+                hebi_data::table
+                    .filter(hebi_data::channel_id.eq(channel_id))
+                    .filter(hebi_data::user_id.eq(scope.user_id()))
+                    .filter(hebi_data::name.eq(key)),
+            )
+            .set((
+                hebi_data::value.eq(new_value.to_string()),
+                hebi_data::expires_at.eq(expires_at),
+            ))
+            .execute(conn)?;
+
+            Ok(new_value)
+        })
+    }
+
+    pub fn remove_hebi_data(
+        &self,
+        channel_id: u64,
+        scope: HebiDataScope,
+        key: &str,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
 
         diesel::delete(
             hebi_data::table
                 .filter(hebi_data::channel_id.eq(channel_id))
+                .filter(hebi_data::user_id.eq(scope.user_id()))
                 .filter(hebi_data::name.eq(key)),
         )
         .execute(&mut conn)?;
@@ -905,8 +1470,187 @@ impl Database {
         Ok(())
     }
 
+    /// Lists the unexpired data with keys starting with the prefix, across all scopes when none is specified.
+    pub fn list_hebi_data(
+        &self,
+        channel_id: u64,
+        scope: Option<HebiDataScope>,
+        prefix: &str,
+        limit: i64,
+    ) -> Result<Vec<HebiData>, DatabaseError> {
+        let mut conn = self.conn()?;
+        let now = Utc::now().timestamp() as u64;
+
+        let pattern = format!(
+            "{}%",
+            prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+
+        let mut query = hebi_data::table
+            .filter(hebi_data::channel_id.eq(channel_id))
+            .filter(hebi_data::name.like(pattern))
+            .filter(
+                hebi_data::expires_at
+                    .is_null()
+                    .or(hebi_data::expires_at.gt(now)),
+            )
+            .order((hebi_data::user_id, hebi_data::name))
+            .limit(limit)
+            .into_boxed();
+
+        if let Some(scope) = scope {
+            query = query.filter(hebi_data::user_id.eq(scope.user_id()));
+        }
+
+        Ok(query.load(&mut conn)?)
+    }
+
+    pub fn get_hebi_limits(&self, channel_id: u64) -> Result<Option<HebiLimits>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        Ok(hebi_limits::table
+            .filter(hebi_limits::channel_id.eq(channel_id))
+            .first(&mut conn)
+            .optional()?)
+    }
+
+    pub fn set_hebi_limits(&self, limits: HebiLimits) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        diesel::replace_into(hebi_limits::table)
+            .values(limits)
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn get_hebi_modules(&self, channel_id: u64) -> Result<Vec<HebiModule>, DatabaseError> {
+        Ok(self.get_cached_hebi_modules(channel_id)?.as_ref().clone())
+    }
+
+    /// Modules are loaded on every Hebi evaluation in the channel
+    pub fn get_cached_hebi_modules(
+        &self,
+        channel_id: u64,
+    ) -> Result<Arc<Vec<HebiModule>>, DatabaseError> {
+        if let Some(modules) = self.hebi_modules_cache.get(&channel_id) {
+            return Ok(modules);
+        }
+
+        let mut conn = self.conn()?;
+
+        let modules = Arc::new(
+            hebi_modules::table
+                .filter(hebi_modules::channel_id.eq(channel_id))
+                .order(hebi_modules::name)
+                .load(&mut conn)?,
+        );
+        self.hebi_modules_cache.insert(channel_id, modules.clone());
+
+        Ok(modules)
+    }
+
+    pub fn add_hebi_module(&self, module: HebiModule) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+        let channel_id = module.channel_id;
+
+        diesel::insert_into(hebi_modules::table)
+            .values(module)
+            .execute(&mut conn)?;
+
+        self.hebi_modules_cache.remove(&channel_id);
+
+        Ok(())
+    }
+
+    pub fn update_hebi_module(
+        &self,
+        channel_id: u64,
+        name: &str,
+        source: &str,
+    ) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let updated = diesel::update(
+            hebi_modules::table
+                .filter(hebi_modules::channel_id.eq(channel_id))
+                .filter(hebi_modules::name.eq(name)),
+        )
+        .set(hebi_modules::source.eq(source))
+        .execute(&mut conn)?;
+
+        self.hebi_modules_cache.remove(&channel_id);
+
+        if updated == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn remove_hebi_module(&self, channel_id: u64, name: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let deleted = diesel::delete(
+            hebi_modules::table
+                .filter(hebi_modules::channel_id.eq(channel_id))
+                .filter(hebi_modules::name.eq(name)),
+        )
+        .execute(&mut conn)?;
+
+        self.hebi_modules_cache.remove(&channel_id);
+
+        if deleted == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn get_egress_domains(&self, channel_id: u64) -> Result<Vec<String>, DatabaseError> {
+        let mut conn = self.conn()?;
+
+        Ok(egress_domains::table
+            .select(egress_domains::domain)
+            .filter(egress_domains::channel_id.eq(channel_id))
+            .load(&mut conn)?)
+    }
+
+    pub fn add_egress_domain(&self, channel_id: u64, domain: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        diesel::replace_into(egress_domains::table)
+            .values((
+                egress_domains::channel_id.eq(channel_id),
+                egress_domains::domain.eq(domain),
+            ))
+            .execute(&mut conn)?;
+
+        Ok(())
+    }
+
+    pub fn remove_egress_domain(&self, channel_id: u64, domain: &str) -> Result<(), DatabaseError> {
+        let mut conn = self.conn()?;
+
+        let deleted = diesel::delete(
+            egress_domains::table
+                .filter(egress_domains::channel_id.eq(channel_id))
+                .filter(egress_domains::domain.eq(domain)),
+        )
+        .execute(&mut conn)?;
+
+        if deleted == 0 {
+            Err(DatabaseError::InvalidValue)
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn create_geohub_link(&self, link: GeohubLink) -> Result<(), DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
         diesel::insert_into(geohub_link::table)
             .values(link)
             .execute(&mut conn)?;
@@ -914,7 +1658,7 @@ impl Database {
     }
 
     pub fn get_geohub_link_names(&self, channel_id: u64) -> Result<Vec<String>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
         let values = geohub_link::table
             .select(geohub_link::geohub_name)
             .filter(geohub_link::channel_id.eq(channel_id))
@@ -923,7 +1667,7 @@ impl Database {
     }
 
     pub fn get_geohub_links(&self) -> Result<Vec<GeohubLink>, DatabaseError> {
-        let mut conn = self.conn_pool.get().unwrap();
+        let mut conn = self.conn()?;
         let values = geohub_link::table.load(&mut conn)?;
         Ok(values)
     }
@@ -939,10 +1683,26 @@ impl Database {
     }*/
 }
 
+/// Reads an optional setting from the environment
+fn env_config<T>(name: &str, default: T) -> Result<T, ConnectionError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|e| ConnectionError::BadConnection(format!("Invalid {name} `{value}`: {e}"))),
+        Err(_) => Ok(default),
+    }
+}
+
 #[derive(Debug)]
 pub enum DatabaseError {
     DieselError(diesel::result::Error),
+    PoolError(PoolError),
     InvalidValue,
+    OutOfRange,
 }
 
 impl From<diesel::result::Error> for DatabaseError {
@@ -951,6 +1711,12 @@ impl From<diesel::result::Error> for DatabaseError {
     }
 }
 
+impl From<PoolError> for DatabaseError {
+    fn from(e: PoolError) -> Self {
+        Self::PoolError(e)
+    }
+}
+
 impl From<UserIdentifierError> for DatabaseError {
     fn from(e: UserIdentifierError) -> Self {
         error!("Invalid user identifier: {e:?}");
@@ -965,7 +1731,9 @@ impl Display for DatabaseError {
             "{}",
             match self {
                 DatabaseError::DieselError(e) => format!("Database error: {}", e),
+                DatabaseError::PoolError(e) => format!("Database connection error: {}", e),
                 DatabaseError::InvalidValue => "Invalid value".to_string(),
+                DatabaseError::OutOfRange => "Value out of range".to_string(),
             }
         )
     }
@@ -983,43 +1751,12 @@ impl TokenStorage for Database {
     type UpdateError = anyhow::Error;
 
     async fn load_token(&mut self) -> Result<UserAccessToken, Self::LoadError> {
-        let access_token = self.get_auth("twitch_access_token")?.unwrap_or_default();
-        let refresh_token = self.get_auth("twitch_refresh_token")?.unwrap_or_default();
-
-        let created_at = DateTime::from_utc(
-            DateTime::parse_from_rfc3339(&self.get_auth("twitch_created_at")?.unwrap_or_default())?
-                .naive_utc(),
-            Utc,
-        );
-
-        let expires_at = match self.get_auth("twitch_expires_at")? {
-            Some(date) => Some(DateTime::from_utc(
-                DateTime::parse_from_rfc3339(&date)?.naive_utc(),
-                Utc,
-            )),
-            None => None,
-        };
-
-        Ok(UserAccessToken {
-            access_token,
-            refresh_token,
-            created_at,
-            expires_at,
-        })
+        credentials::load_token(self, String::new()).await
     }
 
     async fn update_token(&mut self, token: &UserAccessToken) -> Result<(), Self::UpdateError> {
         tracing::info!("Refreshed Twitch token!");
 
-        self.set_auth("twitch_access_token", &token.access_token)?;
-        self.set_auth("twitch_refresh_token", &token.refresh_token)?;
-
-        self.set_auth("twitch_created_at", &token.created_at.to_rfc3339())?;
-
-        if let Some(expires_at) = token.expires_at {
-            self.set_auth("twitch_expires_at", &expires_at.to_rfc3339())?;
-        }
-
-        Ok(())
+        credentials::update_token(self, String::new(), token).await
     }
 }
@@ -1,6 +1,6 @@
 use std::str::FromStr;
 
-use crate::platform::ChannelIdentifier;
+use crate::platform::{ChannelIdentifier, Permissions};
 
 use super::schema::*;
 use diesel::Queryable;
@@ -19,14 +19,28 @@ pub struct User {
 }
 
 impl User {
+    /// Takes the identifiers of the other user on the platforms that this user doesn't have.
     pub fn merge(&mut self, other: User) {
-        if self.twitch_id.is_none() && other.twitch_id.is_some() {
-            self.twitch_id = other.twitch_id;
-        }
+        self.twitch_id = self.twitch_id.take().or(other.twitch_id);
+        self.discord_id = self.discord_id.take().or(other.discord_id);
+        self.irc_name = self.irc_name.take().or(other.irc_name);
+        self.local_addr = self.local_addr.take().or(other.local_addr);
+        self.telegram_id = self.telegram_id.take().or(other.telegram_id);
+        self.matrix_id = self.matrix_id.take().or(other.matrix_id);
+    }
 
-        if self.discord_id.is_none() && other.discord_id.is_some() {
-            self.discord_id = other.discord_id;
+    /// Users can't be merged if they have different accounts on the same platform.
+    pub fn can_merge(&self, other: &User) -> bool {
+        fn compatible(first: &Option<String>, second: &Option<String>) -> bool {
+            first.is_none() || second.is_none() || first == second
         }
+
+        compatible(&self.twitch_id, &other.twitch_id)
+            && compatible(&self.discord_id, &other.discord_id)
+            && compatible(&self.irc_name, &other.irc_name)
+            && compatible(&self.local_addr, &other.local_addr)
+            && compatible(&self.telegram_id, &other.telegram_id)
+            && compatible(&self.matrix_id, &other.matrix_id)
     }
 }
 
@@ -61,7 +75,7 @@ pub struct NewChannel<'a> {
     pub channel: &'a str,
 }
 
-#[derive(Queryable, Debug, PartialEq, Eq, Serialize)]
+#[derive(Queryable, Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Command {
     pub name: String,
     pub action: String,
@@ -89,6 +103,82 @@ impl TryFrom<String> for CommandMode {
     }
 }
 
+/// A role given to a user in a channel, in addition to their role on the platform
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Mod,
+    Owner,
+}
+
+impl Role {
+    pub fn permissions(&self) -> Permissions {
+        match self {
+            Role::Mod => Permissions::ChannelMod,
+            Role::Owner => Permissions::ChannelOwner,
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+/// A role of the user in all channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum GlobalRole {
+    /// Can't use any commands
+    Blocked,
+    /// Can use some of the commands meant for channel moderators in any channel
+    Trusted,
+    Admin,
+}
+
+impl GlobalRole {
+    /// The role of a user merged from two accounts. A block on either account is kept,
+    /// otherwise the higher of the roles.
+    pub fn merge(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::Blocked, _) | (_, Self::Blocked) => Self::Blocked,
+            (Self::Admin, _) | (_, Self::Admin) => Self::Admin,
+            (Self::Trusted, Self::Trusted) => Self::Trusted,
+        }
+    }
+}
+
+impl TryFrom<String> for GlobalRole {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+#[derive(Queryable, Debug, Clone, PartialEq, Eq, Serialize)]
+#[diesel(table_name = global_roles)]
+pub struct GlobalRoleAssignment {
+    pub user_id: u64,
+    #[diesel(deserialize_as = String)]
+    pub role: GlobalRole,
+}
+
+#[derive(Queryable, Debug, Clone, PartialEq, Eq, Serialize)]
+#[diesel(table_name = channel_roles)]
+pub struct ChannelRole {
+    #[serde(skip)]
+    pub channel_id: u64,
+    pub user_id: u64,
+    #[diesel(deserialize_as = String)]
+    pub role: Role,
+}
+
 #[derive(Insertable, Debug, PartialEq, Eq)]
 #[diesel(table_name = commands)]
 pub struct NewCommand<'a> {
@@ -108,6 +198,58 @@ pub struct UserData {
     pub user_id: u64,
 }
 
+/// The user data that users can set themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ProfileKey {
+    Location,
+    LastFM,
+    Timezone,
+    Pronouns,
+    Birthday,
+}
+
+impl ProfileKey {
+    pub const ALL: [ProfileKey; 5] = [
+        ProfileKey::Location,
+        ProfileKey::LastFM,
+        ProfileKey::Timezone,
+        ProfileKey::Pronouns,
+        ProfileKey::Birthday,
+    ];
+
+    /// Name of the value in `user_data`
+    pub fn data_name(&self) -> &'static str {
+        match self {
+            ProfileKey::Location => "location",
+            ProfileKey::LastFM => "lastfm_name",
+            ProfileKey::Timezone => "timezone",
+            ProfileKey::Pronouns => "pronouns",
+            ProfileKey::Birthday => "birthday",
+        }
+    }
+
+    pub fn from_data_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|key| key.data_name() == name)
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct ProfileValue {
+    pub key: ProfileKey,
+    pub value: String,
+    pub public: bool,
+}
+
+#[derive(Queryable, Insertable, Debug)]
+#[diesel(table_name = link_codes)]
+pub struct LinkCode {
+    pub code: String,
+    pub user_id: u64,
+    pub expires_at: u64,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = user_data)]
 pub struct UserDataUserId {
@@ -167,12 +309,131 @@ pub struct Filter {
     pub replacement: Option<String>,
 }
 
-#[derive(Queryable, Insertable)]
+#[derive(Queryable, Debug, Clone, Serialize)]
+pub struct ModerationRule {
+    pub id: u64,
+    #[serde(skip)]
+    pub channel_id: u64,
+    #[diesel(deserialize_as = String)]
+    pub kind: ModerationRuleKind,
+    pub pattern: Option<String>,
+    pub threshold: Option<u32>,
+    pub window_secs: Option<u32>,
+    #[diesel(deserialize_as = String)]
+    pub action: ModerationRuleAction,
+    pub timeout_duration: Option<u32>,
+    pub reason: Option<String>,
+}
+
+#[derive(Insertable, AsChangeset, Debug)]
+#[diesel(table_name = moderation_rules, treat_none_as_null = true)]
+pub struct NewModerationRule {
+    pub channel_id: u64,
+    pub kind: String,
+    pub pattern: Option<String>,
+    pub threshold: Option<u32>,
+    pub window_secs: Option<u32>,
+    pub action: String,
+    pub timeout_duration: Option<u32>,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, strum::Display)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationRuleKind {
+    /// Messages matching the regex in `pattern`
+    BannedPhrase,
+    /// Links to domains not in the comma-separated `pattern`
+    Links,
+    /// Messages with at least `threshold` percent of uppercase letters
+    Caps,
+    /// Messages with more than `threshold` emotes
+    Emotes,
+    /// Messages with at least `threshold` percent of symbols
+    Symbols,
+    /// The same message sent `threshold` times within `window_secs`
+    Repetition,
+    /// More than `threshold` messages sent within `window_secs`
+    Rate,
+}
+
+impl TryFrom<String> for ModerationRuleKind {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, EnumString, strum::Display)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ModerationRuleAction {
+    Delete,
+    Timeout,
+    Warn,
+}
+
+impl TryFrom<String> for ModerationRuleAction {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+#[derive(Queryable, Insertable, Debug)]
 #[diesel(table_name = hebi_data)]
 pub struct HebiData {
     pub channel_id: u64,
     pub name: String,
+    /// JSON encoded value
     pub value: Option<String>,
+    /// 0 for data that is shared by the whole channel
+    pub user_id: u64,
+    /// Unix timestamp in seconds
+    pub expires_at: Option<u64>,
+}
+
+/// Whether Hebi data belongs to the whole channel or a single user in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HebiDataScope {
+    Channel,
+    User(u64),
+}
+
+impl HebiDataScope {
+    pub fn user_id(self) -> u64 {
+        match self {
+            HebiDataScope::Channel => 0,
+            HebiDataScope::User(user_id) => user_id,
+        }
+    }
+}
+
+/// Hebi module that can be imported by the commands of a channel
+#[derive(Queryable, Insertable, Debug, Clone, Serialize)]
+#[diesel(table_name = hebi_modules)]
+pub struct HebiModule {
+    #[serde(skip)]
+    pub channel_id: u64,
+    pub name: String,
+    pub source: String,
+}
+
+/// Per-channel overrides of the Hebi sandbox limits, `None` means the default is used
+#[derive(Queryable, Insertable, Debug, Clone, Default, Serialize, Deserialize)]
+#[diesel(table_name = hebi_limits)]
+pub struct HebiLimits {
+    #[serde(skip)]
+    pub channel_id: u64,
+    pub fuel: Option<u32>,
+    pub native_output_kb: Option<u32>,
+    pub http_requests: Option<u32>,
+    pub response_kb: Option<u32>,
+    pub output_length: Option<u32>,
+    pub timeout_secs: Option<u32>,
 }
 
 #[derive(Queryable, Insertable)]
@@ -187,7 +448,7 @@ pub struct GeohubLink {
 mod tests {
     use crate::platform::ChannelIdentifier;
 
-    use super::Channel;
+    use super::{Channel, GlobalRole};
 
     #[test]
     fn channel_to_identifier() {
@@ -202,4 +463,20 @@ mod tests {
             ChannelIdentifier::TwitchChannel((String::from("123"), None))
         )
     }
+
+    #[test]
+    fn merged_global_roles() {
+        assert_eq!(
+            GlobalRole::Admin.merge(GlobalRole::Trusted),
+            GlobalRole::Admin
+        );
+        assert_eq!(
+            GlobalRole::Trusted.merge(GlobalRole::Blocked),
+            GlobalRole::Blocked
+        );
+        assert_eq!(
+            GlobalRole::Blocked.merge(GlobalRole::Admin),
+            GlobalRole::Blocked
+        );
+    }
 }
@@ -9,6 +9,15 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    channel_roles (channel_id, user_id) {
+        channel_id -> Unsigned<Bigint>,
+        user_id -> Unsigned<Bigint>,
+        #[max_length = 16]
+        role -> Varchar,
+    }
+}
+
 diesel::table! {
     channels (id) {
         id -> Unsigned<Bigint>,
@@ -33,6 +42,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    egress_domains (channel_id, domain) {
+        channel_id -> Unsigned<Bigint>,
+        #[max_length = 255]
+        domain -> Varchar,
+    }
+}
+
 diesel::table! {
     eventsub_triggers (id) {
         #[max_length = 255]
@@ -69,11 +86,51 @@ diesel::table! {
 }
 
 diesel::table! {
-    hebi_data (channel_id, name) {
+    global_roles (user_id) {
+        user_id -> Unsigned<Bigint>,
+        #[max_length = 16]
+        role -> Varchar,
+    }
+}
+
+diesel::table! {
+    hebi_data (channel_id, user_id, name) {
         channel_id -> Unsigned<Bigint>,
         #[max_length = 255]
         name -> Varchar,
         value -> Nullable<Text>,
+        user_id -> Unsigned<Bigint>,
+        expires_at -> Nullable<Unsigned<Bigint>>,
+    }
+}
+
+diesel::table! {
+    hebi_limits (channel_id) {
+        channel_id -> Unsigned<Bigint>,
+        fuel -> Nullable<Unsigned<Integer>>,
+        native_output_kb -> Nullable<Unsigned<Integer>>,
+        http_requests -> Nullable<Unsigned<Integer>>,
+        response_kb -> Nullable<Unsigned<Integer>>,
+        output_length -> Nullable<Unsigned<Integer>>,
+        timeout_secs -> Nullable<Unsigned<Integer>>,
+    }
+}
+
+diesel::table! {
+    hebi_modules (channel_id, name) {
+        channel_id -> Unsigned<Bigint>,
+        #[max_length = 255]
+        name -> Varchar,
+        source -> Text,
+    }
+}
+
+diesel::table! {
+    link_codes (code) {
+        #[max_length = 16]
+        code -> Varchar,
+        user_id -> Unsigned<Bigint>,
+        expires_at -> Unsigned<Bigint>,
     }
 }
 
@@ -84,6 +141,23 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    moderation_rules (id) {
+        id -> Unsigned<Bigint>,
+        channel_id -> Unsigned<Bigint>,
+        #[max_length = 63]
+        kind -> Varchar,
+        pattern -> Nullable<Text>,
+        threshold -> Nullable<Unsigned<Integer>>,
+        window_secs -> Nullable<Unsigned<Integer>>,
+        #[max_length = 63]
+        action -> Varchar,
+        timeout_duration -> Nullable<Unsigned<Integer>>,
+        #[max_length = 255]
+        reason -> Nullable<Varchar>,
+    }
+}
+
 diesel::table! {
     prefixes (channel_id) {
         channel_id -> Unsigned<Bigint>,
@@ -123,24 +197,39 @@ diesel::table! {
     }
 }
 
+diesel::joinable!(channel_roles -> channels (channel_id));
+diesel::joinable!(channel_roles -> users (user_id));
 diesel::joinable!(commands -> channels (channel_id));
+diesel::joinable!(egress_domains -> channels (channel_id));
 diesel::joinable!(filters -> channels (channel_id));
 diesel::joinable!(geohub_link -> channels (channel_id));
 diesel::joinable!(geohub_link -> users (user_id));
+diesel::joinable!(global_roles -> users (user_id));
 diesel::joinable!(hebi_data -> channels (channel_id));
+diesel::joinable!(hebi_limits -> channels (channel_id));
+diesel::joinable!(hebi_modules -> channels (channel_id));
+diesel::joinable!(link_codes -> users (user_id));
+diesel::joinable!(moderation_rules -> channels (channel_id));
 diesel::joinable!(prefixes -> channels (channel_id));
 diesel::joinable!(user_data -> users (user_id));
 diesel::joinable!(web_sessions -> users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
     auth,
+    channel_roles,
     channels,
     commands,
+    egress_domains,
     eventsub_triggers,
     filters,
     geohub_link,
+    global_roles,
     hebi_data,
+    hebi_limits,
+    hebi_modules,
+    link_codes,
     mirror_connections,
+    moderation_rules,
     prefixes,
     user_data,
     users,
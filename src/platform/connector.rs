@@ -148,5 +148,7 @@ pub async fn get_connector_permissions(
 
     let permissions_response: PermissionsResponse = serde_json::from_slice(&message.payload)
         .context("Could not deserialize response payload")?;
-    permissions_response.map_err(|err| anyhow!("{err}"))
+    permissions_response
+        .map(Permissions::from)
+        .map_err(|err| anyhow!("{err}"))
 }
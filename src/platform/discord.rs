@@ -7,7 +7,7 @@ use twilight_model::{gateway::payload::incoming::MessageCreate, guild::Permissio
 
 use crate::command_handler::CommandHandler;
 
-use super::{ChannelIdentifier, ChatPlatform, PlatformContext, UserIdentifier};
+use super::{ChannelIdentifier, ChatPlatform, MessageReference, PlatformContext, UserIdentifier};
 
 #[derive(Clone)]
 pub struct Discord {
@@ -169,4 +169,30 @@ impl PlatformContext for DiscordPlatformContext<'_> {
     fn get_prefixes(&self) -> Vec<&str> {
         vec![&self.prefix, &self.self_mention]
     }
+
+    fn get_message_reference(&self) -> Option<MessageReference> {
+        Some(MessageReference::Discord {
+            channel_id: self.msg.channel_id.get(),
+            message_id: self.msg.id.get(),
+        })
+    }
+
+    fn get_emote_count(&self) -> usize {
+        // Custom emotes are sent as <:name:id> or <a:name:id>
+        self.msg
+            .content
+            .split('<')
+            .skip(1)
+            .filter(|part| {
+                let emote = part.split('>').next().unwrap_or_default();
+                let mut fields = emote.trim_start_matches('a').split(':').skip(1);
+
+                matches!(
+                    (fields.next(), fields.next(), fields.next()),
+                    (Some(name), Some(id), None)
+                        if !name.is_empty() && id.parse::<u64>().is_ok()
+                )
+            })
+            .count()
+    }
 }
@@ -17,7 +17,27 @@ use std::net::IpAddr;
 use std::str::FromStr;
 use tracing::error;
 
-pub use connector_schema::Permissions;
+/// Permission levels, ordered from the least to the most privileged.
+/// The numeric values are exposed in the web API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Permissions {
+    Default = 0,
+    Vip = 3,
+    ChannelMod = 5,
+    ChannelOwner = 7,
+    Admin = 10,
+}
+
+impl From<connector_schema::Permissions> for Permissions {
+    fn from(permissions: connector_schema::Permissions) -> Self {
+        match permissions {
+            connector_schema::Permissions::Default => Self::Default,
+            connector_schema::Permissions::ChannelMod => Self::ChannelMod,
+            connector_schema::Permissions::ChannelOwner => Self::ChannelOwner,
+            connector_schema::Permissions::Admin => Self::Admin,
+        }
+    }
+}
 
 #[async_trait]
 pub trait ChatPlatform {
@@ -45,6 +65,21 @@ pub trait PlatformContext: Debug {
     fn get_server_timestamp(&self) -> Option<DateTime<Utc>> {
         None
     }
+
+    /// Reference to the message being handled, used for moderating it.
+    fn get_message_reference(&self) -> Option<MessageReference> {
+        None
+    }
+
+    fn get_emote_count(&self) -> usize {
+        0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MessageReference {
+    Twitch { message_id: String },
+    Discord { channel_id: u64, message_id: u64 },
 }
 
 #[derive(Clone)]
@@ -68,12 +103,19 @@ impl Debug for ServerPlatformContext {
 #[async_trait]
 impl PlatformContext for ServerPlatformContext {
     async fn get_permissions_internal(&self) -> Permissions {
-        let user = self
+        let executing_user = self.executing_user.clone();
+        let user = match self
             .cmd
             .db
-            .get_user(&self.executing_user)
-            .expect("DB error")
-            .expect("Invalid user");
+            .run(move |db| db.get_user(&executing_user))
+            .await
+        {
+            Ok(user) => user.expect("Invalid user"),
+            Err(e) => {
+                tracing::error!("Could not get the executing user: {e}");
+                return Permissions::Default;
+            }
+        };
 
         self.cmd
             .get_permissions_in_channel(user, &self.target_channel)
@@ -142,6 +184,17 @@ impl fmt::Display for UserIdentifier {
 }
 
 impl UserIdentifier {
+    pub fn get_platform_name(&self) -> &'static str {
+        match self {
+            UserIdentifier::TwitchID(_) => "twitch",
+            UserIdentifier::DiscordID(_) => "discord",
+            UserIdentifier::TelegramId(_) => "telegram",
+            UserIdentifier::IrcName(_) => "irc",
+            UserIdentifier::IpAddr(_) => "local",
+            UserIdentifier::MatrixId(_) => "matrix",
+        }
+    }
+
     pub fn from_string(s: &str) -> Result<Self, UserIdentifierError> {
         tracing::info!("parsing user identifier {}", s);
 
@@ -1,34 +1,43 @@
+mod queue;
+
 use std::collections::HashSet;
-use std::sync::Arc;
+use std::env;
+use std::sync::{Arc, Mutex};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
-use dashmap::DashMap;
 use std::fmt::Debug;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::{self, UnboundedSender};
 use tokio::task;
-use tokio::time::sleep;
+use tokio::time::sleep_until;
 use twitch_irc::login::{LoginCredentials, RefreshingLoginCredentials};
 use twitch_irc::message::{Badge, PrivmsgMessage, ServerMessage, TwitchUserBasics, WhisperMessage};
 use twitch_irc::{ClientConfig, SecureTCPTransport, TwitchIRCClient};
 
+use crate::command_handler::twitch_api::helix::{HelixApi, WhisperError};
 use crate::command_handler::CommandHandler;
 use crate::database::Database;
-use crate::platform::{ChannelIdentifier, PlatformContext};
+use crate::platform::{ChannelIdentifier, MessageReference, PlatformContext};
 
+use self::queue::{OutboundQueue, WhisperQueue};
 use super::{ChatPlatform, Permissions, UserIdentifier};
 
+pub use self::queue::QueueMetrics;
+
 pub type Credentials = RefreshingLoginCredentials<Database>;
 pub type TwitchClient = TwitchIRCClient<SecureTCPTransport, Credentials>;
 
 pub const MSG_LENGTH_LIMIT: usize = 420;
+/// How long whisper replies are paused after Twitch refuses to send one, doubled on every repeated refusal
+const WHISPER_RETRY_INITIAL: Duration = Duration::from_secs(5 * 60);
+const WHISPER_RETRY_MAX: Duration = Duration::from_secs(6 * 60 * 60);
 
 #[derive(Clone)]
 pub struct Twitch {
     command_handler: CommandHandler,
     possible_prefixes: Arc<[String; 5]>,
-    last_messages: Arc<DashMap<String, String>>,
+    whisper_backoff: Arc<Mutex<WhisperBackoff>>,
 }
 
 #[async_trait]
@@ -61,7 +70,7 @@ impl ChatPlatform for Twitch {
         Ok(Box::new(Self {
             command_handler,
             possible_prefixes,
-            last_messages: Arc::new(DashMap::new()),
+            whisper_backoff: Arc::default(),
         }))
     }
 
@@ -82,7 +91,18 @@ impl ChatPlatform for Twitch {
 
         *twitch_api.chat_sender.lock().await = Some(tx.clone());
 
-        let channels = self.command_handler.db.get_channels().unwrap();
+        let verified = env::var("TWITCH_VERIFIED_BOT").map_or(false, |value| value == "1");
+        let mut queue = OutboundQueue::new(verified, twitch_api.queue_metrics.clone());
+        let mut whispers = WhisperQueue::new(twitch_api.queue_metrics.clone());
+        let helix_api = twitch_api.helix_api.clone();
+        let whisper_backoff = self.whisper_backoff.clone();
+
+        let channels = self
+            .command_handler
+            .db
+            .run(|db| db.get_channels())
+            .await
+            .unwrap();
 
         let channel_ids: Vec<&str> = channels
             .iter()
@@ -118,19 +138,55 @@ impl ChatPlatform for Twitch {
         drop(platform_handler);
 
         tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                tracing::trace!("Received Twitch sender message: {:?}", msg);
-                match msg {
-                    SenderMessage::Privmsg(pm) => {
+            loop {
+                let next_ready = match queue.next_ready(Instant::now()) {
+                    Ok(pm) => {
                         if let Err(error) = send_message(pm, &client).await {
                             tracing::error!("Failed to send message: {error}");
                         }
+                        continue;
                     }
+                    Err(next_ready) => next_ready,
+                };
+
+                let next_whisper = match whispers.next_ready(Instant::now()) {
+                    Ok(whisper) => {
+                        // Sent separately so that a slow API request doesn't hold up the chat messages
+                        tokio::spawn(send_whisper(
+                            whisper,
+                            helix_api.clone(),
+                            whisper_backoff.clone(),
+                        ));
+                        continue;
+                    }
+                    Err(next_whisper) => next_whisper,
+                };
+
+                let msg = match next_ready.into_iter().chain(next_whisper).min() {
+                    Some(ready_at) => tokio::select! {
+                        msg = rx.recv() => msg,
+                        _ = sleep_until(ready_at.into()) => continue,
+                    },
+                    None => rx.recv().await,
+                };
+
+                let Some(msg) = msg else {
+                    break;
+                };
+
+                tracing::trace!("Received Twitch sender message: {:?}", msg);
+                match msg {
+                    SenderMessage::Privmsg(pm) => queue.push(pm),
+                    SenderMessage::Whisper(whisper) => whispers.push(whisper),
                     SenderMessage::JoinChannel(channel_login) => {
                         if let Err(e) = client.join(channel_login) {
                             tracing::error!("Failed to join channel: {}", e);
                         }
                     }
+                    SenderMessage::UserState {
+                        channel_login,
+                        elevated,
+                    } => queue.set_elevated(&channel_login, elevated),
                 }
             }
         });
@@ -142,6 +198,15 @@ impl ChatPlatform for Twitch {
                     ServerMessage::Whisper(whisper) => {
                         self.handle_message(whisper, tx.clone()).await
                     }
+                    ServerMessage::UserState(state) => {
+                        let elevated = get_badge_permissions(&state.badges) >= Permissions::Vip;
+
+                        tx.send(SenderMessage::UserState {
+                            channel_login: state.channel_login,
+                            elevated,
+                        })
+                        .unwrap();
+                    }
                     _ => (),
                 }
             }
@@ -224,17 +289,7 @@ pub struct TwitchExecutionContext<M: TwitchMessage + std::marker::Sync + Clone>
 #[async_trait]
 impl<T: TwitchMessage + Sync + Clone + Debug> PlatformContext for TwitchExecutionContext<T> {
     async fn get_permissions_internal(&self) -> Permissions {
-        let mut permissions = Permissions::Default;
-
-        for badge in self.msg.get_badges() {
-            match badge.name.as_str() {
-                "broadcaster" => permissions = Permissions::ChannelOwner,
-                "moderator" => permissions = Permissions::ChannelMod,
-                _ => (),
-            }
-        }
-
-        permissions
+        get_badge_permissions(self.msg.get_badges())
     }
 
     fn get_channel(&self) -> ChannelIdentifier {
@@ -261,6 +316,57 @@ impl<T: TwitchMessage + Sync + Clone + Debug> PlatformContext for TwitchExecutio
     fn get_server_timestamp(&self) -> Option<DateTime<Utc>> {
         self.msg.get_server_timestamp()
     }
+
+    fn get_message_reference(&self) -> Option<MessageReference> {
+        self.msg.get_privmsg().map(|pm| MessageReference::Twitch {
+            message_id: pm.message_id.clone(),
+        })
+    }
+
+    fn get_emote_count(&self) -> usize {
+        self.msg.get_privmsg().map_or(0, |pm| pm.emotes.len())
+    }
+}
+
+fn get_badge_permissions(badges: &[Badge]) -> Permissions {
+    badges
+        .iter()
+        .map(|badge| match badge.name.as_str() {
+            "broadcaster" => Permissions::ChannelOwner,
+            "moderator" => Permissions::ChannelMod,
+            "vip" => Permissions::Vip,
+            _ => Permissions::Default,
+        })
+        .max()
+        .unwrap_or(Permissions::Default)
+}
+
+/// Pauses whisper replies after the bot is refused sending them, as retrying right away would fail the same way.
+#[derive(Debug, Default)]
+struct WhisperBackoff {
+    paused_until: Option<Instant>,
+    next_pause: Option<Duration>,
+}
+
+impl WhisperBackoff {
+    fn paused_for(&self) -> Option<Duration> {
+        self.paused_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+    }
+
+    /// Pauses the replies, returning for how long
+    fn pause(&mut self) -> Duration {
+        let pause = self.next_pause.unwrap_or(WHISPER_RETRY_INITIAL);
+
+        self.paused_until = Some(Instant::now() + pause);
+        self.next_pause = Some((pause * 2).min(WHISPER_RETRY_MAX));
+
+        pause
+    }
+
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
 }
 
 impl Twitch {
@@ -271,26 +377,42 @@ impl Twitch {
     ) {
         let Self {
             command_handler,
-            last_messages,
             possible_prefixes,
             ..
         } = self.clone();
 
         task::spawn(async move {
-            let prefixes = if let Some(custom_prefix) = command_handler
+            if let Some(pm) = msg.get_privmsg() {
+                let platform_handler = command_handler.platform_handler.read().await;
+
+                if let Some(twitch_api) = &platform_handler.twitch_api {
+                    twitch_api.sync_channel_role(
+                        &pm.channel_id,
+                        &pm.sender.id,
+                        get_badge_permissions(&pm.badges),
+                    );
+                }
+            }
+
+            let channel_identifier = ChannelIdentifier::TwitchChannel((
+                match msg.get_channel() {
+                    Some((channel_id, _)) => channel_id.to_string(),
+                    None => msg.get_sender().id.clone(),
+                },
+                None,
+            ));
+
+            let prefixes = match command_handler
                 .db
-                .get_prefix_in_channel(&ChannelIdentifier::TwitchChannel((
-                    match msg.get_channel() {
-                        Some((channel_id, _)) => channel_id.to_string(),
-                        None => msg.get_sender().id.clone(),
-                    },
-                    None,
-                )))
-                .expect("DB error")
+                .run(move |db| db.get_prefix_in_channel(&channel_identifier))
+                .await
             {
-                vec![custom_prefix]
-            } else {
-                possible_prefixes.to_vec()
+                Ok(Some(custom_prefix)) => vec![custom_prefix],
+                Ok(None) => possible_prefixes.to_vec(),
+                Err(e) => {
+                    tracing::error!("Could not get the channel prefix: {e}");
+                    possible_prefixes.to_vec()
+                }
             };
 
             let context = TwitchExecutionContext {
@@ -312,99 +434,71 @@ impl Twitch {
                 recieved_instant.elapsed().as_millis()
             );
 
-            if let Some(mut response) = response {
+            if let Some(response) = response {
                 if response.trim().is_empty() {
                     tracing::info!("Empty command response");
                     return;
                 }
 
-                let channel = match msg.get_channel() {
-                    Some((channel_id, _)) => channel_id,
-                    None => &msg.get_sender().id,
-                };
-
-                if let Some(last_msg) = last_messages.get(channel) {
-                    if *last_msg == response {
-                        tracing::info!("Detected same matching message, adding an empty character");
-
-                        let magic_char = char::from_u32(0x000E0000).unwrap();
-
-                        if response.ends_with(magic_char) {
-                            response.remove(response.len() - 1);
-                        } else {
-                            response.push(' ');
-                            response.push(magic_char);
-                        }
-                    }
-                }
-
-                last_messages.insert(channel.to_string(), response.clone());
-
                 tracing::info!("Replying with {}", response);
 
-                if let Some(pm) = msg.get_privmsg() {
-                    tx.send(SenderMessage::Privmsg(Privmsg {
+                let message = match msg.get_privmsg() {
+                    Some(pm) => SenderMessage::Privmsg(Privmsg {
                         channel_login: pm.channel_login.clone(),
                         message: response,
                         reply_to_id: Some(pm.message_id.clone()),
-                    }))
-                    .unwrap();
-                } else {
-                    tx.send(SenderMessage::Privmsg(Privmsg {
-                        channel_login: msg.get_sender().login.clone(),
-                        message: format!("/w {} {}", msg.get_sender().login, response),
-                        reply_to_id: None,
-                    }))
-                    .unwrap();
-                }
+                    }),
+                    None => SenderMessage::Whisper(Whisper {
+                        to_user_id: msg.get_sender().id.clone(),
+                        message: response,
+                    }),
+                };
 
-                sleep(Duration::from_secs(1)).await; // This is needed to adhere to the twitch rate limits
+                tx.send(message).unwrap();
             }
         });
     }
 }
 
-async fn send_message(mut pm: Privmsg, client: &TwitchClient) -> Result<(), anyhow::Error> {
-    let send = |pm: Privmsg| {
-        let client = client.clone();
-        async move {
-            match pm.reply_to_id {
-                Some(reply_to_id) => {
-                    client
-                        .say_in_response(pm.channel_login.clone(), pm.message, Some(reply_to_id))
-                        .await
-                }
-                None => client.privmsg(pm.channel_login, pm.message).await,
-            }
-        }
-    };
-
-    while pm.message.len() > MSG_LENGTH_LIMIT {
-        let mut index = MSG_LENGTH_LIMIT - 1;
+async fn send_whisper(
+    whisper: Whisper,
+    helix_api: HelixApi<Credentials>,
+    whisper_backoff: Arc<Mutex<WhisperBackoff>>,
+) {
+    if let Some(retry_in) = whisper_backoff.lock().unwrap().paused_for() {
+        tracing::warn!(
+            "Whispers are unavailable, dropping the reply (retrying in {}s)",
+            retry_in.as_secs()
+        );
+        return;
+    }
 
-        while !pm.message.is_char_boundary(index) {
-            index -= 1;
+    match helix_api
+        .send_whisper(&whisper.to_user_id, &whisper.message)
+        .await
+    {
+        Ok(()) => whisper_backoff.lock().unwrap().reset(),
+        Err(WhisperError::PhoneNotVerified) => {
+            let retry_in = whisper_backoff.lock().unwrap().pause();
+            tracing::warn!(
+                "The bot account has no verified phone number, whisper replies are paused for {}s",
+                retry_in.as_secs()
+            );
         }
+        Err(e) => tracing::error!("Failed to send whisper: {e}"),
+    }
+}
 
-        let mut rest = pm.message.split_off(index);
-
-        if pm.message.chars().last().map(|c| c.is_whitespace()) != Some(true) {
-            let mut words = pm.message.split_whitespace();
-            if let Some(last_word) = words.next_back() {
-                rest = format!("{}{}", last_word, rest);
-            }
-            pm.message = words.collect::<Vec<&str>>().join(" ").to_owned();
+async fn send_message(pm: Privmsg, client: &TwitchClient) -> Result<(), anyhow::Error> {
+    match pm.reply_to_id {
+        Some(reply_to_id) => {
+            client
+                .say_in_response(pm.channel_login, pm.message, Some(reply_to_id))
+                .await?
         }
-
-        send(pm.clone()).await?;
-
-        pm.message = rest;
-
-        sleep(Duration::from_secs(1)).await;
+        None => client.privmsg(pm.channel_login, pm.message).await?,
     }
-    send(pm).await?;
 
-    sleep(Duration::from_secs(1)).await;
     Ok(())
 }
 
@@ -412,7 +506,14 @@ async fn send_message(mut pm: Privmsg, client: &TwitchClient) -> Result<(), anyh
 #[derive(Clone, Debug)]
 pub enum SenderMessage {
     Privmsg(Privmsg),
+    /// Replies to whispers are sent through the Helix API, with their own rate limits
+    Whisper(Whisper),
     JoinChannel(String),
+    /// Whether the bot is a moderator or VIP in the channel, which affects the rate limits
+    UserState {
+        channel_login: String,
+        elevated: bool,
+    },
 }
 
 #[derive(Clone, Debug)]
@@ -422,11 +523,16 @@ pub struct Privmsg {
     pub reply_to_id: Option<String>,
 }
 
+#[derive(Clone, Debug)]
+pub struct Whisper {
+    pub to_user_id: String,
+    pub message: String,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::TwitchExecutionContext;
-    use crate::platform::PlatformContext;
-    use connector_schema::Permissions;
+    use super::{TwitchExecutionContext, WhisperBackoff, WHISPER_RETRY_INITIAL, WHISPER_RETRY_MAX};
+    use crate::platform::{Permissions, PlatformContext};
     use pretty_assertions::assert_eq;
     use twitch_irc::message::{IRCMessage, PrivmsgMessage};
 
@@ -443,4 +549,37 @@ mod tests {
         let permissions = twitch_context.get_permissions_internal().await;
         assert_eq!(permissions, Permissions::ChannelMod);
     }
+
+    #[tokio::test]
+    async fn vip_message_permissions() {
+        let raw = "@badge-info=;badges=vip/1;color=#1E90FF;display-name=Supibot;emotes=;first-msg=0;flags=;id=473c54c7-7bad-4b8a-aa32-d526a1bebbf4;mod=0;returning-chatter=0;room-id=31400525;subscriber=0;tmi-sent-ts=1676400113453;turbo=0;user-id=68136884;user-type= :supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #supinic :%whoami";
+        let irc_message = IRCMessage::parse(raw).unwrap();
+        let privmsg = PrivmsgMessage::try_from(irc_message).unwrap();
+
+        let twitch_context = TwitchExecutionContext {
+            msg: privmsg,
+            prefixes: vec!["%".to_owned()],
+        };
+        let permissions = twitch_context.get_permissions_internal().await;
+        assert_eq!(permissions, Permissions::Vip);
+    }
+
+    #[test]
+    fn whisper_backoff() {
+        let mut backoff = WhisperBackoff::default();
+        assert!(backoff.paused_for().is_none());
+
+        assert_eq!(backoff.pause(), WHISPER_RETRY_INITIAL);
+        assert!(backoff.paused_for().is_some());
+        assert_eq!(backoff.pause(), WHISPER_RETRY_INITIAL * 2);
+
+        for _ in 0..10 {
+            backoff.pause();
+        }
+        assert_eq!(backoff.pause(), WHISPER_RETRY_MAX);
+
+        backoff.reset();
+        assert!(backoff.paused_for().is_none());
+        assert_eq!(backoff.pause(), WHISPER_RETRY_INITIAL);
+    }
 }
@@ -0,0 +1,396 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Privmsg, Whisper, MSG_LENGTH_LIMIT};
+
+/// Maximum amount of pending messages per channel, the oldest ones get dropped after that
+pub const CHANNEL_QUEUE_CAPACITY: usize = 10;
+pub const WHISPER_QUEUE_CAPACITY: usize = 10;
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(30);
+/// Messages in channels where the bot is not a moderator or VIP
+const NORMAL_GLOBAL_LIMIT: usize = 20;
+const ELEVATED_GLOBAL_LIMIT: usize = 100;
+const VERIFIED_GLOBAL_LIMIT: usize = 7500;
+/// Twitch only allows one message per second in a channel where the bot is not a moderator or VIP
+const NORMAL_CHANNEL_INTERVAL: Duration = Duration::from_millis(1100);
+/// Twitch rejects identical messages sent within this time
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+const MAGIC_CHAR: char = '\u{E0000}';
+/// Helix allows sending 3 whispers per second and 100 per minute
+const WHISPER_SECOND_LIMIT: usize = 3;
+const WHISPER_MINUTE_LIMIT: usize = 100;
+
+#[derive(Debug, Default)]
+pub struct QueueMetrics {
+    pub sent: AtomicU64,
+    pub dropped: AtomicU64,
+    pub deduplicated: AtomicU64,
+}
+
+impl QueueMetrics {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} sent, {} dropped, {} deduplicated",
+            self.sent.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.deduplicated.load(Ordering::Relaxed)
+        )
+    }
+}
+
+struct SlidingWindow {
+    limit: usize,
+    window: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl SlidingWindow {
+    fn new(limit: usize, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            sent: VecDeque::new(),
+        }
+    }
+
+    fn available_at(&mut self, now: Instant) -> Instant {
+        while self
+            .sent
+            .front()
+            .map_or(false, |sent_at| now.duration_since(*sent_at) >= self.window)
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() < self.limit {
+            now
+        } else {
+            self.sent[self.sent.len() - self.limit] + self.window
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.sent.push_back(now);
+    }
+}
+
+#[derive(Default)]
+struct ChannelQueue {
+    pending: VecDeque<Privmsg>,
+    last_sent: Option<(Instant, String)>,
+    elevated: bool,
+}
+
+impl ChannelQueue {
+    fn available_at(&self, now: Instant) -> Instant {
+        match &self.last_sent {
+            Some((sent_at, _)) if !self.elevated => (*sent_at + NORMAL_CHANNEL_INTERVAL).max(now),
+            _ => now,
+        }
+    }
+}
+
+/// Schedules outgoing messages across channels, respecting the Twitch rate limits.
+pub struct OutboundQueue {
+    channels: HashMap<String, ChannelQueue>,
+    global: SlidingWindow,
+    global_normal: SlidingWindow,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl OutboundQueue {
+    pub fn new(verified: bool, metrics: Arc<QueueMetrics>) -> Self {
+        let (global_limit, normal_limit) = match verified {
+            true => (VERIFIED_GLOBAL_LIMIT, VERIFIED_GLOBAL_LIMIT),
+            false => (ELEVATED_GLOBAL_LIMIT, NORMAL_GLOBAL_LIMIT),
+        };
+
+        Self {
+            channels: HashMap::new(),
+            global: SlidingWindow::new(global_limit, RATE_LIMIT_WINDOW),
+            global_normal: SlidingWindow::new(normal_limit, RATE_LIMIT_WINDOW),
+            metrics,
+        }
+    }
+
+    pub fn push(&mut self, pm: Privmsg) {
+        let queue = self.channels.entry(pm.channel_login.clone()).or_default();
+
+        if queue
+            .pending
+            .iter()
+            .any(|pending| pending.message == pm.message)
+        {
+            tracing::info!("Skipping duplicate message in {}", pm.channel_login);
+            self.metrics.deduplicated.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        for part in split_message(pm) {
+            if queue.pending.len() >= CHANNEL_QUEUE_CAPACITY {
+                if let Some(dropped) = queue.pending.pop_front() {
+                    tracing::warn!(
+                        "Outgoing queue for {} is full, dropping message {}",
+                        dropped.channel_login,
+                        dropped.message
+                    );
+                    self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            queue.pending.push_back(part);
+        }
+    }
+
+    /// Sets whether the bot is a moderator or VIP in the channel, which lifts the per-channel limit.
+    pub fn set_elevated(&mut self, channel_login: &str, elevated: bool) {
+        self.channels
+            .entry(channel_login.to_owned())
+            .or_default()
+            .elevated = elevated;
+    }
+
+    /// Returns the next message that can be sent right now.
+    /// Otherwise returns the time at which one will be available, or `None` if there are no pending messages.
+    pub fn next_ready(&mut self, now: Instant) -> Result<Privmsg, Option<Instant>> {
+        let global_available = self.global.available_at(now);
+        let normal_available = self.global_normal.available_at(now).max(global_available);
+
+        let (channel_login, available_at) = match self
+            .channels
+            .iter()
+            .filter(|(_, queue)| !queue.pending.is_empty())
+            .map(|(channel_login, queue)| {
+                let global_available = match queue.elevated {
+                    true => global_available,
+                    false => normal_available,
+                };
+                (channel_login, queue.available_at(now).max(global_available))
+            })
+            .min_by_key(|(_, available_at)| *available_at)
+        {
+            Some((channel_login, available_at)) => (channel_login.clone(), available_at),
+            None => return Err(None),
+        };
+
+        if available_at > now {
+            return Err(Some(available_at));
+        }
+
+        let queue = self.channels.get_mut(&channel_login).unwrap();
+        let mut pm = queue.pending.pop_front().unwrap();
+
+        if let Some((sent_at, last_message)) = &queue.last_sent {
+            if now.duration_since(*sent_at) < DUPLICATE_WINDOW && *last_message == pm.message {
+                tracing::info!("Detected same matching message, adding an empty character");
+
+                if pm.message.ends_with(MAGIC_CHAR) {
+                    pm.message.pop();
+                    pm.message.pop();
+                } else {
+                    pm.message.push(' ');
+                    pm.message.push(MAGIC_CHAR);
+                }
+            }
+        }
+
+        queue.last_sent = Some((now, pm.message.clone()));
+
+        self.global.record(now);
+        if !queue.elevated {
+            self.global_normal.record(now);
+        }
+        self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+
+        Ok(pm)
+    }
+}
+
+/// Schedules whisper replies, which have their own rate limits separate from chat.
+pub struct WhisperQueue {
+    pending: VecDeque<Whisper>,
+    per_second: SlidingWindow,
+    per_minute: SlidingWindow,
+    metrics: Arc<QueueMetrics>,
+}
+
+impl WhisperQueue {
+    pub fn new(metrics: Arc<QueueMetrics>) -> Self {
+        Self {
+            pending: VecDeque::new(),
+            per_second: SlidingWindow::new(WHISPER_SECOND_LIMIT, Duration::from_secs(1)),
+            per_minute: SlidingWindow::new(WHISPER_MINUTE_LIMIT, Duration::from_secs(60)),
+            metrics,
+        }
+    }
+
+    pub fn push(&mut self, whisper: Whisper) {
+        if self.pending.len() >= WHISPER_QUEUE_CAPACITY {
+            if let Some(dropped) = self.pending.pop_front() {
+                tracing::warn!(
+                    "Outgoing whisper queue is full, dropping whisper to {}",
+                    dropped.to_user_id
+                );
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.pending.push_back(whisper);
+    }
+
+    /// Same as [`OutboundQueue::next_ready`]
+    pub fn next_ready(&mut self, now: Instant) -> Result<Whisper, Option<Instant>> {
+        if self.pending.is_empty() {
+            return Err(None);
+        }
+
+        let available_at = self
+            .per_second
+            .available_at(now)
+            .max(self.per_minute.available_at(now));
+
+        if available_at > now {
+            return Err(Some(available_at));
+        }
+
+        self.per_second.record(now);
+        self.per_minute.record(now);
+        self.metrics.sent.fetch_add(1, Ordering::Relaxed);
+
+        Ok(self.pending.pop_front().unwrap())
+    }
+}
+
+/// Splits a message into parts that fit in the Twitch message length limit, preferring to split between words.
+fn split_message(mut pm: Privmsg) -> Vec<Privmsg> {
+    let mut parts = Vec::new();
+
+    while pm.message.len() > MSG_LENGTH_LIMIT {
+        let mut index = MSG_LENGTH_LIMIT - 1;
+
+        while !pm.message.is_char_boundary(index) {
+            index -= 1;
+        }
+
+        let mut rest = pm.message.split_off(index);
+
+        if pm.message.chars().last().map(|c| c.is_whitespace()) != Some(true) {
+            let mut words = pm.message.split_whitespace();
+            if let Some(last_word) = words.next_back() {
+                rest = format!("{}{}", last_word, rest);
+            }
+            pm.message = words.collect::<Vec<&str>>().join(" ");
+        }
+
+        parts.push(pm.clone());
+
+        pm.message = rest;
+        pm.reply_to_id = None;
+    }
+    parts.push(pm);
+
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        OutboundQueue, QueueMetrics, WhisperQueue, CHANNEL_QUEUE_CAPACITY, NORMAL_CHANNEL_INTERVAL,
+        WHISPER_SECOND_LIMIT,
+    };
+    use crate::platform::twitch::{Privmsg, Whisper};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn privmsg(channel_login: &str, message: &str) -> Privmsg {
+        Privmsg {
+            channel_login: channel_login.to_owned(),
+            message: message.to_owned(),
+            reply_to_id: None,
+        }
+    }
+
+    #[test]
+    fn busy_channel_does_not_block_others() {
+        let mut queue = OutboundQueue::new(false, Arc::default());
+        let now = Instant::now();
+
+        queue.push(privmsg("forsen", "1"));
+        queue.push(privmsg("forsen", "2"));
+        queue.push(privmsg("supinic", "3"));
+
+        let first = queue.next_ready(now).unwrap();
+        let second = queue.next_ready(now).unwrap();
+        assert_ne!(first.channel_login, second.channel_login);
+
+        // The remaining message has to wait for the per-channel interval
+        assert_eq!(
+            queue.next_ready(now).unwrap_err(),
+            Some(now + NORMAL_CHANNEL_INTERVAL)
+        );
+        assert_eq!(
+            queue
+                .next_ready(now + NORMAL_CHANNEL_INTERVAL)
+                .unwrap()
+                .channel_login,
+            "forsen"
+        );
+        assert_eq!(
+            queue.next_ready(now + Duration::from_secs(5)).unwrap_err(),
+            None
+        );
+    }
+
+    #[test]
+    fn elevated_channel_has_no_interval() {
+        let mut queue = OutboundQueue::new(false, Arc::default());
+        let now = Instant::now();
+
+        queue.set_elevated("forsen", true);
+        queue.push(privmsg("forsen", "1"));
+        queue.push(privmsg("forsen", "2"));
+
+        assert!(queue.next_ready(now).is_ok());
+        assert!(queue.next_ready(now).is_ok());
+    }
+
+    #[test]
+    fn overflow_drops_oldest() {
+        let metrics = Arc::new(QueueMetrics::default());
+        let mut queue = OutboundQueue::new(false, metrics.clone());
+
+        for i in 0..CHANNEL_QUEUE_CAPACITY + 2 {
+            queue.push(privmsg("forsen", &i.to_string()));
+        }
+        queue.push(privmsg("forsen", "5"));
+
+        assert_eq!(metrics.dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.deduplicated.load(Ordering::Relaxed), 1);
+        assert_eq!(queue.next_ready(Instant::now()).unwrap().message, "2");
+    }
+
+    #[test]
+    fn whispers_are_rate_limited() {
+        let mut queue = WhisperQueue::new(Arc::default());
+        let now = Instant::now();
+
+        for i in 0..=WHISPER_SECOND_LIMIT {
+            queue.push(Whisper {
+                to_user_id: i.to_string(),
+                message: "hi".to_owned(),
+            });
+        }
+
+        for _ in 0..WHISPER_SECOND_LIMIT {
+            assert!(queue.next_ready(now).is_ok());
+        }
+        assert_eq!(
+            queue.next_ready(now).unwrap_err(),
+            Some(now + Duration::from_secs(1))
+        );
+        assert!(queue.next_ready(now + Duration::from_secs(1)).is_ok());
+    }
+}
@@ -39,11 +39,13 @@ impl Foobot for FoobotService {
         let request = request.into_inner();
         tracing::info!("{:?}", request);
 
+        let channel_id = request.channel_id;
         let channel = self
             .command_handler
             .db
-            .get_channel_by_id(request.channel_id)
-            .expect("DB error")
+            .run(move |db| db.get_channel_by_id(channel_id))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
             .ok_or_else(|| Status::not_found("Specified channel not found"))?;
 
         let platform_handler = self.command_handler.platform_handler.read().await;